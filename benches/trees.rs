@@ -51,54 +51,54 @@ macro_rules! bench_trees {
     }};
 }
 
-fn bench_sink_box(b: &mut Bencher<'_>) -> () {
+fn bench_sink_box(b: &mut Bencher<'_>) {
     bench_trees!(b, io::sink(), BoxAllocator, 1)
 }
 
-fn bench_sink_arena(b: &mut Bencher<'_>) -> () {
+fn bench_sink_arena(b: &mut Bencher<'_>) {
     bench_trees!(b, io::sink(), Arena::new(), 1)
 }
 
-fn bench_vec_box(b: &mut Bencher<'_>) -> () {
+fn bench_vec_box(b: &mut Bencher<'_>) {
     bench_trees!(b, Vec::new(), BoxAllocator, 1)
 }
 
-fn bench_vec_arena(b: &mut Bencher<'_>) -> () {
+fn bench_vec_arena(b: &mut Bencher<'_>) {
     bench_trees!(b, Vec::new(), Arena::new(), 1)
 }
 
-fn bench_io_box(b: &mut Bencher<'_>) -> () {
+fn bench_io_box(b: &mut Bencher<'_>) {
     let out = tempfile::tempfile().unwrap();
     bench_trees!(b, io::BufWriter::new(out), BoxAllocator, 1)
 }
 
-fn bench_io_arena(b: &mut Bencher<'_>) -> () {
+fn bench_io_arena(b: &mut Bencher<'_>) {
     let out = tempfile::tempfile().unwrap();
     bench_trees!(b, io::BufWriter::new(out), Arena::new(), 1)
 }
 
-fn bench_large_sink_box(b: &mut Bencher<'_>) -> () {
+fn bench_large_sink_box(b: &mut Bencher<'_>) {
     bench_trees!(b, io::sink(), BoxAllocator, 50)
 }
 
-fn bench_large_sink_arena(b: &mut Bencher<'_>) -> () {
+fn bench_large_sink_arena(b: &mut Bencher<'_>) {
     bench_trees!(b, io::sink(), Arena::new(), 50)
 }
 
-fn bench_large_vec_box(b: &mut Bencher<'_>) -> () {
+fn bench_large_vec_box(b: &mut Bencher<'_>) {
     bench_trees!(b, Vec::new(), BoxAllocator, 50)
 }
 
-fn bench_large_vec_arena(b: &mut Bencher<'_>) -> () {
+fn bench_large_vec_arena(b: &mut Bencher<'_>) {
     bench_trees!(b, Vec::new(), Arena::new(), 50)
 }
 
-fn bench_large_io_box(b: &mut Bencher<'_>) -> () {
+fn bench_large_io_box(b: &mut Bencher<'_>) {
     let out = tempfile::tempfile().unwrap();
     bench_trees!(b, io::BufWriter::new(out), BoxAllocator, 50)
 }
 
-fn bench_large_io_arena(b: &mut Bencher<'_>) -> () {
+fn bench_large_io_arena(b: &mut Bencher<'_>) {
     let out = tempfile::tempfile().unwrap();
     bench_trees!(b, io::BufWriter::new(out), Arena::new(), 50)
 }
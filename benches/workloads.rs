@@ -0,0 +1,56 @@
+use std::io;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use pretty::{bench_support, Arena, BoxAllocator, DocAllocator};
+
+fn bench_workloads(c: &mut Criterion) {
+    let mut group = c.benchmark_group("workloads");
+
+    for &depth in &[100, 10_000] {
+        group.bench_with_input(
+            BenchmarkId::new("deep_nesting", depth),
+            &depth,
+            |b, &depth| {
+                let arena = Arena::<()>::new();
+                let doc = bench_support::deep_nesting(&arena, depth);
+                b.iter(|| doc.1.render(80, &mut io::sink()).unwrap());
+            },
+        );
+    }
+
+    for &len in &[100, 10_000] {
+        group.bench_with_input(BenchmarkId::new("wide_flat", len), &len, |b, &len| {
+            let arena = Arena::<()>::new();
+            let doc = bench_support::wide_flat(&arena, len);
+            b.iter(|| doc.1.render(usize::MAX, &mut io::sink()).unwrap());
+        });
+    }
+
+    group.bench_function("annotated", |b| {
+        let arena = Arena::new();
+        let doc = bench_support::annotated(&arena, 10_000, "tag");
+        b.iter(|| doc.1.render(80, &mut io::sink()).unwrap());
+    });
+
+    for &size in &[1_000, 100_000] {
+        group.bench_with_input(BenchmarkId::new("random", size), &size, |b, &size| {
+            let arena = Arena::<()>::new();
+            let doc = bench_support::random(&arena, 1, size);
+            b.iter(|| doc.1.render(80, &mut io::sink()).unwrap());
+        });
+    }
+
+    group.bench_function("build_random_box", |b| {
+        b.iter(|| bench_support::random::<_, ()>(&BoxAllocator, 1, 1_000).into_doc());
+    });
+    group.bench_function("build_random_arena", |b| {
+        b.iter(|| {
+            let arena = Arena::<()>::new();
+            arena.nil().append(bench_support::random(&arena, 1, 1_000));
+        });
+    });
+}
+
+criterion_group!(benches, bench_workloads);
+criterion_main!(benches);
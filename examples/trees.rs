@@ -6,6 +6,7 @@ use std::str;
 pub struct Forest<'a>(&'a [Tree<'a>]);
 
 impl<'a> Forest<'a> {
+    #[allow(clippy::self_named_constructors)]
     fn forest(forest: &'a [Tree<'a>]) -> Forest<'a> {
         Forest(forest)
     }
@@ -20,7 +21,7 @@ impl<'a> Forest<'a> {
         D::Doc: Clone,
         A: Clone,
     {
-        if (self.0).len() == 0 {
+        if (self.0).is_empty() {
             allocator.nil()
         } else {
             allocator
@@ -39,10 +40,7 @@ impl<'a> Forest<'a> {
     {
         let forest = self.0;
         let separator = allocator.text(",").append(allocator.hardline());
-        allocator.intersperse(
-            forest.into_iter().map(|tree| tree.pretty(allocator)),
-            separator,
-        )
+        allocator.intersperse(forest.iter().map(|tree| tree.pretty(allocator)), separator)
     }
 }
 
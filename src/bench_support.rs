@@ -0,0 +1,129 @@
+//! Generators of representative documents for benchmarks.
+//!
+//! The `benches/workloads.rs` suite of this crate renders these documents, and formatters built
+//! on it can use them as a baseline for their own benchmarks. Every generator is deterministic,
+//! so results can be compared between runs.
+//!
+//! # Performance contracts
+//!
+//! Rendering is expected to stay within these bounds, changes which break them are regressions:
+//!
+//! - Without `union`, `column` or `nesting`, a document is rendered in time linear in its size
+//!   plus the time needed to check whether each group fits. Checking a group stops at the end of
+//!   the line, so it takes at most time linear in the width of the page.
+//! - Nesting does not use recursion in the renderer, so [`deep_nesting`](fn.deep_nesting.html)
+//!   can be rendered at any depth without overflowing the stack.
+//! - Annotations add a constant amount of work for each annotated document.
+//!
+//! ```
+//! use pretty::{bench_support, Arena};
+//!
+//! let arena = Arena::<()>::new();
+//! let doc = bench_support::wide_flat(&arena, 3);
+//! assert_eq!(doc.1.pretty(80).to_string(), "[item0, item1, item2]");
+//! ```
+
+use crate::{DocAllocator, DocBuilder};
+
+/// `depth` groups nested inside of each other, each containing a line break.
+pub fn deep_nesting<'a, D, A>(allocator: &'a D, depth: usize) -> DocBuilder<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+{
+    (0..depth).fold(allocator.text("x"), |doc, i| {
+        allocator
+            .text(if i % 2 == 0 { "(" } else { "[" })
+            .append(allocator.line_().append(doc).nest(2))
+            .append(allocator.line_())
+            .append(if i % 2 == 0 { ")" } else { "]" })
+            .group()
+    })
+}
+
+/// A single group of `len` comma separated items, which is flat on a wide enough page.
+pub fn wide_flat<'a, D, A>(allocator: &'a D, len: usize) -> DocBuilder<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+{
+    let items = (0..len).fold(allocator.nil(), |doc, i| {
+        let doc = if i == 0 {
+            doc
+        } else {
+            doc.append(",").append(allocator.line())
+        };
+        doc.append(allocator.as_string(format_args!("item{}", i)))
+    });
+    allocator
+        .text("[")
+        .append(allocator.line_().append(items).nest(2))
+        .append(allocator.line_())
+        .append("]")
+        .group()
+}
+
+/// `len` lines of words where every word is annotated with `annotation`.
+pub fn annotated<'a, D, A>(allocator: &'a D, len: usize, annotation: A) -> DocBuilder<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+    A: Clone,
+{
+    (0..len).fold(allocator.nil(), |doc, i| {
+        let doc = if i == 0 {
+            doc
+        } else {
+            doc.append(allocator.hardline())
+        };
+        let word = |s: &'static str| allocator.text(s).annotate(annotation.clone());
+        doc.append(
+            word("let")
+                .append(allocator.space())
+                .append(word("value"))
+                .append(" = ")
+                .append(word("42"))
+                .annotate(annotation.clone()),
+        )
+    })
+}
+
+/// A pseudo-random document of about `size` nodes mixing text, line breaks, groups and nesting.
+/// The same `seed` always produces the same document.
+pub fn random<'a, D, A>(allocator: &'a D, seed: u64, size: usize) -> DocBuilder<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+{
+    const WORDS: [&str; 6] = ["a", "foo", "bar", "lorem", "ipsum", "consectetur"];
+
+    // xorshift64, the state must not be zero
+    let mut state = seed ^ 0x9e37_79b9_7f4a_7c15;
+    let mut next = move |n: u64| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state % n) as usize
+    };
+
+    // Build the document bottom up with an explicit stack so that any size can be generated
+    let mut stack = vec![];
+    for _ in 0..size {
+        let doc = match (next(6), stack.len()) {
+            (0, n) if n >= 2 => {
+                let r = stack.pop().unwrap();
+                let l: DocBuilder<'a, D, A> = stack.pop().unwrap();
+                l.append(allocator.line()).append(r)
+            }
+            (1, n) if n >= 1 => stack.pop().unwrap().group(),
+            (2, n) if n >= 1 => stack.pop().unwrap().nest(2),
+            (3, n) if n >= 2 => {
+                let r = stack.pop().unwrap();
+                let l: DocBuilder<'a, D, A> = stack.pop().unwrap();
+                l.append(allocator.softline()).append(r)
+            }
+            _ => allocator.text(WORDS[next(WORDS.len() as u64)]),
+        };
+        stack.push(doc);
+    }
+    let mut docs = stack.into_iter();
+    let first = docs.next().unwrap_or_else(|| allocator.nil());
+    docs.fold(first, |doc, next| doc.append(allocator.line()).append(next))
+        .group()
+}
@@ -0,0 +1,172 @@
+//! Construction of documents from a flat stream of tokens.
+//!
+//! Pretty printers driven directly by a lexer or parser often do not have an AST at hand to
+//! recursively convert into a `Doc`. [`TokenSink`](struct.TokenSink.html) accepts the tokens of
+//! an Oppen style pretty printer (`open`/`close` of groups interleaved with text and breaks) and
+//! assembles the corresponding document, checking that every group is closed exactly once.
+//!
+//! ```
+//! use pretty::builder::{Token, TokenSink};
+//! use pretty::BoxAllocator;
+//!
+//! let mut sink = TokenSink::<_, ()>::new(&BoxAllocator);
+//! sink.extend(vec![
+//!     Token::Text("f(".into()),
+//!     Token::Open(2),
+//!     Token::ZeroBreak,
+//!     Token::Text("x,".into()),
+//!     Token::Break,
+//!     Token::Text("y".into()),
+//!     Token::Close,
+//!     Token::Text(")".into()),
+//! ])
+//! .unwrap();
+//! let doc = sink.finish().unwrap();
+//! assert_eq!(doc.1.pretty(80).to_string(), "f(x, y)");
+//! assert_eq!(doc.1.pretty(4).to_string(), "f(\n  x,\n  y)");
+//! ```
+
+use std::{borrow::Cow, error, fmt};
+
+use crate::{DocAllocator, DocBuilder};
+
+/// A single token of a document.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Token<'a> {
+    /// Start a group whose contents are indented by the given amount when broken.
+    Open(isize),
+    /// End the most recently opened group.
+    Close,
+    /// Text, which must not contain line breaks.
+    Text(Cow<'a, str>),
+    /// A space if the enclosing group fits on a line, otherwise a newline.
+    Break,
+    /// Nothing if the enclosing group fits on a line, otherwise a newline.
+    ZeroBreak,
+    /// A newline which is always taken.
+    HardBreak,
+}
+
+/// Errors caused by an unbalanced token stream.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TokenError {
+    /// A `Close` token was encountered without a matching `Open`.
+    UnmatchedClose,
+    /// The stream ended with the given number of groups still open.
+    Unclosed(usize),
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenError::UnmatchedClose => write!(f, "close token without a matching open"),
+            TokenError::Unclosed(n) => write!(f, "{} group(s) were never closed", n),
+        }
+    }
+}
+
+impl error::Error for TokenError {}
+
+/// Builds a document out of a stream of [`Token`](enum.Token.html)s.
+pub struct TokenSink<'a, D, A = ()>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+{
+    allocator: &'a D,
+    current: DocBuilder<'a, D, A>,
+    open: Vec<(isize, DocBuilder<'a, D, A>)>,
+}
+
+impl<'a, D, A> TokenSink<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+{
+    pub fn new(allocator: &'a D) -> Self {
+        TokenSink {
+            allocator,
+            current: allocator.nil(),
+            open: Vec::new(),
+        }
+    }
+
+    /// The number of groups which are currently open.
+    pub fn depth(&self) -> usize {
+        self.open.len()
+    }
+
+    /// Start a new group, indented by `indent` when broken.
+    pub fn open(&mut self, indent: isize) {
+        let parent = std::mem::replace(&mut self.current, self.allocator.nil());
+        self.open.push((indent, parent));
+    }
+
+    /// Close the most recently opened group.
+    pub fn close(&mut self) -> Result<(), TokenError> {
+        let (indent, parent) = self.open.pop().ok_or(TokenError::UnmatchedClose)?;
+        let group = std::mem::replace(&mut self.current, parent);
+        self.append(group.nest(indent).group());
+        Ok(())
+    }
+
+    /// Append `text` to the current group.
+    pub fn text<U: Into<Cow<'a, str>>>(&mut self, text: U) {
+        let text = self.allocator.text(text);
+        self.append(text);
+    }
+
+    /// Append a `line` to the current group.
+    pub fn line(&mut self) {
+        let line = self.allocator.line();
+        self.append(line);
+    }
+
+    /// Append a `line_` to the current group.
+    pub fn line_(&mut self) {
+        let line = self.allocator.line_();
+        self.append(line);
+    }
+
+    /// Append a `hardline` to the current group.
+    pub fn hardline(&mut self) {
+        let line = self.allocator.hardline();
+        self.append(line);
+    }
+
+    /// Feed a single token into the sink.
+    pub fn push(&mut self, token: Token<'a>) -> Result<(), TokenError> {
+        match token {
+            Token::Open(indent) => self.open(indent),
+            Token::Close => return self.close(),
+            Token::Text(text) => self.text(text),
+            Token::Break => self.line(),
+            Token::ZeroBreak => self.line_(),
+            Token::HardBreak => self.hardline(),
+        }
+        Ok(())
+    }
+
+    /// Feed all `tokens` into the sink, stopping at the first unbalanced `Close`.
+    pub fn extend<I>(&mut self, tokens: I) -> Result<(), TokenError>
+    where
+        I: IntoIterator<Item = Token<'a>>,
+    {
+        for token in tokens {
+            self.push(token)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the built document, failing if any group is still open.
+    pub fn finish(self) -> Result<DocBuilder<'a, D, A>, TokenError> {
+        if self.open.is_empty() {
+            Ok(self.current)
+        } else {
+            Err(TokenError::Unclosed(self.open.len()))
+        }
+    }
+
+    fn append(&mut self, doc: DocBuilder<'a, D, A>) {
+        let current = std::mem::replace(&mut self.current, self.allocator.nil());
+        self.current = current.append(doc);
+    }
+}
@@ -0,0 +1,63 @@
+use bumpalo::Bump;
+
+use crate::{Doc, DocAllocator, DocPtr, RefDoc};
+
+/// Allows a `bumpalo::Bump` to be used as a document allocator, producing the same `RefDoc`
+/// documents as `Arena`.
+///
+/// Owned text is copied into the bump arena when a document is allocated. Note that `Bump` never
+/// runs destructors so any annotations or captured values in `column` and `nesting` functions
+/// which need to be dropped will be leaked.
+///
+/// ```
+/// use pretty::DocAllocator;
+///
+/// let bump = bumpalo::Bump::new();
+/// let doc: pretty::RefDoc<()> = bump
+///     .text(String::from("hello"))
+///     .append(bump.line())
+///     .append("world")
+///     .group()
+///     .into_doc();
+/// assert_eq!(doc.pretty(80).to_string(), "hello world");
+/// ```
+impl<'a, A> DocAllocator<'a, A> for Bump
+where
+    A: 'a,
+{
+    type Doc = RefDoc<'a, A>;
+
+    #[inline]
+    fn alloc(&'a self, doc: Doc<'a, Self::Doc, A>) -> Self::Doc {
+        RefDoc(match doc {
+            // Return 'static references for common variants to avoid some allocations
+            Doc::Nil => &Doc::Nil,
+            Doc::Line => &Doc::Line,
+            // line()
+            Doc::FlatAlt(RefDoc(Doc::Line), RefDoc(Doc::BorrowedText(" "))) => {
+                &Doc::FlatAlt(RefDoc(&Doc::Line), RefDoc(&Doc::BorrowedText(" ")))
+            }
+            // line_()
+            Doc::FlatAlt(RefDoc(Doc::Line), RefDoc(Doc::Nil)) => {
+                &Doc::FlatAlt(RefDoc(&Doc::Line), RefDoc(&Doc::Nil))
+            }
+            // The bump arena does not drop its contents so owned text must be moved into it
+            Doc::OwnedText(text) => Bump::alloc(self, Doc::BorrowedText(self.alloc_str(&text))),
+            _ => Bump::alloc(self, doc),
+        })
+    }
+
+    fn alloc_column_fn(
+        &'a self,
+        f: impl Fn(usize) -> Self::Doc + 'a,
+    ) -> <Self::Doc as DocPtr<'a, A>>::ColumnFn {
+        Bump::alloc(self, f)
+    }
+
+    fn alloc_width_fn(
+        &'a self,
+        f: impl Fn(isize) -> Self::Doc + 'a,
+    ) -> <Self::Doc as DocPtr<'a, A>>::WidthFn {
+        Bump::alloc(self, f)
+    }
+}
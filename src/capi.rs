@@ -0,0 +1,185 @@
+//! A C ABI for building and rendering documents.
+//!
+//! Documents are opaque `PrettyDoc` handles. The combinators consume the handles they are given
+//! and return a new handle, which lets them reuse the documents instead of copying them, so a
+//! handle must not be used after it has been passed to a combinator. Every other handle must be
+//! released with `pretty_doc_free` and every rendered string with `pretty_string_free`. Text is
+//! passed as UTF-8 buffers with an explicit length.
+//!
+//! A shared library can be built with `cargo rustc --release --features capi --crate-type cdylib`.
+//!
+//! ```c
+//! PrettyDoc *hello = pretty_doc_text("hello", 5);
+//! PrettyDoc *line = pretty_doc_line();
+//! PrettyDoc *world = pretty_doc_text("world", 5);
+//! PrettyDoc *doc = pretty_doc_append(pretty_doc_append(hello, line), world);
+//! PrettyDoc *grouped = pretty_doc_group(doc);
+//!
+//! size_t len;
+//! char *out = pretty_doc_render(grouped, 80, &len);
+//! printf("%s\n", out); // hello world
+//! pretty_string_free(out, len);
+//! pretty_doc_free(grouped);
+//! ```
+
+use std::{os::raw::c_char, ptr, slice, str};
+
+use crate::RcDoc;
+
+/// An opaque handle to a document.
+pub struct PrettyDoc(RcDoc<'static, ()>);
+
+fn into_handle(doc: RcDoc<'static, ()>) -> *mut PrettyDoc {
+    Box::into_raw(Box::new(PrettyDoc(doc)))
+}
+
+unsafe fn from_handle(doc: *mut PrettyDoc) -> RcDoc<'static, ()> {
+    Box::from_raw(doc).0
+}
+
+/// Creates a document containing `len` bytes of UTF-8 text starting at `text`, with each line
+/// break laid out as `pretty_doc_hardline`. Returns null if the text is not valid UTF-8.
+///
+/// # Safety
+///
+/// `text` must point to `len` readable bytes, it may be null if `len` is 0.
+#[no_mangle]
+pub unsafe extern "C" fn pretty_doc_text(text: *const c_char, len: usize) -> *mut PrettyDoc {
+    let bytes = if len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(text as *const u8, len)
+    };
+    match str::from_utf8(bytes) {
+        Ok(text) => {
+            let mut doc = RcDoc::nil();
+            for (i, line) in text.split('\n').enumerate() {
+                if i != 0 {
+                    doc = doc.append(RcDoc::hardline());
+                }
+                doc = doc.append(RcDoc::text(line.to_string()));
+            }
+            into_handle(doc)
+        }
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Creates the empty document.
+#[no_mangle]
+pub extern "C" fn pretty_doc_nil() -> *mut PrettyDoc {
+    into_handle(RcDoc::nil())
+}
+
+/// Creates a single space.
+#[no_mangle]
+pub extern "C" fn pretty_doc_space() -> *mut PrettyDoc {
+    into_handle(RcDoc::space())
+}
+
+/// Creates a line break which is never flattened.
+#[no_mangle]
+pub extern "C" fn pretty_doc_hardline() -> *mut PrettyDoc {
+    into_handle(RcDoc::hardline())
+}
+
+/// Creates a line break which is a space when the enclosing group is flattened.
+#[no_mangle]
+pub extern "C" fn pretty_doc_line() -> *mut PrettyDoc {
+    into_handle(RcDoc::line())
+}
+
+/// Creates a line break which is nothing when the enclosing group is flattened.
+#[no_mangle]
+pub extern "C" fn pretty_doc_line_() -> *mut PrettyDoc {
+    into_handle(RcDoc::line_())
+}
+
+/// Creates a space if the rest of the line fits, otherwise a line break.
+#[no_mangle]
+pub extern "C" fn pretty_doc_softline() -> *mut PrettyDoc {
+    into_handle(RcDoc::line().group())
+}
+
+/// Creates a document containing `left` followed by `right`. Both handles are consumed.
+///
+/// # Safety
+///
+/// `left` and `right` must be distinct valid handles which are not used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn pretty_doc_append(
+    left: *mut PrettyDoc,
+    right: *mut PrettyDoc,
+) -> *mut PrettyDoc {
+    into_handle(from_handle(left).append(from_handle(right)))
+}
+
+/// Creates a group of `doc`, which is laid out on a single line if it fits. The handle is
+/// consumed.
+///
+/// # Safety
+///
+/// `doc` must be a valid handle which is not used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn pretty_doc_group(doc: *mut PrettyDoc) -> *mut PrettyDoc {
+    into_handle(from_handle(doc).group())
+}
+
+/// Increases the indentation of the line breaks in `doc` by `offset`. The handle is consumed.
+///
+/// # Safety
+///
+/// `doc` must be a valid handle which is not used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn pretty_doc_nest(doc: *mut PrettyDoc, offset: isize) -> *mut PrettyDoc {
+    into_handle(from_handle(doc).nest(offset))
+}
+
+/// Releases a handle which has not been passed to a combinator.
+///
+/// # Safety
+///
+/// `doc` must be null or a valid handle which is not used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn pretty_doc_free(doc: *mut PrettyDoc) {
+    if !doc.is_null() {
+        drop(Box::from_raw(doc));
+    }
+}
+
+/// Renders `doc` at `width`. Returns a UTF-8 buffer which is terminated by a NUL byte, its length
+/// without the terminator is stored in `len` if it is not null. The buffer must be released with
+/// `pretty_string_free`.
+///
+/// # Safety
+///
+/// `doc` must be a valid handle and `len` null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn pretty_doc_render(
+    doc: *const PrettyDoc,
+    width: usize,
+    len: *mut usize,
+) -> *mut c_char {
+    let mut out = (*doc).0.pretty(width).to_string().into_bytes();
+    if !len.is_null() {
+        *len = out.len();
+    }
+    out.push(0);
+    Box::into_raw(out.into_boxed_slice()) as *mut c_char
+}
+
+/// Releases a buffer returned by `pretty_doc_render`.
+///
+/// # Safety
+///
+/// `s` must be null or a buffer returned by `pretty_doc_render` together with its length, which
+/// is not used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn pretty_string_free(s: *mut c_char, len: usize) {
+    if !s.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+            s as *mut u8,
+            len + 1,
+        )));
+    }
+}
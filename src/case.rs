@@ -0,0 +1,81 @@
+/// Splits an identifier in any common style into its words. Words are separated by `_`, `-` and
+/// whitespace or start at an uppercase letter following a lowercase letter or digit. A run of
+/// uppercase letters is an acronym whose last letter starts the next word if it is followed by a
+/// lowercase letter, so `HTTPServer` is split into `HTTP` and `Server`.
+fn words(ident: &str) -> Vec<&str> {
+    let mut words = vec![];
+    let mut start = None;
+    let chars: Vec<(usize, char)> = ident.char_indices().collect();
+    for (i, &(offset, c)) in chars.iter().enumerate() {
+        if c == '_' || c == '-' || c.is_whitespace() {
+            if let Some(start) = start.take() {
+                words.push(&ident[start..offset]);
+            }
+            continue;
+        }
+        if let Some(word_start) = start {
+            let prev = chars[i - 1].1;
+            let next_lower = chars.get(i + 1).is_some_and(|&(_, c)| c.is_lowercase());
+            let boundary = c.is_uppercase()
+                && (prev.is_lowercase() || prev.is_numeric() || prev.is_uppercase() && next_lower);
+            if boundary {
+                words.push(&ident[word_start..offset]);
+                start = Some(offset);
+            }
+        } else {
+            start = Some(offset);
+        }
+    }
+    if let Some(start) = start {
+        words.push(&ident[start..]);
+    }
+    words
+}
+
+fn capitalize(word: &str, out: &mut String) {
+    let mut chars = word.chars();
+    if let Some(first) = chars.next() {
+        out.extend(first.to_uppercase());
+        out.push_str(&chars.as_str().to_lowercase());
+    }
+}
+
+/// `snake_case`
+pub(crate) fn snake(ident: &str) -> String {
+    words(ident)
+        .iter()
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+/// `SCREAMING_SNAKE_CASE`
+pub(crate) fn screaming_snake(ident: &str) -> String {
+    words(ident)
+        .iter()
+        .map(|word| word.to_uppercase())
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+/// `camelCase`
+pub(crate) fn camel(ident: &str) -> String {
+    let mut out = String::new();
+    for (i, word) in words(ident).iter().enumerate() {
+        if i == 0 {
+            out.push_str(&word.to_lowercase());
+        } else {
+            capitalize(word, &mut out);
+        }
+    }
+    out
+}
+
+/// `PascalCase`
+pub(crate) fn pascal(ident: &str) -> String {
+    let mut out = String::new();
+    for word in words(ident) {
+        capitalize(word, &mut out);
+    }
+    out
+}
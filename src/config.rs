@@ -0,0 +1,344 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::display::DEFAULT_WIDTH;
+
+thread_local! {
+    static CONFIG: Cell<RenderConfig> = const { Cell::new(RenderConfig::new(DEFAULT_WIDTH)) };
+    static VARS: RefCell<HashMap<String, String>> = RefCell::new(HashMap::new());
+}
+
+/// Options used when rendering. The configuration of the current thread is used when rendering
+/// without an explicit width, such as by the `Display` implementations of documents and
+/// [`DisplayAdapter`](display/struct.DisplayAdapter.html). Renders which only take a width, such
+/// as `pretty` and `render`, use its other options.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct RenderConfig {
+    /// The preferred width of the page.
+    pub width: usize,
+    /// The hard maximum width, defaults to `width`. A group is only flattened if its content
+    /// fits within `width`, but the content following it on the same line may extend up to
+    /// `max_width`. This keeps a group from breaking just because a trailing `;` or `)` ends up
+    /// slightly past the preferred width.
+    pub max_width: Option<usize>,
+    /// Whether a group is only flattened if the rest of the line fits as well, up to the next
+    /// line break which is certain to be taken. By default the rest of the line is only measured
+    /// up to the next line break which may be taken, so a group can be flattened even though the
+    /// content after it ends up overflowing the width once the groups following it are
+    /// flattened as well.
+    pub strict_fitting: bool,
+    /// The maximum number of consecutive blank lines, longer runs are collapsed. A line is blank
+    /// if no text is written on it, the indentation of the last line break of a run is kept.
+    pub max_blank_lines: Option<usize>,
+    /// How indentation is written.
+    pub indent: Indent,
+    pub line_ending: LineEnding,
+}
+
+/// How indentation is written, see [`RenderConfig::indent`](struct.RenderConfig.html#structfield.indent).
+///
+/// The layout always measures indentation in columns. The width of an indentation level is not
+/// used by the renderer, it tells a formatter how far to `nest` each level.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Indent {
+    /// Indentation is written as spaces, each level is the given number of columns.
+    Spaces(usize),
+    /// Indentation is written as tabs which are the given number of columns wide, followed by
+    /// spaces for the columns which do not make up a whole tab.
+    Tabs(usize),
+}
+
+impl Indent {
+    /// The number of columns of one level of indentation.
+    pub const fn width(self) -> usize {
+        match self {
+            Indent::Spaces(width) | Indent::Tabs(width) => width,
+        }
+    }
+}
+
+/// The characters written for a line break.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LineEnding {
+    /// `\n`
+    Lf,
+    /// `\r\n`
+    CrLf,
+}
+
+impl LineEnding {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+}
+
+impl RenderConfig {
+    pub const fn new(width: usize) -> Self {
+        RenderConfig {
+            width,
+            max_width: None,
+            strict_fitting: false,
+            max_blank_lines: None,
+            indent: Indent::Spaces(4),
+            line_ending: LineEnding::Lf,
+        }
+    }
+
+    pub const fn with_max_width(self, max_width: usize) -> Self {
+        RenderConfig {
+            max_width: Some(max_width),
+            ..self
+        }
+    }
+
+    pub const fn with_strict_fitting(self, strict_fitting: bool) -> Self {
+        RenderConfig {
+            strict_fitting,
+            ..self
+        }
+    }
+
+    pub const fn with_max_blank_lines(self, max_blank_lines: usize) -> Self {
+        RenderConfig {
+            max_blank_lines: Some(max_blank_lines),
+            ..self
+        }
+    }
+
+    pub const fn with_indent(self, indent: Indent) -> Self {
+        RenderConfig { indent, ..self }
+    }
+
+    pub const fn with_line_ending(self, line_ending: LineEnding) -> Self {
+        RenderConfig {
+            line_ending,
+            ..self
+        }
+    }
+
+    /// Sets the indentation and line ending to the ones mostly used by `source`, so that a
+    /// formatter rewriting an existing file keeps its conventions. Settings which can not be
+    /// detected, such as the indentation of a file without indented lines or the width of a tab,
+    /// are kept.
+    ///
+    /// ```
+    /// use pretty::{Indent, LineEnding, RenderConfig};
+    ///
+    /// let source = "fn main() {\r\n  if x {\r\n    y();\r\n  }\r\n}\r\n";
+    /// let config = RenderConfig::new(80).with_detected(source);
+    /// assert_eq!(config.indent, Indent::Spaces(2));
+    /// assert_eq!(config.line_ending, LineEnding::CrLf);
+    ///
+    /// let config = RenderConfig::new(80).with_detected("a:\n\tb\n\tc\n");
+    /// assert_eq!(config.indent, Indent::Tabs(4));
+    /// assert_eq!(config.line_ending, LineEnding::Lf);
+    /// ```
+    pub fn with_detected(mut self, source: &str) -> Self {
+        let crlf = source.matches("\r\n").count();
+        let lf = source.matches('\n').count() - crlf;
+        if crlf != lf {
+            self.line_ending = if crlf > lf {
+                LineEnding::CrLf
+            } else {
+                LineEnding::Lf
+            };
+        }
+
+        let (mut tabs, mut spaces) = (0, 0);
+        // How often each increase of the indentation between consecutive lines occurs
+        let mut steps = [0usize; 17];
+        let mut prev = 0;
+        for line in source.lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let indent = line.len() - line.trim_start_matches(' ').len();
+            if line.starts_with('\t') {
+                tabs += 1;
+            } else if indent > 0 {
+                spaces += 1;
+                if indent > prev && indent - prev < steps.len() {
+                    steps[indent - prev] += 1;
+                }
+            }
+            prev = indent;
+        }
+        if tabs > spaces {
+            self.indent = Indent::Tabs(self.indent.width());
+        } else if spaces > 0 {
+            // The smallest of the most frequent steps
+            let (width, _) =
+                steps
+                    .iter()
+                    .enumerate()
+                    .skip(1)
+                    .fold(
+                        (0, 0),
+                        |best, (width, &count)| {
+                            if count > best.1 {
+                                (width, count)
+                            } else {
+                                best
+                            }
+                        },
+                    );
+            if width > 0 {
+                self.indent = Indent::Spaces(width);
+            }
+        }
+        self
+    }
+
+    /// The hard maximum width, which is never less than `width`.
+    pub fn hard_width(&self) -> usize {
+        self.max_width.map_or(self.width, |max| max.max(self.width))
+    }
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        RenderConfig::new(DEFAULT_WIDTH)
+    }
+}
+
+/// The configuration set by the innermost `with_config` call on this thread, or the default
+/// configuration if there is none.
+pub fn current_config() -> RenderConfig {
+    CONFIG.with(|config| config.get())
+}
+
+/// The configuration of this thread with its width replaced by `width`.
+pub(crate) fn current_config_with_width(width: usize) -> RenderConfig {
+    RenderConfig {
+        width,
+        ..current_config()
+    }
+}
+
+/// Calls `f` with `config` as the default render configuration of this thread. The previous
+/// configuration is restored afterwards, even if `f` panics.
+///
+/// ```
+/// use pretty::{with_config, BoxDoc, RenderConfig};
+///
+/// let doc = BoxDoc::<()>::text("hello")
+///     .append(BoxDoc::line())
+///     .append("world")
+///     .group();
+/// assert_eq!(doc.to_string(), "hello world");
+/// assert_eq!(
+///     with_config(RenderConfig::new(5), || doc.to_string()),
+///     "hello\nworld",
+/// );
+/// ```
+pub fn with_config<R>(config: RenderConfig, f: impl FnOnce() -> R) -> R {
+    struct Restore(RenderConfig);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            CONFIG.with(|config| config.set(self.0));
+        }
+    }
+
+    let _restore = Restore(CONFIG.with(|c| c.replace(config)));
+    f()
+}
+
+/// Calls `f` with `vars` as the values of the [`placeholder`](trait.DocAllocator.html#method.placeholder)
+/// documents rendered on this thread. The previous values are restored afterwards, even if `f`
+/// panics.
+///
+/// ```
+/// use pretty::{with_vars, RcDoc};
+///
+/// let doc = RcDoc::<()>::text("[").append(RcDoc::placeholder("time")).append("]");
+/// assert_eq!(with_vars([("time", "12:00")], || doc.to_string()), "[12:00]");
+/// assert_eq!(doc.to_string(), "[]");
+/// ```
+pub fn with_vars<I, K, V, R>(vars: I, f: impl FnOnce() -> R) -> R
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: Into<String>,
+    V: Into<String>,
+{
+    struct Restore(HashMap<String, String>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            VARS.with(|vars| *vars.borrow_mut() = std::mem::take(&mut self.0));
+        }
+    }
+
+    let vars = vars
+        .into_iter()
+        .map(|(key, value)| (key.into(), value.into()))
+        .collect();
+    let _restore = Restore(VARS.with(|v| v.replace(vars)));
+    f()
+}
+
+/// The value of the variable `key` set by the innermost `with_vars` call on this thread.
+pub(crate) fn var(key: &str) -> Option<String> {
+    VARS.with(|vars| vars.borrow().get(key).cloned())
+}
+
+/// Whether the colored renderers write colors, see [`set_color_mode`](fn.set_color_mode.html).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ColorMode {
+    /// Follow the `NO_COLOR` and `CLICOLOR_FORCE` environment variables.
+    Auto,
+    Always,
+    Never,
+}
+
+static COLOR_MODE: AtomicU8 = AtomicU8::new(ColorMode::Auto as u8);
+
+/// Overrides the color mode for the whole program. Renderers created afterwards use the new
+/// mode.
+pub fn set_color_mode(mode: ColorMode) {
+    COLOR_MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+pub fn color_mode() -> ColorMode {
+    match COLOR_MODE.load(Ordering::Relaxed) {
+        x if x == ColorMode::Always as u8 => ColorMode::Always,
+        x if x == ColorMode::Never as u8 => ColorMode::Never,
+        _ => ColorMode::Auto,
+    }
+}
+
+/// Whether the colored renderers, such as `TermColored` and `AnsiStyled`, write colors.
+///
+/// An explicit mode set with `set_color_mode` takes precedence. Otherwise colors are enabled
+/// unless `NO_COLOR` is set to a non-empty value, and `CLICOLOR_FORCE` set to anything other
+/// than `0` enables them regardless of `NO_COLOR`.
+pub fn colors_enabled() -> bool {
+    resolve_colors(
+        color_mode(),
+        env::var_os("NO_COLOR"),
+        env::var_os("CLICOLOR_FORCE"),
+    )
+}
+
+pub(crate) fn resolve_colors(
+    mode: ColorMode,
+    no_color: Option<OsString>,
+    force: Option<OsString>,
+) -> bool {
+    match mode {
+        ColorMode::Always => true,
+        ColorMode::Never => false,
+        ColorMode::Auto => match (force, no_color) {
+            (Some(force), _) if !force.is_empty() && force != "0" => true,
+            (_, Some(no_color)) => no_color.is_empty(),
+            (_, None) => true,
+        },
+    }
+}
@@ -0,0 +1,313 @@
+//! Operations on whole documents which use an explicit stack instead of recursion, so that
+//! documents of any depth can be cloned, compared, dropped and transformed.
+
+use std::{convert::Infallible, rc::Rc};
+
+use crate::{Doc, DocAllocator, DocPtr};
+
+enum Frame<'d, 'a, T, A>
+where
+    T: DocPtr<'a, A>,
+{
+    Visit(&'d Doc<'a, T, A>),
+    Build(&'d Doc<'a, T, A>),
+}
+
+/// The converted children of a document, see `fold`.
+pub(crate) enum Children<U> {
+    One(U),
+    Two(U, U),
+}
+
+/// Converts `doc` bottom up using an explicit stack. `leaf` is called on every document before
+/// its children and its result is used as is if it returns `Some`, which it must for documents
+/// without children. Otherwise the children are converted first and passed to `build`.
+pub(crate) fn fold<'d, 'a, T, A, U, E>(
+    doc: &'d Doc<'a, T, A>,
+    mut leaf: impl FnMut(&'d Doc<'a, T, A>) -> Result<Option<U>, E>,
+    mut build: impl FnMut(&'d Doc<'a, T, A>, Children<U>) -> U,
+) -> Result<U, E>
+where
+    T: DocPtr<'a, A>,
+{
+    let mut stack = vec![Frame::Visit(doc)];
+    let mut done: Vec<U> = vec![];
+    while let Some(frame) = stack.pop() {
+        match frame {
+            Frame::Visit(doc) => {
+                if let Some(converted) = leaf(doc)? {
+                    done.push(converted);
+                    continue;
+                }
+                stack.push(Frame::Build(doc));
+                match *doc {
+                    Doc::Append(ref l, ref r)
+                    | Doc::FlatAlt(ref l, ref r)
+                    | Doc::Union(ref l, ref r) => {
+                        stack.push(Frame::Visit(&**r));
+                        stack.push(Frame::Visit(&**l));
+                    }
+                    Doc::Group(ref child)
+                    | Doc::BiasedGroup(_, ref child)
+                    | Doc::Nest(_, ref child)
+                    | Doc::MaxWidth(_, ref child)
+                    | Doc::Unbounded(ref child)
+                    | Doc::AlignmentGroup(ref child)
+                    | Doc::Delimiter(ref child)
+                    | Doc::Annotated(_, ref child) => stack.push(Frame::Visit(&**child)),
+                    _ => unreachable!("documents without children are converted by `leaf`"),
+                }
+            }
+            Frame::Build(doc) => {
+                // The children were converted in order so they are popped in reverse
+                let mut pop = || done.pop().expect("converted child");
+                let children = match *doc {
+                    Doc::Append(..) | Doc::FlatAlt(..) | Doc::Union(..) => {
+                        let r = pop();
+                        Children::Two(pop(), r)
+                    }
+                    _ => Children::One(pop()),
+                };
+                done.push(build(doc, children));
+            }
+        }
+    }
+    Ok(done.pop().expect("converted document"))
+}
+
+/// Unwraps the result of a conversion which cannot fail.
+pub(crate) fn infallible<U>(result: Result<U, Infallible>) -> U {
+    match result {
+        Ok(value) => value,
+        Err(never) => match never {},
+    }
+}
+
+/// Creates a document of the same kind as `doc`, which has children, out of the converted
+/// `children`.
+pub(crate) fn rebuild<'a, 'b, T, A, U, B>(
+    doc: &Doc<'a, T, A>,
+    children: Children<U>,
+    annotation: impl FnOnce(&A) -> B,
+) -> Doc<'b, U, B>
+where
+    T: DocPtr<'a, A>,
+    U: DocPtr<'b, B>,
+{
+    match (doc, children) {
+        (Doc::Append(..), Children::Two(l, r)) => Doc::Append(l, r),
+        (Doc::FlatAlt(..), Children::Two(l, r)) => Doc::FlatAlt(l, r),
+        (Doc::Union(..), Children::Two(l, r)) => Doc::Union(l, r),
+        (Doc::Group(_), Children::One(child)) => Doc::Group(child),
+        (&Doc::BiasedGroup(bias, _), Children::One(child)) => Doc::BiasedGroup(bias, child),
+        (&Doc::Nest(off, _), Children::One(child)) => Doc::Nest(off, child),
+        (&Doc::MaxWidth(width, _), Children::One(child)) => Doc::MaxWidth(width, child),
+        (Doc::Unbounded(_), Children::One(child)) => Doc::Unbounded(child),
+        (Doc::AlignmentGroup(_), Children::One(child)) => Doc::AlignmentGroup(child),
+        (Doc::Delimiter(_), Children::One(child)) => Doc::Delimiter(child),
+        (Doc::Annotated(ann, _), Children::One(child)) => Doc::Annotated(annotation(ann), child),
+        _ => unreachable!("only documents with children are rebuilt"),
+    }
+}
+
+/// Clones `doc` and everything it points to.
+pub(crate) fn clone<'a, T, A>(doc: &Doc<'a, T, A>) -> T
+where
+    T: DocPtr<'a, A> + From<Doc<'a, T, A>>,
+    A: Clone,
+{
+    let leaf = |doc: &Doc<'a, T, A>| {
+        Ok(Some(
+            match *doc {
+                Doc::Nil => Doc::Nil,
+                Doc::Line => Doc::Line,
+                Doc::AlignmentMark => Doc::AlignmentMark,
+                Doc::OwnedText(ref s) => Doc::OwnedText(s.clone()),
+                Doc::BorrowedText(s) => Doc::BorrowedText(s),
+                Doc::SmallText(s) => Doc::SmallText(s),
+                Doc::Column(ref f) => Doc::Column(f.clone()),
+                Doc::Nesting(ref f) => Doc::Nesting(f.clone()),
+                _ => return Ok(None),
+            }
+            .into(),
+        ))
+    };
+    infallible(fold(doc, leaf, |doc, children| {
+        rebuild(doc, children, A::clone).into()
+    }))
+}
+
+/// Moves the children of `doc` onto `stack`, leaving `Nil` in its place. Dropping the children
+/// from the stack one at a time afterwards keeps `Drop` from recursing.
+pub(crate) fn take_children<'a, T, A>(doc: &mut Doc<'a, T, A>, stack: &mut Vec<T>)
+where
+    T: DocPtr<'a, A>,
+{
+    match *doc {
+        Doc::Append(..)
+        | Doc::FlatAlt(..)
+        | Doc::Union(..)
+        | Doc::Group(_)
+        | Doc::BiasedGroup(..)
+        | Doc::Nest(..)
+        | Doc::MaxWidth(..)
+        | Doc::Unbounded(_)
+        | Doc::AlignmentGroup(_)
+        | Doc::Delimiter(_)
+        | Doc::Annotated(..) => (),
+        _ => return,
+    }
+    match std::mem::replace(doc, Doc::Nil) {
+        Doc::Append(l, r) | Doc::FlatAlt(l, r) | Doc::Union(l, r) => {
+            stack.push(r);
+            stack.push(l);
+        }
+        Doc::Group(child)
+        | Doc::BiasedGroup(_, child)
+        | Doc::Nest(_, child)
+        | Doc::MaxWidth(_, child)
+        | Doc::Unbounded(child)
+        | Doc::AlignmentGroup(child)
+        | Doc::Delimiter(child)
+        | Doc::Annotated(_, child) => stack.push(child),
+        _ => unreachable!(),
+    }
+}
+
+fn text<'d, 'a, T, A>(doc: &'d Doc<'a, T, A>) -> Option<&'d str>
+where
+    T: DocPtr<'a, A>,
+{
+    match *doc {
+        Doc::OwnedText(ref s) => Some(s),
+        Doc::BorrowedText(s) => Some(s),
+        Doc::SmallText(ref s) => Some(s),
+        _ => None,
+    }
+}
+
+/// Documents are equal if they have the same structure. Text is compared by content regardless of
+/// how it is stored, while `column` and `nesting` documents are only equal if they share the same
+/// function.
+impl<'a, T, A> PartialEq for Doc<'a, T, A>
+where
+    T: DocPtr<'a, A>,
+    A: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        let mut stack = vec![(self, other)];
+        while let Some(pair) = stack.pop() {
+            match pair {
+                (Doc::Nil, Doc::Nil)
+                | (Doc::Line, Doc::Line)
+                | (Doc::AlignmentMark, Doc::AlignmentMark) => (),
+                (Doc::Append(l1, r1), Doc::Append(l2, r2))
+                | (Doc::FlatAlt(l1, r1), Doc::FlatAlt(l2, r2))
+                | (Doc::Union(l1, r1), Doc::Union(l2, r2)) => {
+                    stack.push((&**r1, &**r2));
+                    stack.push((&**l1, &**l2));
+                }
+                (Doc::Group(l), Doc::Group(r))
+                | (Doc::Unbounded(l), Doc::Unbounded(r))
+                | (Doc::AlignmentGroup(l), Doc::AlignmentGroup(r))
+                | (Doc::Delimiter(l), Doc::Delimiter(r)) => stack.push((&**l, &**r)),
+                (Doc::BiasedGroup(a, l), Doc::BiasedGroup(b, r))
+                | (Doc::Nest(a, l), Doc::Nest(b, r))
+                    if a == b =>
+                {
+                    stack.push((&**l, &**r))
+                }
+                (Doc::MaxWidth(a, l), Doc::MaxWidth(b, r)) if a == b => stack.push((&**l, &**r)),
+                (Doc::Annotated(a, l), Doc::Annotated(b, r)) if a == b => stack.push((&**l, &**r)),
+                (Doc::Column(f), Doc::Column(g)) | (Doc::Nesting(f), Doc::Nesting(g)) => {
+                    let f = &**f as *const _ as *const u8;
+                    let g = &**g as *const _ as *const u8;
+                    if f != g {
+                        return false;
+                    }
+                }
+                (l, r) => match (text(l), text(r)) {
+                    (Some(l), Some(r)) if l == r => (),
+                    _ => return false,
+                },
+            }
+        }
+        true
+    }
+}
+
+type TextFn<'b> = Rc<dyn Fn(&str) -> String + 'b>;
+
+/// The functions applied by `map`.
+pub(crate) struct Mapper<'b, A, B> {
+    /// Applied to the text of every text document, which is copied as is if this is `None`
+    pub(crate) text: Option<TextFn<'b>>,
+    pub(crate) annotation: Rc<dyn Fn(&A) -> B + 'b>,
+}
+
+impl<A, B> Clone for Mapper<'_, A, B> {
+    fn clone(&self) -> Self {
+        Mapper {
+            text: self.text.clone(),
+            annotation: self.annotation.clone(),
+        }
+    }
+}
+
+/// Copies `doc` into `allocator` with the functions of `f` applied to its text and annotations.
+pub(crate) fn map<'a, 'b, T, A, B, D>(
+    doc: &Doc<'a, T, A>,
+    allocator: &'b D,
+    f: &Mapper<'b, A, B>,
+) -> D::Doc
+where
+    'a: 'b,
+    T: DocPtr<'a, A>,
+    B: 'b,
+    D: ?Sized + DocAllocator<'b, B>,
+{
+    infallible(fold(
+        doc,
+        |doc| Ok(map_leaf(doc, allocator, f)),
+        |doc, children| allocator.alloc(rebuild(doc, children, &*f.annotation)),
+    ))
+}
+
+/// Copies `doc` into `allocator` if it has no children, see `map`.
+pub(crate) fn map_leaf<'a, 'b, T, A, B, D>(
+    doc: &Doc<'a, T, A>,
+    allocator: &'b D,
+    f: &Mapper<'b, A, B>,
+) -> Option<D::Doc>
+where
+    'a: 'b,
+    T: DocPtr<'a, A>,
+    B: 'b,
+    D: ?Sized + DocAllocator<'b, B>,
+{
+    let text = |s: &str| match f.text {
+        Some(ref text) => allocator.text(text(s)).into_doc(),
+        None => allocator.text(s.to_string()).into_doc(),
+    };
+    Some(match *doc {
+        Doc::Nil => allocator.alloc(Doc::Nil),
+        Doc::Line => allocator.alloc(Doc::Line),
+        Doc::AlignmentMark => allocator.alloc(Doc::AlignmentMark),
+        Doc::BorrowedText(s) if f.text.is_none() => allocator.alloc(Doc::BorrowedText(s)),
+        Doc::SmallText(s) if f.text.is_none() => allocator.alloc(Doc::SmallText(s)),
+        Doc::OwnedText(ref s) => text(s),
+        Doc::BorrowedText(s) => text(s),
+        Doc::SmallText(ref s) => text(s),
+        Doc::Column(ref g) => {
+            let (f, g) = (f.clone(), g.clone());
+            let g = allocator.alloc_column_fn(move |col| map(&g(col), allocator, &f));
+            allocator.alloc(Doc::Column(g))
+        }
+        Doc::Nesting(ref g) => {
+            let (f, g) = (f.clone(), g.clone());
+            let g = allocator.alloc_column_fn(move |ind| map(&g(ind), allocator, &f));
+            allocator.alloc(Doc::Nesting(g))
+        }
+        _ => return None,
+    })
+}
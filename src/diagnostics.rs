@@ -0,0 +1,303 @@
+//! Rendering of compiler style diagnostics.
+//!
+//! A [`Diagnostic`](struct.Diagnostic.html) is converted into a single annotated document
+//! consisting of a header, a code frame where each label is underlined below its source line and
+//! a list of notes. Tabs in the code frame are expanded to four spaces and the underlines are
+//! placed by the display width of the text before them, so wide characters are accounted for.
+//!
+//! ```
+//! use pretty::diagnostics::{Diagnostic, Label, Severity};
+//! use pretty::Arena;
+//!
+//! let source = "fn main() {\n    let x: i32 = \"a\";\n}\n";
+//! let diagnostic = Diagnostic::new(Severity::Error, "mismatched types")
+//!     .with_label(Label::secondary(23..26, "expected due to this"))
+//!     .with_label(Label::primary(29..32, "expected `i32`, found `&str`"))
+//!     .with_note("integers can not be created from string literals");
+//!
+//! let arena = Arena::<()>::new();
+//! let doc = diagnostic.to_doc(&arena, "main.rs", source, |_| ());
+//! assert_eq!(
+//!     doc.1.pretty(40).to_string(),
+//!     r#"error: mismatched types
+//!  --> main.rs:2:18
+//!   |
+//! 2 |     let x: i32 = "a";
+//!   |            --- expected due to this
+//!   |                  ^^^ expected `i32`, found `&str`
+//!   = note: integers can not be created
+//!           from string literals"#,
+//! );
+//! ```
+
+use std::{borrow::Cow, fmt, ops::Range};
+
+use unicode_width::UnicodeWidthChar;
+
+use crate::{DocAllocator, DocBuilder};
+
+/// The severity of a diagnostic.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+    Help,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+            Severity::Help => "help",
+        })
+    }
+}
+
+/// Whether a label marks the main cause of a diagnostic or additional context.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LabelStyle {
+    /// Underlined with `^`
+    Primary,
+    /// Underlined with `-`
+    Secondary,
+}
+
+/// The parts of a rendered diagnostic, used to pick the annotation of each part.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Style {
+    /// The severity and message at the top of the diagnostic.
+    Header(Severity),
+    /// The line numbers, the `|` separators and the location arrow.
+    Gutter,
+    /// The underline and message of a label.
+    Label(LabelStyle),
+    /// A note below the code frame.
+    Note,
+}
+
+/// A message attached to a byte range of the source.
+#[derive(Clone, Debug)]
+pub struct Label<'a> {
+    pub style: LabelStyle,
+    pub range: Range<usize>,
+    pub message: Cow<'a, str>,
+}
+
+impl<'a> Label<'a> {
+    pub fn new<M>(style: LabelStyle, range: Range<usize>, message: M) -> Self
+    where
+        M: Into<Cow<'a, str>>,
+    {
+        Label {
+            style,
+            range,
+            message: message.into(),
+        }
+    }
+
+    pub fn primary<M>(range: Range<usize>, message: M) -> Self
+    where
+        M: Into<Cow<'a, str>>,
+    {
+        Label::new(LabelStyle::Primary, range, message)
+    }
+
+    pub fn secondary<M>(range: Range<usize>, message: M) -> Self
+    where
+        M: Into<Cow<'a, str>>,
+    {
+        Label::new(LabelStyle::Secondary, range, message)
+    }
+}
+
+/// A diagnostic message with labels pointing into a single source file.
+#[derive(Clone, Debug)]
+pub struct Diagnostic<'a> {
+    pub severity: Severity,
+    pub message: Cow<'a, str>,
+    pub labels: Vec<Label<'a>>,
+    pub notes: Vec<Cow<'a, str>>,
+}
+
+/// The number of columns a tab is expanded to in the code frame.
+const TAB_WIDTH: usize = 4;
+
+/// The zero based line of a byte offset in `source` and the text of that line before it. Offsets
+/// inside of a character are moved to its start.
+fn location(source: &str, offset: usize) -> (usize, &str) {
+    let mut offset = offset.min(source.len());
+    while !source.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let before = &source[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (before.matches('\n').count(), &before[line_start..])
+}
+
+/// The number of columns `s` takes up in the code frame.
+fn display_width(s: &str) -> usize {
+    s.chars()
+        .map(|c| match c {
+            '\t' => TAB_WIDTH,
+            _ => c.width().unwrap_or(0),
+        })
+        .sum()
+}
+
+impl<'a> Diagnostic<'a> {
+    pub fn new<M>(severity: Severity, message: M) -> Self
+    where
+        M: Into<Cow<'a, str>>,
+    {
+        Diagnostic {
+            severity,
+            message: message.into(),
+            labels: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn with_label(mut self, label: Label<'a>) -> Self {
+        self.labels.push(label);
+        self
+    }
+
+    pub fn with_note<M>(mut self, note: M) -> Self
+    where
+        M: Into<Cow<'a, str>>,
+    {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Renders the diagnostic for the source file `name` with the contents `source`. Each part
+    /// of the diagnostic is annotated with the value returned by `annotate`.
+    pub fn to_doc<'b, D, A>(
+        &self,
+        allocator: &'b D,
+        name: &str,
+        source: &'b str,
+        annotate: impl Fn(Style) -> A,
+    ) -> DocBuilder<'b, D, A>
+    where
+        D: DocAllocator<'b, A>,
+        D::Doc: Clone,
+        A: Clone,
+    {
+        let lines: Vec<&str> = source.lines().collect();
+
+        let mut labels: Vec<_> = self
+            .labels
+            .iter()
+            .map(|label| {
+                let (line, before) = location(source, label.range.start);
+                let (end_line, end_before) = location(source, label.range.end);
+                let start = display_width(before);
+                // Labels spanning several lines are underlined to the end of their first line
+                let end = if end_line == line {
+                    display_width(end_before)
+                } else {
+                    lines.get(line).map_or(0, |l| display_width(l))
+                };
+                (line, start, end.max(start + 1), label)
+            })
+            .collect();
+        labels.sort_by_key(|&(line, start, _, _)| (line, start));
+
+        let gutter_width = labels
+            .last()
+            .map_or(1, |&(line, ..)| (line + 1).to_string().len());
+        let gutter = |line: Option<usize>| {
+            let number = line.map_or(String::new(), |line| (line + 1).to_string());
+            allocator
+                .text(format!("{:>width$} |", number, width = gutter_width))
+                .annotate(annotate(Style::Gutter))
+        };
+
+        let mut doc = allocator
+            .text(format!("{}: {}", self.severity, self.message))
+            .annotate(annotate(Style::Header(self.severity)));
+
+        let main = labels
+            .iter()
+            .find(|label| label.3.style == LabelStyle::Primary)
+            .or_else(|| labels.first());
+        if let Some(&(line, _, _, label)) = main {
+            // The column in the header counts characters, like the columns of other tools
+            let column = location(source, label.range.start).1.chars().count();
+            doc = doc
+                .append(allocator.hardline())
+                .append(
+                    allocator
+                        .text(format!(
+                            "{:width$}--> {}:{}:{}",
+                            "",
+                            name,
+                            line + 1,
+                            column + 1,
+                            width = gutter_width
+                        ))
+                        .annotate(annotate(Style::Gutter)),
+                )
+                .append(allocator.hardline())
+                .append(gutter(None));
+        }
+
+        let mut previous_line = None;
+        for &(line, start, end, label) in &labels {
+            if previous_line != Some(line) {
+                if matches!(previous_line, Some(previous) if previous + 1 < line) {
+                    doc = doc
+                        .append(allocator.hardline())
+                        .append(allocator.text("...").annotate(annotate(Style::Gutter)));
+                }
+                let text = lines.get(line).copied().unwrap_or("");
+                doc = doc.append(allocator.hardline()).append(gutter(Some(line)));
+                if !text.is_empty() {
+                    let text: Cow<'b, str> = if text.contains('\t') {
+                        text.replace('\t', &" ".repeat(TAB_WIDTH)).into()
+                    } else {
+                        text.into()
+                    };
+                    doc = doc.append(" ").append(text);
+                }
+                previous_line = Some(line);
+            }
+
+            let marker = match label.style {
+                LabelStyle::Primary => "^",
+                LabelStyle::Secondary => "-",
+            };
+            let mut underline = allocator
+                .text(" ".repeat(start + 1))
+                .append(allocator.text(marker.repeat(end - start)));
+            if !label.message.is_empty() {
+                underline = underline
+                    .append(" ")
+                    .append(allocator.text(label.message.to_string()));
+            }
+            doc = doc
+                .append(allocator.hardline())
+                .append(gutter(None))
+                .append(underline.annotate(annotate(Style::Label(label.style))));
+        }
+
+        for note in &self.notes {
+            let words = note
+                .split_whitespace()
+                .map(|word| allocator.text(word.to_string()));
+            let note = allocator.intersperse(words, allocator.softline()).align();
+            doc = doc.append(allocator.hardline()).append(
+                allocator
+                    .text(format!("{:width$} = note: ", "", width = gutter_width))
+                    .append(note)
+                    .annotate(annotate(Style::Note)),
+            );
+        }
+
+        doc
+    }
+}
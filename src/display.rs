@@ -0,0 +1,126 @@
+//! Adapters for passing document producing types to APIs which only accept `Display`.
+//!
+//! A [`DisplayAdapter`](struct.DisplayAdapter.html) renders the document of a
+//! [`ToDoc`](trait.ToDoc.html) value each time it is formatted. The width is taken from, in
+//! order, the adapter itself, the width of the format specifier (`{:60}`) or the ambient width of
+//! the current thread set with [`with_width`](fn.with_width.html) or
+//! [`with_config`](../fn.with_config.html).
+//!
+//! ```
+//! use pretty::display::{self, ToDoc};
+//! use pretty::RcDoc;
+//!
+//! struct Call(&'static str, Vec<&'static str>);
+//!
+//! impl ToDoc for Call {
+//!     fn to_doc(&self) -> RcDoc<'_> {
+//!         let args = RcDoc::intersperse(
+//!             self.1.iter().map(|arg| RcDoc::text(*arg)),
+//!             RcDoc::text(",").append(RcDoc::line()),
+//!         );
+//!         RcDoc::text(self.0)
+//!             .append(RcDoc::text("("))
+//!             .append(RcDoc::line_().append(args).nest(4))
+//!             .append(RcDoc::line_())
+//!             .append(RcDoc::text(")"))
+//!             .group()
+//!     }
+//! }
+//!
+//! let call = Call("f", vec!["first", "second"]);
+//! assert_eq!(call.display().to_string(), "f(first, second)");
+//! assert_eq!(format!("{:10}", call.display()), "f(\n    first,\n    second\n)");
+//! assert_eq!(
+//!     display::with_width(10, || call.display().to_string()),
+//!     "f(\n    first,\n    second\n)",
+//! );
+//! ```
+
+use std::fmt;
+
+use crate::{config::current_config_with_width, current_config, with_config, RcDoc};
+
+/// The ambient width used when no width has been set with `with_width` or `with_config`.
+pub const DEFAULT_WIDTH: usize = 80;
+
+/// The width used by adapters on this thread which have no width of their own, see
+/// [`with_config`](../fn.with_config.html).
+pub fn ambient_width() -> usize {
+    current_config().width
+}
+
+/// Calls `f` with the ambient width of this thread set to `width`.
+pub fn with_width<R>(width: usize, f: impl FnOnce() -> R) -> R {
+    with_config(current_config_with_width(width), f)
+}
+
+/// A type which can be converted into a document.
+pub trait ToDoc {
+    fn to_doc(&self) -> RcDoc<'_>;
+
+    /// Returns a `Display` implementation rendering this value.
+    fn display(&self) -> DisplayAdapter<&Self> {
+        DisplayAdapter::new(self)
+    }
+
+    /// Returns a `Display` implementation rendering this value at `width`.
+    fn display_width(&self, width: usize) -> DisplayAdapter<&Self> {
+        DisplayAdapter::with_width(self, width)
+    }
+}
+
+impl<T> ToDoc for &'_ T
+where
+    T: ?Sized + ToDoc,
+{
+    fn to_doc(&self) -> RcDoc<'_> {
+        (**self).to_doc()
+    }
+}
+
+/// Renders the document of a `ToDoc` value when formatted.
+#[derive(Clone, Copy, Debug)]
+pub struct DisplayAdapter<T> {
+    value: T,
+    width: Option<usize>,
+}
+
+impl<T> DisplayAdapter<T> {
+    /// Renders `value` at the width of the format specifier or the ambient width.
+    pub fn new(value: T) -> Self {
+        DisplayAdapter { value, width: None }
+    }
+
+    /// Renders `value` at `width`.
+    pub fn with_width(value: T, width: usize) -> Self {
+        DisplayAdapter {
+            value,
+            width: Some(width),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T> fmt::Display for DisplayAdapter<T>
+where
+    T: ToDoc,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self
+            .width
+            .or_else(|| f.width())
+            .unwrap_or_else(ambient_width);
+        self.value.to_doc().render_fmt(width, f)
+    }
+}
+
+/// Renders `value` at `width` into a `String`.
+pub fn to_string_width<T>(value: &T, width: usize) -> String
+where
+    T: ?Sized + ToDoc,
+{
+    value.display_width(width).to_string()
+}
@@ -0,0 +1,213 @@
+//! Layout helpers for the Graphviz DOT language.
+//!
+//! Statements are placed on their own lines inside graph and subgraph blocks while attribute
+//! lists stay on the line of their statement if they fit, otherwise each attribute is placed on
+//! its own line.
+//!
+//! ```
+//! use pretty::dot::{edge, graph, node, subgraph};
+//! use pretty::Arena;
+//!
+//! let arena = Arena::<()>::new();
+//! let doc = graph(&arena, "digraph", "G", vec![
+//!     node(&arena, "a", vec![("label", "Start here"), ("shape", "box")]),
+//!     subgraph(&arena, "cluster_0", vec![
+//!         edge(&arena, true, "a", "b", Vec::<(&str, &str)>::new()),
+//!     ]),
+//! ]);
+//! assert_eq!(
+//!     doc.1.pretty(80).to_string(),
+//!     r#"digraph G {
+//!     a [label="Start here", shape=box];
+//!     subgraph cluster_0 {
+//!         a -> b;
+//!     }
+//! }"#,
+//! );
+//! assert_eq!(
+//!     doc.1.pretty(20).to_string(),
+//!     r#"digraph G {
+//!     a [
+//!         label="Start here",
+//!         shape=box
+//!     ];
+//!     subgraph cluster_0 {
+//!         a -> b;
+//!     }
+//! }"#,
+//! );
+//! ```
+
+use std::borrow::Cow;
+
+use crate::{Doc, DocAllocator, DocBuilder};
+
+const INDENT: isize = 4;
+
+/// Keywords can not be used as IDs unless they are quoted, in any case.
+const KEYWORDS: [&str; 6] = ["node", "edge", "graph", "digraph", "subgraph", "strict"];
+
+fn is_plain_id(s: &str) -> bool {
+    let is_name = matches!(s.chars().next(), Some(c) if c.is_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_alphanumeric() || c == '_')
+        && !KEYWORDS
+            .iter()
+            .any(|keyword| s.eq_ignore_ascii_case(keyword));
+    // An optional minus followed by digits with at most one decimal point
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    let is_numeral = digits.chars().any(|c| c.is_ascii_digit())
+        && digits.chars().all(|c| c.is_ascii_digit() || c == '.')
+        && digits.matches('.').count() <= 1;
+    is_name || is_numeral
+}
+
+/// A DOT identifier, quoted if it is not a name or numeral or if it is a keyword.
+pub fn id<'a, D, A, S>(allocator: &'a D, id: S) -> DocBuilder<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+    S: Into<Cow<'a, str>>,
+{
+    let id = id.into();
+    if is_plain_id(&id) {
+        allocator.text(id)
+    } else if id.contains(&['"', '\\', '\n'][..]) {
+        allocator
+            .text(
+                id.replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace('\n', "\\n"),
+            )
+            .double_quotes()
+    } else {
+        allocator.text(id).double_quotes()
+    }
+}
+
+/// An attribute list, `[key=value, ...]`. Empty attribute lists produce an empty document.
+pub fn attrs<'a, D, A, I, K, V>(allocator: &'a D, attrs: I) -> DocBuilder<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+    I: IntoIterator<Item = (K, V)>,
+    K: Into<Cow<'a, str>>,
+    V: Into<Cow<'a, str>>,
+{
+    let mut attrs = attrs
+        .into_iter()
+        .map(|(key, value)| id(allocator, key).append("=").append(id(allocator, value)));
+    let first = match attrs.next() {
+        Some(first) => first,
+        None => return allocator.nil(),
+    };
+    let body = attrs.fold(first, |body, attr| {
+        body.append(",").append(allocator.line()).append(attr)
+    });
+    allocator
+        .text("[")
+        .append(allocator.line_().append(body).nest(INDENT))
+        .append(allocator.line_())
+        .append("]")
+        .group()
+}
+
+fn statement<'a, D, A>(
+    allocator: &'a D,
+    doc: DocBuilder<'a, D, A>,
+    attrs: DocBuilder<'a, D, A>,
+) -> DocBuilder<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+{
+    let doc = if let Doc::Nil = *attrs.1 {
+        doc
+    } else {
+        doc.append(" ").append(attrs)
+    };
+    doc.append(allocator.text(";"))
+}
+
+/// A node statement, `name [attrs];`.
+pub fn node<'a, D, A, N, I, K, V>(allocator: &'a D, name: N, attrs: I) -> DocBuilder<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+    N: Into<Cow<'a, str>>,
+    I: IntoIterator<Item = (K, V)>,
+    K: Into<Cow<'a, str>>,
+    V: Into<Cow<'a, str>>,
+{
+    statement(
+        allocator,
+        id(allocator, name),
+        self::attrs(allocator, attrs),
+    )
+}
+
+/// An edge statement, `from -> to [attrs];` if `directed` and `from -- to [attrs];` otherwise.
+pub fn edge<'a, D, A, N, M, I, K, V>(
+    allocator: &'a D,
+    directed: bool,
+    from: N,
+    to: M,
+    attrs: I,
+) -> DocBuilder<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+    N: Into<Cow<'a, str>>,
+    M: Into<Cow<'a, str>>,
+    I: IntoIterator<Item = (K, V)>,
+    K: Into<Cow<'a, str>>,
+    V: Into<Cow<'a, str>>,
+{
+    let op = if directed { " -> " } else { " -- " };
+    statement(
+        allocator,
+        id(allocator, from).append(op).append(id(allocator, to)),
+        self::attrs(allocator, attrs),
+    )
+}
+
+fn block<'a, D, A, I>(
+    allocator: &'a D,
+    header: DocBuilder<'a, D, A>,
+    statements: I,
+) -> DocBuilder<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+    I: IntoIterator<Item = DocBuilder<'a, D, A>>,
+{
+    let body = statements.into_iter().fold(allocator.nil(), |body, stmt| {
+        body.append(allocator.hardline()).append(stmt)
+    });
+    header
+        .append(" {")
+        .append(body.nest(INDENT))
+        .append(allocator.hardline())
+        .append("}")
+}
+
+/// A top level graph, `kind name { statements }` where `kind` is `graph` or `digraph`
+/// (optionally prefixed by `strict`).
+pub fn graph<'a, D, A, N, I>(
+    allocator: &'a D,
+    kind: &'a str,
+    name: N,
+    statements: I,
+) -> DocBuilder<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+    N: Into<Cow<'a, str>>,
+    I: IntoIterator<Item = DocBuilder<'a, D, A>>,
+{
+    let header = allocator.text(kind).append(" ").append(id(allocator, name));
+    block(allocator, header, statements)
+}
+
+/// A subgraph, `subgraph name { statements }`.
+pub fn subgraph<'a, D, A, N, I>(allocator: &'a D, name: N, statements: I) -> DocBuilder<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+    N: Into<Cow<'a, str>>,
+    I: IntoIterator<Item = DocBuilder<'a, D, A>>,
+{
+    let header = allocator.text("subgraph ").append(id(allocator, name));
+    block(allocator, header, statements)
+}
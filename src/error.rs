@@ -0,0 +1,139 @@
+//! Conversion of `std::error::Error` chains into documents.
+//!
+//! ```
+//! use std::{error::Error, fmt};
+//! use pretty::error::report;
+//! use pretty::Arena;
+//!
+//! #[derive(Debug)]
+//! struct Outer(std::num::ParseIntError);
+//!
+//! impl fmt::Display for Outer {
+//!     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+//!         write!(f, "could not read the configuration file")
+//!     }
+//! }
+//!
+//! impl Error for Outer {
+//!     fn source(&self) -> Option<&(dyn Error + 'static)> {
+//!         Some(&self.0)
+//!     }
+//! }
+//!
+//! let error = Outer("x".parse::<i32>().unwrap_err());
+//! let arena = Arena::<()>::new();
+//! let doc = report(&arena, &error, None, |_| ());
+//! assert_eq!(
+//!     doc.1.pretty(80).to_string(),
+//!     "error: could not read the configuration file\ncaused by:\n  0: invalid digit found in string",
+//! );
+//! assert_eq!(
+//!     doc.1.pretty(30).to_string(),
+//!     "error: could not read the\n       configuration file\ncaused by:\n  0: invalid digit found in\n     string",
+//! );
+//! ```
+
+use std::{backtrace::Backtrace, error::Error};
+
+use crate::{DocAllocator, DocBuilder};
+
+/// The parts of an error report, used to pick the annotation of each part.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ErrorPart {
+    /// The `error:`, `caused by:` and `backtrace:` headings.
+    Heading,
+    /// The message of the reported error.
+    Message,
+    /// The message of an error in the `source` chain.
+    Cause,
+    /// The index in front of each cause.
+    Index,
+    /// The lines of the backtrace.
+    Backtrace,
+}
+
+/// Breaks `text` into words which are filled into the available width. Lines of the text are
+/// kept as is.
+fn message<'a, D, A>(allocator: &'a D, text: &str, indent: usize) -> DocBuilder<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+{
+    let mut doc = allocator.nil();
+    for (i, line) in text.lines().enumerate() {
+        if i != 0 {
+            doc = doc.append(allocator.hardline());
+        }
+        for (j, word) in line.split_whitespace().enumerate() {
+            if j != 0 {
+                doc = doc.append(allocator.softline());
+            }
+            doc = doc.append(allocator.text(word.to_string()));
+        }
+    }
+    doc.nest(indent as isize)
+}
+
+/// Creates a report of `error` and the chain of errors returned by `source`, followed by
+/// `backtrace` if one is given. Each part of the report is annotated with the value returned by
+/// `annotate`.
+pub fn report<'a, D, A>(
+    allocator: &'a D,
+    error: &(dyn Error + 'static),
+    backtrace: Option<&Backtrace>,
+    annotate: impl Fn(ErrorPart) -> A,
+) -> DocBuilder<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+{
+    const ERROR: &str = "error: ";
+
+    let mut doc = allocator
+        .text(ERROR)
+        .annotate(annotate(ErrorPart::Heading))
+        .append(
+            message(allocator, &error.to_string(), ERROR.len())
+                .annotate(annotate(ErrorPart::Message)),
+        );
+
+    let mut causes = Vec::new();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        causes.push(cause.to_string());
+        source = cause.source();
+    }
+
+    if !causes.is_empty() {
+        doc = doc.append(allocator.hardline()).append(
+            allocator
+                .text("caused by:")
+                .annotate(annotate(ErrorPart::Heading)),
+        );
+        let digits = (causes.len() - 1).to_string().len();
+        for (i, cause) in causes.iter().enumerate() {
+            let index = format!("  {:>width$}: ", i, width = digits);
+            let indent = index.len();
+            doc = doc
+                .append(allocator.hardline())
+                .append(allocator.text(index).annotate(annotate(ErrorPart::Index)))
+                .append(message(allocator, cause, indent).annotate(annotate(ErrorPart::Cause)));
+        }
+    }
+
+    if let Some(backtrace) = backtrace {
+        doc = doc.append(allocator.hardline()).append(
+            allocator
+                .text("backtrace:")
+                .annotate(annotate(ErrorPart::Heading)),
+        );
+        let lines = backtrace
+            .to_string()
+            .lines()
+            .fold(allocator.nil(), |doc, line| {
+                doc.append(allocator.hardline())
+                    .append(allocator.text(line.to_string()))
+            });
+        doc = doc.append(lines.nest(2).annotate(annotate(ErrorPart::Backtrace)));
+    }
+
+    doc
+}
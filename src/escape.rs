@@ -0,0 +1,197 @@
+use std::{borrow::Cow, fmt::Write};
+
+/// Escapes `s` by replacing each character for which `escape` returns `Some` with the returned
+/// text. No allocation is done if nothing needs to be escaped.
+fn escape_with<'a>(s: Cow<'a, str>, escape: impl Fn(char, &mut String) -> bool) -> Cow<'a, str> {
+    let mut buf = String::new();
+    let first = match s.char_indices().find(|&(_, c)| escape(c, &mut buf)) {
+        Some((i, c)) => (i, c),
+        None => return s,
+    };
+
+    let mut out = String::with_capacity(s.len() + buf.len());
+    out.push_str(&s[..first.0]);
+    out.push_str(&buf);
+    for c in s[first.0 + first.1.len_utf8()..].chars() {
+        buf.clear();
+        if escape(c, &mut buf) {
+            out.push_str(&buf);
+        } else {
+            out.push(c);
+        }
+    }
+    Cow::Owned(out)
+}
+
+/// Escapes `s` for use inside a JSON string literal.
+pub(crate) fn json(s: Cow<'_, str>) -> Cow<'_, str> {
+    escape_with(s, |c, out| {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            c if c < ' ' => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            _ => return false,
+        }
+        true
+    })
+}
+
+/// Escapes `s` for use as HTML text or inside a quoted attribute value.
+pub(crate) fn html(s: Cow<'_, str>) -> Cow<'_, str> {
+    escape_with(s, |c, out| {
+        out.push_str(match c {
+            '&' => "&amp;",
+            '<' => "&lt;",
+            '>' => "&gt;",
+            '"' => "&quot;",
+            '\'' => "&#39;",
+            '\n' => "&#10;",
+            _ => return false,
+        });
+        true
+    })
+}
+
+/// Escapes `s` for use inside a Rust string literal.
+pub(crate) fn rust_str(s: Cow<'_, str>) -> Cow<'_, str> {
+    escape_with(s, |c, out| {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\0' => out.push_str("\\0"),
+            c if c.is_control() => write!(out, "\\u{{{:x}}}", c as u32).unwrap(),
+            _ => return false,
+        }
+        true
+    })
+}
+
+/// The quotes of a string literal, see [`QuoteStyle`](struct.QuoteStyle.html).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Quote {
+    /// `"…"`
+    Double,
+    /// `'…'`
+    Single,
+    /// Double quotes unless the text contains more double quotes than single quotes, so that the
+    /// fewest quotes need to be escaped.
+    Fewest,
+}
+
+/// The language whose escapes [`QuoteStyle`](struct.QuoteStyle.html) uses for characters which
+/// can not appear in a string literal as is.
+///
+/// Every dialect escapes `\`, the quote, `\n`, `\r` and `\t`. The other control characters are
+/// escaped with fixed length escapes so that a following digit is never read as part of them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Dialect {
+    /// Three digit octal escapes of the UTF-8 bytes, `\001`
+    C,
+    /// `\u0001`
+    Java,
+    /// `\x01`
+    JavaScript,
+    /// `\u0001`. JSON strings are always double quoted, whatever the quote of the style.
+    Json,
+    /// `\x01`
+    Python,
+    /// `\u{1}`
+    Rust,
+}
+
+/// How [`DocAllocator::string_literal`](trait.DocAllocator.html#method.string_literal) writes a
+/// string literal.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct QuoteStyle {
+    pub quote: Quote,
+    /// The escapes of the content, `Dialect::JavaScript` by default.
+    pub dialect: Dialect,
+    /// If set, a literal which does not fit the line is split after whitespace into several
+    /// literals on separate lines, each but the last followed by this text. Use `""` for
+    /// languages which concatenate adjacent literals, such as C and Python, or `" +"` for Java
+    /// and JavaScript.
+    pub split: Option<&'static str>,
+}
+
+impl QuoteStyle {
+    pub const DOUBLE: QuoteStyle = QuoteStyle::new(Quote::Double);
+    pub const SINGLE: QuoteStyle = QuoteStyle::new(Quote::Single);
+    pub const FEWEST: QuoteStyle = QuoteStyle::new(Quote::Fewest);
+
+    pub const fn new(quote: Quote) -> Self {
+        QuoteStyle {
+            quote,
+            dialect: Dialect::JavaScript,
+            split: None,
+        }
+    }
+
+    /// Escapes the content with the escapes of `dialect`.
+    pub const fn with_dialect(self, dialect: Dialect) -> Self {
+        QuoteStyle { dialect, ..self }
+    }
+
+    /// Splits literals which do not fit the line into literals joined by `joiner`.
+    pub const fn with_split(self, joiner: &'static str) -> Self {
+        QuoteStyle {
+            split: Some(joiner),
+            ..self
+        }
+    }
+}
+
+/// Picks the quote of a string literal containing `s` and escapes `s` in pieces which may be
+/// written as separate literals. There is a single piece unless `style` splits literals.
+pub(crate) fn string_literal(s: &str, style: QuoteStyle) -> (&'static str, Vec<String>) {
+    let quote = match style.quote {
+        _ if style.dialect == Dialect::Json => '"',
+        Quote::Double => '"',
+        Quote::Single => '\'',
+        Quote::Fewest if s.matches('"').count() > s.matches('\'').count() => '\'',
+        Quote::Fewest => '"',
+    };
+    let escape = |piece: &str| {
+        escape_with(piece.into(), |c, out| {
+            match c {
+                '\\' => out.push_str("\\\\"),
+                '\n' => out.push_str("\\n"),
+                '\r' => out.push_str("\\r"),
+                '\t' => out.push_str("\\t"),
+                c if c == quote => {
+                    out.push('\\');
+                    out.push(c);
+                }
+                c if c.is_control() => match style.dialect {
+                    Dialect::C => {
+                        for byte in c.encode_utf8(&mut [0; 4]).bytes() {
+                            write!(out, "\\{:03o}", byte).unwrap();
+                        }
+                    }
+                    Dialect::Java | Dialect::Json => write!(out, "\\u{:04x}", c as u32).unwrap(),
+                    // All control characters are below U+0100
+                    Dialect::JavaScript | Dialect::Python => {
+                        write!(out, "\\x{:02x}", c as u32).unwrap()
+                    }
+                    Dialect::Rust => write!(out, "\\u{{{:x}}}", c as u32).unwrap(),
+                },
+                _ => return false,
+            }
+            true
+        })
+        .into_owned()
+    };
+    let pieces = match style.split {
+        // Whitespace stays at the end of the piece before the split
+        Some(_) => s.split_inclusive(char::is_whitespace).map(escape).collect(),
+        None => vec![escape(s)],
+    };
+    (if quote == '"' { "\"" } else { "'" }, pieces)
+}
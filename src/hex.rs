@@ -0,0 +1,31 @@
+use std::fmt::Write;
+
+/// The number of bytes per row which are tried in order, the last one is used if none fit.
+pub(crate) const COLUMNS: [usize; 4] = [32, 16, 8, 4];
+
+/// Formats `bytes` in the layout of `hexdump -C` with `columns` bytes per row.
+pub(crate) fn rows(bytes: &[u8], columns: usize) -> impl Iterator<Item = String> + '_ {
+    bytes.chunks(columns).enumerate().map(move |(i, chunk)| {
+        let mut row = format!("{:08x} ", i * columns);
+        for j in 0..columns {
+            row.push(' ');
+            if j != 0 && j % 8 == 0 {
+                row.push(' ');
+            }
+            match chunk.get(j) {
+                Some(byte) => write!(row, "{:02x}", byte).unwrap(),
+                None => row.push_str("  "),
+            }
+        }
+        row.push_str("  |");
+        row.extend(chunk.iter().map(|&byte| {
+            if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            }
+        }));
+        row.push('|');
+        row
+    })
+}
@@ -0,0 +1,57 @@
+use std::{cmp, fmt, time::Duration};
+
+/// Displays a number of bytes using binary units, such as `1.5 KiB` or `12 MiB`.
+pub(crate) struct Bytes(pub u64);
+
+impl fmt::Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+
+        if self.0 < 1024 {
+            return write!(f, "{} B", self.0);
+        }
+        let mut value = self.0 as f64 / 1024.0;
+        let mut unit = 0;
+        // Move to the next unit if the value would be rounded up to 1024
+        while value >= 1023.5 && unit + 1 < UNITS.len() {
+            value /= 1024.0;
+            unit += 1;
+        }
+        if value < 9.95 {
+            write!(f, "{:.1} {}", value, UNITS[unit])
+        } else {
+            write!(f, "{:.0} {}", value, UNITS[unit])
+        }
+    }
+}
+
+/// Displays a duration with at most two units, such as `1h 5m`, `1.5s` or `250ms`.
+pub(crate) struct HumanDuration(pub Duration);
+
+impl fmt::Display for HumanDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.0.as_secs();
+        let nanos = self.0.subsec_nanos();
+        // Durations which would be rounded up to `10.0s` are shown like the longer ones
+        let secs = if self.0.as_secs_f64() >= 9.95 {
+            cmp::max(secs, 10)
+        } else {
+            secs
+        };
+        if secs >= 3600 {
+            write!(f, "{}h {}m", secs / 3600, secs % 3600 / 60)
+        } else if secs >= 60 {
+            write!(f, "{}m {}s", secs / 60, secs % 60)
+        } else if secs >= 10 {
+            write!(f, "{}s", secs)
+        } else if secs >= 1 {
+            write!(f, "{:.1}s", self.0.as_secs_f64())
+        } else if nanos >= 1_000_000 {
+            write!(f, "{}ms", nanos / 1_000_000)
+        } else if nanos >= 1_000 {
+            write!(f, "{}µs", nanos / 1_000)
+        } else {
+            write!(f, "{}ns", nanos)
+        }
+    }
+}
@@ -137,32 +137,107 @@
 //! [examples/trees.rs](https://github.com/freebroccolo/pretty.rs/blob/master/examples/trees.rs#L39)
 //! for this approach.
 
+#[cfg(feature = "bumpalo")]
+pub extern crate bumpalo;
+#[cfg(feature = "log")]
+pub extern crate log;
 #[cfg(feature = "termcolor")]
 pub extern crate termcolor;
-
-use std::{borrow::Cow, convert::TryInto, fmt, io, ops::Deref, rc::Rc};
+#[cfg(feature = "tracing")]
+pub extern crate tracing;
+
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    convert::TryInto,
+    fmt,
+    hash::Hasher,
+    io,
+    ops::Deref,
+    rc::Rc,
+};
 #[cfg(feature = "termcolor")]
 use termcolor::{ColorSpec, WriteColor};
 
+pub mod bench_support;
+pub mod builder;
+#[cfg(feature = "bumpalo")]
+mod bump;
+#[cfg(feature = "capi")]
+pub mod capi;
+mod case;
+mod config;
+mod deep;
+#[cfg(feature = "diagnostics")]
+pub mod diagnostics;
+pub mod display;
+pub mod dot;
+pub mod error;
+mod escape;
+mod hex;
+#[cfg(feature = "humanize")]
+mod human;
+pub mod logging;
+pub mod number;
+mod owned;
+#[cfg(feature = "pyo3")]
+pub mod python;
 mod render;
-
+pub mod rust;
+pub mod sql;
+pub mod stats;
+pub mod testing;
+pub mod theme;
+#[cfg(feature = "proc-macro2")]
+pub mod token_stream;
+pub mod trace;
+pub mod tree;
+mod validate;
+pub mod verify;
+#[cfg(feature = "wasm-bindgen")]
+pub mod wasm;
+pub mod yaml;
+
+use self::config::current_config_with_width;
+pub use self::config::{
+    color_mode, colors_enabled, current_config, set_color_mode, with_config, with_vars, ColorMode,
+    Indent, LineEnding, RenderConfig,
+};
+pub use self::escape::{Dialect, Quote, QuoteStyle};
+pub use self::owned::{NoFn, OwnedDoc};
 #[cfg(feature = "termcolor")]
 pub use self::render::TermColored;
-pub use self::render::{FmtWrite, IoWrite, Render, RenderAnnotated};
+pub use self::render::{
+    AnnotationTable, BidiIsolate, Cost, DefaultCost, EncodedWrite, Encoding, FmtWrite, Hashed,
+    IoWrite, Keyed, KnifeEdge, LayoutArena, LayoutCmd, LayoutIter, Limit, LimitError, Limits,
+    MapAnnotations, Mode, Overflow, PreserveWhitespace, Recover, Render, RenderAnnotated, Step,
+    Truncate,
+};
+pub use self::validate::ValidationError;
 
 /// The concrete document type. This type is not meant to be used directly. Instead use the static
 /// functions on `Doc` or the methods on an `DocAllocator`.
 ///
 /// The `T` parameter is used to abstract over pointers to `Doc`. See `RefDoc` and `BoxDoc` for how
 /// it is used
+///
+/// New kinds of documents may be added in any release, so matches on `Doc` outside of this crate
+/// need a wildcard arm.
 #[derive(Clone)]
+#[non_exhaustive]
 pub enum Doc<'a, T: DocPtr<'a, A>, A = ()> {
     Nil,
     Append(T, T),
     Group(T),
+    BiasedGroup(isize, T),
     FlatAlt(T, T),
     Nest(isize, T),
+    MaxWidth(usize, T),
+    Unbounded(T),
+    AlignmentGroup(T),
+    Delimiter(T),
     Line,
+    AlignmentMark,
     OwnedText(Box<str>),
     BorrowedText(&'a str),
     SmallText(SmallText),
@@ -187,8 +262,20 @@ where
             }
             Doc::FlatAlt(ref x, ref y) => f.debug_tuple("FlatAlt").field(x).field(y).finish(),
             Doc::Group(ref doc) => f.debug_tuple("Group").field(doc).finish(),
+            Doc::BiasedGroup(bias, ref doc) => f
+                .debug_tuple("BiasedGroup")
+                .field(&bias)
+                .field(doc)
+                .finish(),
             Doc::Nest(off, ref doc) => f.debug_tuple("Nest").field(&off).field(doc).finish(),
+            Doc::MaxWidth(width, ref doc) => {
+                f.debug_tuple("MaxWidth").field(&width).field(doc).finish()
+            }
+            Doc::Unbounded(ref doc) => f.debug_tuple("Unbounded").field(doc).finish(),
+            Doc::AlignmentGroup(ref doc) => f.debug_tuple("AlignmentGroup").field(doc).finish(),
+            Doc::Delimiter(ref doc) => f.debug_tuple("Delimiter").field(doc).finish(),
             Doc::Line => f.debug_tuple("Line").finish(),
+            Doc::AlignmentMark => f.debug_tuple("AlignmentMark").finish(),
             Doc::OwnedText(ref s) => f.debug_tuple("Text").field(s).finish(),
             Doc::BorrowedText(ref s) => f.debug_tuple("Text").field(s).finish(),
             Doc::SmallText(ref s) => f.debug_tuple("Text").field(s).finish(),
@@ -204,9 +291,38 @@ where
 
 macro_rules! impl_doc {
     ($name: ident, $allocator: ident) => {
-        #[derive(Clone)]
         pub struct $name<'a, A = ()>(Box<Doc<'a, $name<'a, A>, A>>);
 
+        // `Clone`, `PartialEq` and `Drop` do not recurse so that deep documents do not overflow
+        // the stack
+        impl<'a, A> Clone for $name<'a, A>
+        where
+            A: Clone,
+        {
+            fn clone(&self) -> Self {
+                deep::clone(&self.0)
+            }
+        }
+
+        impl<'a, A> PartialEq for $name<'a, A>
+        where
+            A: PartialEq,
+        {
+            fn eq(&self, other: &Self) -> bool {
+                *self.0 == *other.0
+            }
+        }
+
+        impl<'a, A> Drop for $name<'a, A> {
+            fn drop(&mut self) {
+                let mut stack = vec![];
+                deep::take_children(&mut self.0, &mut stack);
+                while let Some(mut doc) = stack.pop() {
+                    deep::take_children(&mut doc.0, &mut stack);
+                }
+            }
+        }
+
         impl<'a, A> fmt::Debug for $name<'a, A>
         where
             A: fmt::Debug,
@@ -216,6 +332,12 @@ macro_rules! impl_doc {
             }
         }
 
+        impl<'a, A> fmt::Display for $name<'a, A> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+
         impl<'a, A> $name<'a, A> {
             pub fn new(doc: Doc<'a, $name<'a, A>, A>) -> $name<'a, A> {
                 $name(Box::new(doc))
@@ -292,6 +414,17 @@ macro_rules! impl_doc {
                 $allocator.concat(docs).into_doc()
             }
 
+            /// Like `intersperse`, but empty documents are skipped together with their separator.
+            #[inline]
+            pub fn join_non_empty<I, S>(docs: I, separator: S) -> Self
+            where
+                I: IntoIterator,
+                I::Item: Into<BuildDoc<'a, Self, A>>,
+                S: Into<BuildDoc<'a, Self, A>> + Clone,
+            {
+                $allocator.join_non_empty(docs, separator).into_doc()
+            }
+
             /// A single document interspersing the given separator `S` between the given documents.  For
             /// example, if the documents are `[A, B, C, ..., Z]`, this yields `[A, S, B, S, C, S, ..., S, Z]`.
             ///
@@ -332,6 +465,52 @@ macro_rules! impl_doc {
                 DocBuilder(&$allocator, self.into()).group().into_doc()
             }
 
+            /// Mark this document as a group which is flattened even if it overflows the width by
+            /// up to `weight` columns, see
+            /// [`DocBuilder::prefer_flat`](struct.DocBuilder.html#method.prefer_flat).
+            #[inline]
+            pub fn prefer_flat(self, weight: usize) -> Self {
+                DocBuilder(&$allocator, self.into()).prefer_flat(weight).into_doc()
+            }
+
+            /// Mark this document as a group which is only flattened if it fits with `weight`
+            /// columns to spare.
+            #[inline]
+            pub fn prefer_broken(self, weight: usize) -> Self {
+                DocBuilder(&$allocator, self.into()).prefer_broken(weight).into_doc()
+            }
+
+            /// Lays out this document as if the page ended `width` columns after the column it
+            /// starts at, unless the page ends before that.
+            #[inline]
+            pub fn max_width(self, width: usize) -> Self {
+                DocBuilder(&$allocator, self.into()).max_width(width).into_doc()
+            }
+
+            /// Lays out this document as if the page had no width limit.
+            #[inline]
+            pub fn unbounded(self) -> Self {
+                DocBuilder(&$allocator, self.into()).unbounded().into_doc()
+            }
+
+            /// Pads the alignment marks of this document so that the marks at the same index on
+            /// each of its lines start at the same column, see
+            /// [`DocBuilder::align_marks`](struct.DocBuilder.html#method.align_marks).
+            #[inline]
+            pub fn align_marks(self) -> Self {
+                DocBuilder(&$allocator, self.into()).align_marks().into_doc()
+            }
+
+            /// Lays out this document on a single line if it fits, otherwise `marker` is laid out
+            /// in its place.
+            #[inline]
+            pub fn ellipsize<D>(self, marker: D) -> Self
+            where
+                D: Into<BuildDoc<'a, Self, A>>,
+            {
+                DocBuilder(&$allocator, self.into()).ellipsize(marker).into_doc()
+            }
+
             /// Increase the indentation level of this document.
             #[inline]
             pub fn nest(self, offset: isize) -> Self {
@@ -345,6 +524,67 @@ macro_rules! impl_doc {
                     .into_doc()
             }
 
+            /// Embeds already rendered, multi-line `text`, indenting each line to the current
+            /// nesting.
+            #[inline]
+            pub fn rendered_block<U: Into<Cow<'a, str>>>(text: U) -> Self {
+                $allocator.rendered_block(text).into_doc()
+            }
+
+            /// A string literal containing `s`, see
+            /// [`DocAllocator::string_literal`](trait.DocAllocator.html#method.string_literal).
+            #[inline]
+            pub fn string_literal(s: &str, style: QuoteStyle) -> Self
+            where
+                A: Clone,
+            {
+                $allocator.string_literal(s, style).into_doc()
+            }
+
+            /// A document which is only produced by calling `f` the first time the layout reaches
+            /// it, see [`DocAllocator::lazy`](trait.DocAllocator.html#method.lazy).
+            #[inline]
+            pub fn lazy(f: impl FnOnce() -> Self + 'a) -> Self
+            where
+                A: Clone,
+            {
+                $allocator.lazy(f).into_doc()
+            }
+
+            /// Embeds `doc`, a document in another language, see
+            /// [`DocAllocator::embedded`](trait.DocAllocator.html#method.embedded).
+            #[inline]
+            pub fn embedded<'b, U, B>(
+                tag: A,
+                width: usize,
+                doc: &Doc<'b, U, B>,
+                map: impl Fn(&B) -> A + 'a,
+            ) -> Self
+            where
+                'b: 'a,
+                U: DocPtr<'b, B>,
+                A: Clone,
+            {
+                $allocator.embedded(tag, width, doc, map).into_doc()
+            }
+
+            /// A document which is loaded by calling `loader` with `key` whenever the layout reaches
+            /// it, see [`DocAllocator::include`](trait.DocAllocator.html#method.include).
+            #[inline]
+            pub fn include<K>(key: K, loader: impl Fn(&K) -> Self + 'a) -> Self
+            where
+                K: 'a,
+            {
+                $allocator.include(key, loader).into_doc()
+            }
+
+            /// A document whose text is the value of the variable `key` when it is rendered, see
+            /// [`DocAllocator::placeholder`](trait.DocAllocator.html#method.placeholder).
+            #[inline]
+            pub fn placeholder<K: Into<String>>(key: K) -> Self {
+                $allocator.placeholder(key).into_doc()
+            }
+
             #[inline]
             pub fn union<D>(self, other: D) -> Self
             where
@@ -365,7 +605,7 @@ impl fmt::Write for FmtText {
     fn write_str(&mut self, s: &str) -> fmt::Result {
         match self {
             FmtText::Small(buf) => {
-                if let Err(_) = buf.try_push_str(s) {
+                if buf.try_push_str(s).is_err() {
                     let mut new_str = String::with_capacity(buf.len() + s.len());
                     new_str.push_str(buf);
                     new_str.push_str(s);
@@ -403,12 +643,40 @@ macro_rules! impl_doc_methods {
                 }).into()
             }
 
+            /// The size `bytes` in binary units, such as `1.5 KiB`.
+            #[cfg(feature = "humanize")]
+            #[inline]
+            pub fn human_bytes(bytes: u64) -> Self {
+                Self::as_string(human::Bytes(bytes))
+            }
+
+            /// The duration using at most two units, such as `1h 5m`, `1.5s` or `250ms`.
+            #[cfg(feature = "humanize")]
+            #[inline]
+            pub fn human_duration(duration: std::time::Duration) -> Self {
+                Self::as_string(human::HumanDuration(duration))
+            }
+
+            /// The shortest text which parses back to `value`, such as `0.1` or `1e300`.
+            #[cfg(feature = "ryu")]
+            #[inline]
+            pub fn float(value: f64) -> Self {
+                Self::as_string(ryu::Buffer::new().format(value))
+            }
+
             /// A single hardline.
             #[inline]
             pub fn hardline() -> Self {
                 Doc::Line.into()
             }
 
+            /// A position which is padded to line up with the marks of the other lines of the
+            /// enclosing `align_marks`.
+            #[inline]
+            pub fn alignment_mark() -> Self {
+                Doc::AlignmentMark.into()
+            }
+
             /// The given text, which must not contain line breaks.
             #[inline]
             pub fn text<U: Into<Cow<'a, str>>>(data: U) -> Self {
@@ -418,6 +686,49 @@ macro_rules! impl_doc_methods {
                 }
             }
 
+            /// The given text with the escapes of a JSON string literal applied.
+            #[inline]
+            pub fn text_escaped_json<U: Into<Cow<'a, str>>>(data: U) -> Self {
+                Self::text(escape::json(data.into()))
+            }
+
+            /// The given text with the escapes of HTML text applied.
+            #[inline]
+            pub fn text_escaped_html<U: Into<Cow<'a, str>>>(data: U) -> Self {
+                Self::text(escape::html(data.into()))
+            }
+
+            /// The given text with the escapes of a Rust string literal applied.
+            #[inline]
+            pub fn text_escaped_rust_str<U: Into<Cow<'a, str>>>(data: U) -> Self {
+                Self::text(escape::rust_str(data.into()))
+            }
+
+            /// The identifier `name`, written in any common case, converted to `snake_case`.
+            #[inline]
+            pub fn ident_snake(name: &str) -> Self {
+                Self::text(case::snake(name))
+            }
+
+            /// The identifier `name`, written in any common case, converted to `camelCase`.
+            #[inline]
+            pub fn ident_camel(name: &str) -> Self {
+                Self::text(case::camel(name))
+            }
+
+            /// The identifier `name`, written in any common case, converted to `PascalCase`.
+            #[inline]
+            pub fn ident_pascal(name: &str) -> Self {
+                Self::text(case::pascal(name))
+            }
+
+            /// The identifier `name`, written in any common case, converted to
+            /// `SCREAMING_SNAKE_CASE`.
+            #[inline]
+            pub fn ident_screaming_snake(name: &str) -> Self {
+                Self::text(case::screaming_snake(name))
+            }
+
             #[inline]
             pub fn space() -> Self {
                 Doc::BorrowedText(" ").into()
@@ -438,6 +749,20 @@ macro_rules! impl_doc_methods {
             pub fn line_() -> Self {
                 Self::hardline().flat_alt(Self::nil()).into()
             }
+
+            /// Acts like `line` but is replaced by `text` instead of a space if grouped on a single
+            /// line
+            #[inline]
+            pub fn line_with_flat_text<U: Into<Cow<'a, str>>>(text: U) -> Self {
+                Self::hardline().flat_alt(Self::text(text)).into()
+            }
+
+            /// Acts like `line` but is replaced by the separator `sep` instead of a space if
+            /// grouped on a single line
+            #[inline]
+            pub fn space_or(sep: Self) -> Self {
+                Self::hardline().flat_alt(sep).into()
+            }
         }
     };
 }
@@ -460,7 +785,7 @@ where
     where
         D: Into<BuildDoc<'a, T, A>>,
     {
-        DocBuilder(&T::ALLOCATOR, self.into()).flat_alt(doc).1
+        DocBuilder(&T::ALLOCATOR, self).flat_alt(doc).1
     }
 }
 
@@ -502,7 +827,22 @@ where
     T: DocPtr<'a, A> + 'a,
 {
     doc: &'d Doc<'a, T, A>,
-    width: usize,
+    config: RenderConfig,
+}
+
+/// Renders the document at the width of the format specifier (`{:60}`), or otherwise the width
+/// of the [`current_config`](fn.current_config.html).
+impl<'a, T, A> fmt::Display for Doc<'a, T, A>
+where
+    T: DocPtr<'a, A> + 'a,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let config = match f.width() {
+            Some(width) => current_config_with_width(width),
+            None => current_config(),
+        };
+        self.render_raw_with(config, &mut FmtWrite::new(f))
+    }
 }
 
 impl<'a, T, A> fmt::Display for Pretty<'a, '_, T, A>
@@ -510,7 +850,132 @@ where
     T: DocPtr<'a, A>,
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        self.doc.render_fmt(self.width, f)
+        self.doc.render_raw_with(self.config, &mut FmtWrite::new(f))
+    }
+}
+
+/// The iterator returned by [`Doc::iter_text`](enum.Doc.html#method.iter_text).
+pub struct TextIter<'a, 'd, T, A>
+where
+    A: 'a,
+    T: DocPtr<'a, A> + 'a,
+{
+    stack: Vec<TextFrame<'a, 'd, T, A>>,
+    /// The column after the text so far, measured like the layout does
+    column: usize,
+}
+
+enum TextFrame<'a, 'd, T, A>
+where
+    T: DocPtr<'a, A> + 'a,
+{
+    Doc(&'d Doc<'a, T, A>),
+    /// Text of a document produced by a `column` or `nesting` function
+    Owned(String),
+}
+
+impl<'a, 'd, T, A> TextIter<'a, 'd, T, A>
+where
+    T: DocPtr<'a, A> + 'a,
+{
+    fn produced(&mut self, doc: T) {
+        let iter = TextIter {
+            stack: vec![TextFrame::Doc(&*doc)],
+            column: self.column,
+        };
+        let texts: Vec<_> = iter.map(Cow::into_owned).collect();
+        self.stack
+            .extend(texts.into_iter().rev().map(TextFrame::Owned));
+    }
+}
+
+impl<'a, 'd, T, A> Iterator for TextIter<'a, 'd, T, A>
+where
+    T: DocPtr<'a, A> + 'a,
+{
+    type Item = Cow<'d, str>;
+
+    fn next(&mut self) -> Option<Cow<'d, str>> {
+        while let Some(frame) = self.stack.pop() {
+            let doc = match frame {
+                TextFrame::Doc(doc) => doc,
+                TextFrame::Owned(text) => {
+                    self.column += render::text_width(&text);
+                    return Some(Cow::Owned(text));
+                }
+            };
+            let text = match *doc {
+                Doc::Append(ref l, ref r) => {
+                    self.stack.push(TextFrame::Doc(r));
+                    self.stack.push(TextFrame::Doc(l));
+                    continue;
+                }
+                Doc::Group(ref doc)
+                | Doc::BiasedGroup(_, ref doc)
+                | Doc::Nest(_, ref doc)
+                | Doc::MaxWidth(_, ref doc)
+                | Doc::Unbounded(ref doc)
+                | Doc::AlignmentGroup(ref doc)
+                | Doc::Delimiter(ref doc)
+                | Doc::Annotated(_, ref doc)
+                | Doc::Union(ref doc, _) => {
+                    self.stack.push(TextFrame::Doc(doc));
+                    continue;
+                }
+                // A flat alternative which is a `hardline` is never laid out, it only keeps the
+                // enclosing groups from being flattened
+                Doc::FlatAlt(ref doc, ref flat) => {
+                    let doc = if let Doc::Line = **flat { doc } else { flat };
+                    self.stack.push(TextFrame::Doc(doc));
+                    continue;
+                }
+                Doc::Nil | Doc::AlignmentMark => continue,
+                Doc::Line => " ",
+                Doc::Column(ref f) => {
+                    let doc = f(self.column);
+                    self.produced(doc);
+                    continue;
+                }
+                Doc::Nesting(ref f) => {
+                    self.produced(f(0));
+                    continue;
+                }
+                Doc::OwnedText(ref s) => &s[..],
+                Doc::BorrowedText(s) => s,
+                Doc::SmallText(ref s) => &s[..],
+            };
+            if !text.is_empty() {
+                self.column += render::text_width(text);
+                return Some(Cow::Borrowed(text));
+            }
+        }
+        None
+    }
+}
+
+/// The iterator returned by [`Doc::render_widths`](enum.Doc.html#method.render_widths).
+pub struct RenderWidths<'a, 'd, T, A>
+where
+    A: 'a,
+    T: DocPtr<'a, A> + 'a,
+{
+    doc: &'d Doc<'a, T, A>,
+    widths: std::slice::Iter<'d, usize>,
+}
+
+impl<'a, T, A> Iterator for RenderWidths<'a, '_, T, A>
+where
+    T: DocPtr<'a, A> + 'a,
+{
+    type Item = (usize, String);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let width = *self.widths.next()?;
+        Some((width, self.doc.pretty(width).to_string()))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.widths.size_hint()
     }
 }
 
@@ -527,78 +992,584 @@ where
         self.render_raw(width, &mut IoWrite::new(out))
     }
 
-    /// Writes a rendered document to a `std::fmt::Write` object.
+    /// Writes a rendered document to a `std::io::Write` object, laid out as if it started at
+    /// `column` of a line whose line breaks are indented by `indent`. This lets a document be
+    /// written after text which has already been written on the same line, such as the `error: `
+    /// of a message, and still be wrapped at `width`.
+    ///
+    /// ```
+    /// use pretty::RcDoc;
+    ///
+    /// let words = ["the", "value", "does", "not", "fit"];
+    /// let message = RcDoc::<()>::intersperse(words, RcDoc::line()).group();
+    ///
+    /// let mut out = b"error: ".to_vec();
+    /// message.render_at(20, 7, 7, &mut out).unwrap();
+    /// assert_eq!(
+    ///     String::from_utf8(out).unwrap(),
+    ///     "error: the\n       value\n       does\n       not\n       fit",
+    /// );
+    /// ```
     #[inline]
-    pub fn render_fmt<W>(&self, width: usize, out: &mut W) -> fmt::Result
+    pub fn render_at<W>(
+        &self,
+        width: usize,
+        column: usize,
+        indent: usize,
+        out: &mut W,
+    ) -> io::Result<()>
     where
-        W: ?Sized + fmt::Write,
+        W: ?Sized + io::Write,
     {
-        self.render_raw(width, &mut FmtWrite::new(out))
+        self.render_raw_at(
+            current_config_with_width(width),
+            column,
+            indent,
+            &mut IoWrite::new(out),
+        )
     }
 
-    /// Writes a rendered document to a `RenderAnnotated<A>` object.
+    /// Writes a rendered document to a `RenderAnnotated<A>` object using the widths of `config`,
+    /// laid out as if it started at `column` with `indent` as the indentation of its line breaks,
+    /// see [`render_at`](#method.render_at).
     #[inline]
-    pub fn render_raw<W>(&self, width: usize, out: &mut W) -> Result<(), W::Error>
+    pub fn render_raw_at<W>(
+        &self,
+        config: RenderConfig,
+        column: usize,
+        indent: usize,
+        out: &mut W,
+    ) -> Result<(), W::Error>
     where
         W: ?Sized + render::RenderAnnotated<A>,
     {
-        render::best(self, width, out)
+        render::best_at(self, config, None, (column, indent), out)
     }
 
-    /// Returns a value which implements `std::fmt::Display`
+    /// Writes a rendered document to a `std::io::Write` object with the
+    /// [`placeholder`](trait.DocAllocator.html#method.placeholder) documents filled in from
+    /// `vars`.
+    #[inline]
+    pub fn render_with<W, I, K, V>(&self, width: usize, vars: I, out: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + io::Write,
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        with_vars(vars, || self.render(width, out))
+    }
+
+    /// Writes a rendered document to a `std::io::Write` object using `encoding`, starting with
+    /// the byte order mark of the encoding if it has one.
     ///
     /// ```
-    /// use pretty::{Doc, BoxDoc};
-    /// let doc = BoxDoc::<()>::group(
-    ///     BoxDoc::text("hello").append(Doc::line()).append(Doc::text("world"))
-    /// );
-    /// assert_eq!(format!("{}", doc.pretty(80)), "hello world");
+    /// use pretty::{BoxDoc, Encoding};
+    ///
+    /// let doc = BoxDoc::<()>::text("Grüße €5");
+    /// let mut bytes = Vec::new();
+    /// doc.render_encoded(80, Encoding::Latin1, &mut bytes).unwrap();
+    /// assert_eq!(bytes, b"Gr\xFC\xDFe ?5");
     /// ```
     #[inline]
-    pub fn pretty<'d>(&'d self, width: usize) -> Pretty<'a, 'd, T, A> {
-        Pretty { doc: self, width }
+    pub fn render_encoded<W>(&self, width: usize, encoding: Encoding, out: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + io::Write,
+    {
+        out.write_all(encoding.bom())?;
+        self.render_raw(width, &mut EncodedWrite::new(out, encoding))
     }
-}
 
-#[cfg(feature = "termcolor")]
-impl<'a, T> Doc<'a, T, ColorSpec>
-where
-    T: DocPtr<'a, ColorSpec> + 'a,
-{
+    /// Writes a rendered document to a `std::fmt::Write` object.
     #[inline]
-    pub fn render_colored<W>(&self, width: usize, out: W) -> io::Result<()>
+    pub fn render_fmt<W>(&self, width: usize, out: &mut W) -> fmt::Result
     where
-        W: WriteColor,
+        W: ?Sized + fmt::Write,
     {
-        render::best(self, width, &mut TermColored::new(out))
+        self.render_raw(width, &mut FmtWrite::new(out))
     }
-}
 
-/// The `DocBuilder` type allows for convenient appending of documents even for arena allocated
-/// documents by storing the arena inline.
-pub struct DocBuilder<'a, D, A = ()>(pub &'a D, pub BuildDoc<'a, D::Doc, A>)
-where
-    D: ?Sized + DocAllocator<'a, A>;
+    /// Writes a rendered document into `buf` and returns the number of bytes written. If the
+    /// document does not fit, as much of it as fits is written and `Overflow` is returned. The
+    /// output is written to `buf` directly without an intermediate buffer, though laying out the
+    /// document still allocates.
+    ///
+    /// ```
+    /// use pretty::{BoxDoc, Overflow};
+    ///
+    /// let doc = BoxDoc::<()>::text("hello").append(BoxDoc::line()).append("wörld").group();
+    /// let mut buf = [0; 16];
+    /// assert_eq!(doc.render_into(80, &mut buf), Ok(12));
+    /// assert_eq!(&buf[..12], "hello wörld".as_bytes());
+    ///
+    /// let mut buf = [0; 8];
+    /// assert_eq!(doc.render_into(80, &mut buf), Err(Overflow { written: 7 }));
+    /// assert_eq!(&buf[..7], b"hello w");
+    /// ```
+    #[inline]
+    pub fn render_into(&self, width: usize, buf: &mut [u8]) -> Result<usize, Overflow> {
+        let mut out = render::SliceWrite::new(buf);
+        self.render_raw(width, &mut out)?;
+        Ok(out.len())
+    }
 
-impl<'a, A, D> Clone for DocBuilder<'a, D, A>
-where
-    A: Clone,
-    D: DocAllocator<'a, A> + 'a,
-    D::Doc: Clone,
-{
-    fn clone(&self) -> Self {
-        DocBuilder(self.0, self.1.clone())
+    /// Renders the document into `hasher` and returns the hash of the output, see
+    /// [`Hashed`](struct.Hashed.html).
+    #[inline]
+    pub fn render_hash<H>(&self, width: usize, hasher: H) -> u64
+    where
+        H: Hasher,
+    {
+        let mut out = Hashed::new(IoWrite::new(io::sink()), hasher);
+        self.render_raw(width, &mut out)
+            .expect("writing to a sink does not fail");
+        out.into_inner().1.finish()
     }
-}
 
-impl<'a, D, A> Into<BuildDoc<'a, D::Doc, A>> for DocBuilder<'a, D, A>
-where
-    D: ?Sized + DocAllocator<'a, A>,
-{
-    fn into(self) -> BuildDoc<'a, D::Doc, A> {
-        self.1
+    /// Returns the text of the document as if every group were flattened, with `hardline`s
+    /// replaced by spaces. This is the text of [`iter_text`](#method.iter_text) concatenated.
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// let doc = arena
+    ///     .text("fn")
+    ///     .append(arena.line())
+    ///     .append("main()")
+    ///     .append(arena.line_())
+    ///     .append(arena.hardline())
+    ///     .append("{}")
+    ///     .nest(4);
+    /// assert_eq!(doc.1.collect_text(), "fn main() {}");
+    /// ```
+    pub fn collect_text(&self) -> String {
+        self.iter_text().collect()
     }
-}
+
+    /// Iterates over the text of the document in order, as if every group were flattened.
+    /// `flat_alt`s contribute their flat alternative and `hardline`s produce a space, so both a
+    /// `line` and a `hardline` produce a space while a `line_` produces nothing. A `flat_alt` whose
+    /// flat alternative is a `hardline`, such as `break_parent`, contributes its other
+    /// alternative as it can never be flattened. Nesting is ignored
+    /// and `union`s contribute their first alternative. `column` documents are produced for the
+    /// column they would be at on that single line, measured like the layout measures text, and
+    /// `nesting` documents for nesting 0. Empty text is skipped. The document is traversed with an
+    /// explicit stack so it may be arbitrarily deep.
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// let doc = arena
+    ///     .text("let")
+    ///     .append(arena.line())
+    ///     .append(arena.text("x").annotate(()))
+    ///     .append(arena.line().append("= 1;").nest(4))
+    ///     .group();
+    /// let tokens: Vec<_> = doc.1.iter_text().filter(|s| s.trim() != "").collect();
+    /// assert_eq!(tokens, ["let", "x", "= 1;"]);
+    /// assert_eq!(doc.1.iter_text().map(|s| s.len()).sum::<usize>(), 10);
+    /// ```
+    pub fn iter_text<'d>(&'d self) -> TextIter<'a, 'd, T, A> {
+        TextIter {
+            stack: vec![TextFrame::Doc(self)],
+            column: 0,
+        }
+    }
+
+    /// Returns `true` if the document produces no output in any layout.
+    ///
+    /// Documents built with `column` or `nesting` are never considered empty as their content
+    /// depends on the layout.
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// assert!(arena.nil().append("").group().nest(2).1.is_empty());
+    /// assert!(!arena.line_().1.is_empty());
+    /// assert!(!arena.text("pub").1.is_empty());
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        let mut stack = vec![self];
+        while let Some(doc) = stack.pop() {
+            match *doc {
+                Doc::Nil | Doc::AlignmentMark => (),
+                Doc::Append(ref l, ref r)
+                | Doc::FlatAlt(ref l, ref r)
+                | Doc::Union(ref l, ref r) => {
+                    stack.push(r);
+                    stack.push(l);
+                }
+                Doc::Group(ref doc)
+                | Doc::BiasedGroup(_, ref doc)
+                | Doc::Nest(_, ref doc)
+                | Doc::MaxWidth(_, ref doc)
+                | Doc::Unbounded(ref doc)
+                | Doc::AlignmentGroup(ref doc)
+                | Doc::Delimiter(ref doc)
+                | Doc::Annotated(_, ref doc) => stack.push(doc),
+                Doc::OwnedText(ref s) if s.is_empty() => (),
+                Doc::BorrowedText("") => (),
+                Doc::SmallText(ref s) if s.is_empty() => (),
+                Doc::Line
+                | Doc::OwnedText(_)
+                | Doc::BorrowedText(_)
+                | Doc::SmallText(_)
+                | Doc::Column(_)
+                | Doc::Nesting(_) => return false,
+            }
+        }
+        true
+    }
+
+    /// Checks that the parentheses, brackets and braces inserted by
+    /// [`enclose`](struct.DocBuilder.html#method.enclose) and the helpers built on it, such as
+    /// `parens` and `brackets`, are balanced and that no `nest` moves the indentation left of
+    /// column 0. The delimiters are checked in the order of `collect_text`, and `column` and
+    /// `nesting` documents are skipped. Delimiters in any other text, such as the content of a
+    /// string literal, are ignored.
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// let args = arena.text("a").append(",").append(arena.line()).append("b");
+    /// let call = arena.text("f").append(args.clone().parens().group());
+    /// assert!(call.1.validate().is_ok());
+    ///
+    /// let broken = arena.text("f").append(args.enclose("(", "]").nest(-2));
+    /// let error = broken.1.validate().unwrap_err();
+    /// assert_eq!(error.message, "nesting dedents 2 columns past the left margin");
+    /// assert_eq!(error.path, "root > Append.1 > Nest(-2)");
+    /// ```
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        validate::validate(self)
+    }
+
+    /// Panics with the error of [`validate`](#method.validate) if the document is invalid. Does
+    /// nothing unless debug assertions are enabled.
+    #[track_caller]
+    pub fn debug_validate(&self) {
+        #[cfg(debug_assertions)]
+        if let Err(error) = self.validate() {
+            panic!("invalid document: {}", error);
+        }
+    }
+
+    /// Returns `true` if the whole document can be laid out on a single line of at most `width`
+    /// columns, as if every group were flattened. No output is produced, so this can be used to
+    /// pick between entirely different documents before rendering.
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// let doc = arena.text("hello").append(arena.line()).append("world").group();
+    /// assert!(doc.1.fits(11));
+    /// assert!(!doc.1.fits(10));
+    /// assert!(!arena.text("a").append(arena.hardline()).1.fits(80));
+    /// ```
+    #[inline]
+    pub fn fits(&self, width: usize) -> bool {
+        render::fits(self, width)
+    }
+
+    /// Writes a rendered document to a `RenderAnnotated<A>` object.
+    #[inline]
+    pub fn render_raw<W>(&self, width: usize, out: &mut W) -> Result<(), W::Error>
+    where
+        W: ?Sized + render::RenderAnnotated<A>,
+    {
+        self.render_raw_with(current_config_with_width(width), out)
+    }
+
+    /// Writes a rendered document to a `RenderAnnotated<A>` object using the widths of
+    /// `config`.
+    ///
+    /// ```
+    /// use pretty::{BoxDoc, FmtWrite, RenderConfig};
+    ///
+    /// let call = BoxDoc::<()>::text("f(")
+    ///     .append(BoxDoc::line_().append("argument").nest(4))
+    ///     .append(BoxDoc::line_())
+    ///     .append(")")
+    ///     .group()
+    ///     .append(";");
+    ///
+    /// let mut s = String::new();
+    /// call.render_raw_with(RenderConfig::new(11), &mut FmtWrite::new(&mut s)).unwrap();
+    /// assert_eq!(s, "f(\n    argument\n);");
+    ///
+    /// // The trailing `;` may extend past the preferred width
+    /// let mut s = String::new();
+    /// let config = RenderConfig::new(11).with_max_width(12);
+    /// call.render_raw_with(config, &mut FmtWrite::new(&mut s)).unwrap();
+    /// assert_eq!(s, "f(argument);");
+    /// ```
+    #[inline]
+    pub fn render_raw_with<W>(&self, config: RenderConfig, out: &mut W) -> Result<(), W::Error>
+    where
+        W: ?Sized + render::RenderAnnotated<A>,
+    {
+        render::best(self, config, None, out)
+    }
+
+    /// Writes a rendered document to a `RenderAnnotated<A>` object, choosing between the
+    /// alternatives of each `union` by their `cost` instead of picking the first one which
+    /// fits.
+    ///
+    /// ```
+    /// use pretty::{Cost, FmtWrite, RcDoc, RenderConfig};
+    ///
+    /// #[derive(Clone, PartialEq)]
+    /// enum Tag {
+    ///     Assign,
+    /// }
+    ///
+    /// // Breaking after `=` is more expensive than breaking inside of the arguments
+    /// struct Style;
+    ///
+    /// impl Cost<Tag> for Style {
+    ///     fn line_break(&self, tag: Option<&Tag>) -> u64 {
+    ///         if tag == Some(&Tag::Assign) { 10 } else { 1 }
+    ///     }
+    /// }
+    ///
+    /// let call = RcDoc::text("f(")
+    ///     .append(RcDoc::line_().append("arg").nest(4))
+    ///     .append(RcDoc::line_())
+    ///     .append(")")
+    ///     .group();
+    /// let same_line = RcDoc::text(" ").append(call.clone());
+    /// let next_line = RcDoc::line().annotate(Tag::Assign).append(call).nest(2);
+    /// let doc = RcDoc::text("x =").append(same_line.union(next_line));
+    ///
+    /// // The default layout picks the first alternative which fits
+    /// assert_eq!(doc.pretty(9).to_string(), "x =\n  f(arg)");
+    ///
+    /// let mut s = String::new();
+    /// doc.render_raw_with_cost(RenderConfig::new(9), &Style, &mut FmtWrite::new(&mut s))
+    ///     .unwrap();
+    /// assert_eq!(s, "x = f(\n    arg\n)");
+    /// ```
+    #[inline]
+    pub fn render_raw_with_cost<W>(
+        &self,
+        config: RenderConfig,
+        cost: &dyn render::Cost<A>,
+        out: &mut W,
+    ) -> Result<(), W::Error>
+    where
+        W: ?Sized + render::RenderAnnotated<A>,
+    {
+        render::best(self, config, Some(cost), out)
+    }
+
+    /// Writes a rendered document to a `RenderAnnotated<A>` object, aborting with an error once
+    /// any of `limits` is exceeded. This bounds the resources used to render documents built from
+    /// untrusted input.
+    ///
+    /// ```
+    /// use pretty::{FmtWrite, Limit, LimitError, Limits, RcDoc, RenderConfig};
+    ///
+    /// // A deeply nested array from an untrusted JSON file
+    /// let mut doc = RcDoc::<()>::text("0");
+    /// for _ in 0..1000 {
+    ///     doc = RcDoc::text("[")
+    ///         .append(RcDoc::line_().append(doc).nest(2))
+    ///         .append(RcDoc::line_())
+    ///         .append("]")
+    ///         .group();
+    /// }
+    ///
+    /// let mut s = String::new();
+    /// let limits = Limits::new().with_max_nodes(10_000);
+    /// let result = doc.render_raw_limited(RenderConfig::new(80), limits, &mut FmtWrite::new(&mut s));
+    /// assert_eq!(result, Err(LimitError::Exceeded(Limit::Nodes)));
+    ///
+    /// let mut s = String::new();
+    /// let limits = Limits::new().with_max_bytes(1 << 20);
+    /// let result = doc.render_raw_limited(RenderConfig::new(80), limits, &mut FmtWrite::new(&mut s));
+    /// assert_eq!(result, Err(LimitError::Exceeded(Limit::Bytes)));
+    /// assert!(s.len() <= 1 << 20);
+    /// ```
+    #[inline]
+    pub fn render_raw_limited<W>(
+        &self,
+        config: RenderConfig,
+        limits: Limits,
+        out: &mut W,
+    ) -> Result<(), LimitError<W::Error>>
+    where
+        W: ?Sized + render::RenderAnnotated<A>,
+    {
+        render::best_limited(self, config, limits, out)
+    }
+
+    /// Returns a value which implements `std::fmt::Display`
+    ///
+    /// ```
+    /// use pretty::{Doc, BoxDoc};
+    /// let doc = BoxDoc::<()>::group(
+    ///     BoxDoc::text("hello").append(Doc::line()).append(Doc::text("world"))
+    /// );
+    /// assert_eq!(format!("{}", doc.pretty(80)), "hello world");
+    /// ```
+    #[inline]
+    pub fn pretty<'d>(&'d self, width: usize) -> Pretty<'a, 'd, T, A> {
+        self.pretty_with(current_config_with_width(width))
+    }
+
+    /// Returns a value which implements `std::fmt::Display` using the widths of `config`.
+    #[inline]
+    pub fn pretty_with<'d>(&'d self, config: RenderConfig) -> Pretty<'a, 'd, T, A> {
+        Pretty { doc: self, config }
+    }
+
+    /// Renders the document at each of `widths` in turn, for choosing a layout once the available
+    /// space is known. The widths are only rendered as the iterator reaches them.
+    ///
+    /// ```
+    /// use pretty::RcDoc;
+    ///
+    /// let doc = RcDoc::<()>::intersperse(["alpha", "beta", "gamma"], RcDoc::line()).group();
+    /// let layouts: Vec<_> = doc.render_widths(&[10, 20]).collect();
+    /// assert_eq!(
+    ///     layouts,
+    ///     [(10, "alpha\nbeta\ngamma".to_string()), (20, "alpha beta gamma".to_string())],
+    /// );
+    /// ```
+    pub fn render_widths<'d>(&'d self, widths: &'d [usize]) -> RenderWidths<'a, 'd, T, A> {
+        RenderWidths {
+            doc: self,
+            widths: widths.iter(),
+        }
+    }
+
+    /// Renders the document at each of `widths` in turn and returns the first width, along with
+    /// its output, which `accept` returns `true` for. Returns `None` if no output is accepted.
+    ///
+    /// ```
+    /// use pretty::RcDoc;
+    ///
+    /// let doc = RcDoc::<()>::intersperse(["alpha", "beta", "gamma"], RcDoc::line()).group();
+    /// // The narrowest panel which shows the document on a single line
+    /// assert_eq!(
+    ///     doc.render_best_of(&[10, 20, 40], |out| out.lines().count() <= 1),
+    ///     Some((20, "alpha beta gamma".to_string())),
+    /// );
+    /// assert_eq!(doc.render_best_of(&[5, 10], |out| out.lines().count() <= 1), None);
+    /// ```
+    pub fn render_best_of(
+        &self,
+        widths: &[usize],
+        mut accept: impl FnMut(&str) -> bool,
+    ) -> Option<(usize, String)> {
+        self.render_widths(widths).find(|(_, out)| accept(out))
+    }
+
+    /// Deep copies this document into `allocator`, which may be of a different kind than the one
+    /// this document was built with.
+    ///
+    /// `column` and `nesting` functions are wrapped so that the documents they return are copied
+    /// into `allocator` as well. Documents of any depth can be copied without overflowing the stack.
+    ///
+    /// ```
+    /// use pretty::{Arena, BoxDoc, DocAllocator};
+    ///
+    /// let cached = BoxDoc::<()>::text("cached").append(BoxDoc::line()).append("fragment");
+    ///
+    /// let arena = Arena::new();
+    /// let doc = arena.text("[").append(cached.clone_into_arena(&arena)).append("]").group();
+    /// assert_eq!(doc.1.pretty(80).to_string(), "[cached fragment]");
+    /// ```
+    pub fn clone_into_arena<'b, D>(&self, allocator: &'b D) -> DocBuilder<'b, D, A>
+    where
+        'a: 'b,
+        D: ?Sized + DocAllocator<'b, A>,
+        A: Clone,
+    {
+        let f = deep::Mapper {
+            text: None,
+            annotation: Rc::new(A::clone),
+        };
+        DocBuilder(allocator, BuildDoc::DocPtr(deep::map(self, allocator, &f)))
+    }
+
+    /// Copies this document into `allocator` with `f` applied to the text of every text document,
+    /// for transformations such as case conversion or normalization which should happen before
+    /// the layout is decided. The text returned by `column` and `nesting` functions is transformed
+    /// as well. Documents of any depth can be transformed without overflowing the stack.
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// let doc = arena.text("select").append(arena.line()).append("*").group();
+    /// let upper = doc.1.map_text(&arena, |s| s.to_uppercase());
+    /// assert_eq!(upper.1.pretty(80).to_string(), "SELECT *");
+    /// ```
+    pub fn map_text<'b, D, F>(&self, allocator: &'b D, f: F) -> DocBuilder<'b, D, A>
+    where
+        'a: 'b,
+        D: ?Sized + DocAllocator<'b, A>,
+        A: Clone + 'b,
+        F: Fn(&str) -> String + 'b,
+    {
+        let f = deep::Mapper {
+            text: Some(Rc::new(f)),
+            annotation: Rc::new(A::clone),
+        };
+        DocBuilder(allocator, BuildDoc::DocPtr(deep::map(self, allocator, &f)))
+    }
+}
+
+#[cfg(feature = "termcolor")]
+impl<'a, T> Doc<'a, T, ColorSpec>
+where
+    T: DocPtr<'a, ColorSpec> + 'a,
+{
+    #[inline]
+    pub fn render_colored<W>(&self, width: usize, out: W) -> io::Result<()>
+    where
+        W: WriteColor,
+    {
+        render::best(
+            self,
+            current_config_with_width(width),
+            None,
+            &mut TermColored::new(out),
+        )
+    }
+}
+
+/// The `DocBuilder` type allows for convenient appending of documents even for arena allocated
+/// documents by storing the arena inline.
+pub struct DocBuilder<'a, D, A = ()>(pub &'a D, pub BuildDoc<'a, D::Doc, A>)
+where
+    D: ?Sized + DocAllocator<'a, A>;
+
+impl<'a, A, D> Clone for DocBuilder<'a, D, A>
+where
+    A: Clone,
+    D: DocAllocator<'a, A> + 'a,
+    D::Doc: Clone,
+{
+    fn clone(&self) -> Self {
+        DocBuilder(self.0, self.1.clone())
+    }
+}
+
+impl<'a, D, A> From<DocBuilder<'a, D, A>> for BuildDoc<'a, D::Doc, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+{
+    fn from(doc: DocBuilder<'a, D, A>) -> Self {
+        doc.1
+    }
+}
 
 pub trait DocPtr<'a, A>: Deref<Target = Doc<'a, Self, A>> + Sized
 where
@@ -651,6 +1622,14 @@ where
         DocBuilder(self, Doc::Line.into())
     }
 
+    /// A position which is padded to line up with the marks of the other lines of the enclosing
+    /// [`align_marks`](struct.DocBuilder.html#method.align_marks). Outside of it the mark takes
+    /// up no space.
+    #[inline]
+    fn alignment_mark(&'a self) -> DocBuilder<'a, Self, A> {
+        DocBuilder(self, Doc::AlignmentMark.into())
+    }
+
     #[inline]
     fn space(&'a self) -> DocBuilder<'a, Self, A> {
         self.text(" ")
@@ -687,6 +1666,48 @@ where
         self.hardline().flat_alt(self.nil())
     }
 
+    /// Acts like `line` but is replaced by `text` instead of a space if grouped on a single line
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// let doc = arena
+    ///     .intersperse(vec!["a = 1", "b = 2"], arena.line_with_flat_text("; "))
+    ///     .braces()
+    ///     .group();
+    /// assert_eq!(doc.1.pretty(80).to_string(), "{a = 1; b = 2}");
+    /// assert_eq!(doc.1.pretty(5).to_string(), "{a = 1\nb = 2}");
+    /// ```
+    #[inline]
+    fn line_with_flat_text<U: Into<Cow<'a, str>>>(&'a self, text: U) -> DocBuilder<'a, Self, A> {
+        self.hardline().flat_alt(self.text(text))
+    }
+
+    /// Acts like `line` but is replaced by the separator `sep` instead of a space if grouped on a
+    /// single line. Unlike `line_with_flat_text` the separator can be any document, such as an
+    /// annotated comma.
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// let sep = arena.text(",").append(arena.space());
+    /// let doc = arena
+    ///     .intersperse(vec!["a", "b", "c"], arena.space_or(sep))
+    ///     .brackets()
+    ///     .group();
+    /// assert_eq!(doc.1.pretty(80).to_string(), "[a, b, c]");
+    /// assert_eq!(doc.1.pretty(5).to_string(), "[a\nb\nc]");
+    /// ```
+    #[inline]
+    fn space_or<E>(&'a self, sep: E) -> DocBuilder<'a, Self, A>
+    where
+        E: Into<BuildDoc<'a, Self::Doc, A>>,
+    {
+        self.hardline().flat_alt(sep)
+    }
+
     /// A `softline` acts like `space` if the document fits the page, otherwise like `line`
     #[inline]
     fn softline(&'a self) -> DocBuilder<'a, Self, A> {
@@ -707,33 +1728,265 @@ where
         DocBuilder(self, Doc::as_string(data.to_string()).into())
     }
 
-    /// Allocate a document containing the given text.
-    ///
-    /// The given text must not contain line breaks.
+    /// Allocate a document containing `number` formatted by `formatter`, see the
+    /// [`number`](number/index.html) module.
     #[inline]
-    fn text<U: Into<Cow<'a, str>>>(&'a self, data: U) -> DocBuilder<'a, Self, A> {
-        DocBuilder(self, Doc::text(data).into())
+    fn number<F, U>(&'a self, formatter: &F, number: U) -> DocBuilder<'a, Self, A>
+    where
+        F: ?Sized + number::NumberFormatter,
+        U: fmt::Display,
+    {
+        self.text(formatter.format(&number.to_string()))
     }
 
-    /// Allocate a document concatenating the given documents.
+    /// Allocate a hex dump of `bytes` in the layout of `hexdump -C`, with an address gutter, the
+    /// bytes in hexadecimal and an ASCII panel. The widest of 32, 16, 8 or 4 bytes per row which
+    /// fits the page is used.
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// let doc = arena.hex_dump(b"Hello, World!\n");
+    /// assert_eq!(
+    ///     doc.1.pretty(80).to_string(),
+    ///     "00000000  48 65 6c 6c 6f 2c 20 57  6f 72 6c 64 21 0a        |Hello, World!.|",
+    /// );
+    /// assert_eq!(
+    ///     doc.1.pretty(50).to_string(),
+    ///     "00000000  48 65 6c 6c 6f 2c 20 57  |Hello, W|\n\
+    ///      00000008  6f 72 6c 64 21 0a        |orld!.|",
+    /// );
+    /// ```
+    fn hex_dump(&'a self, bytes: &[u8]) -> DocBuilder<'a, Self, A> {
+        let layout = |columns| {
+            hex::rows(bytes, columns)
+                .enumerate()
+                .fold(self.nil(), |doc, (i, row)| {
+                    let doc = if i == 0 {
+                        doc
+                    } else {
+                        doc.append(self.hardline())
+                    };
+                    doc.append(row)
+                })
+        };
+        let (last, rest) = hex::COLUMNS.split_last().unwrap();
+        rest.iter().rev().fold(layout(*last), |narrower, &columns| {
+            layout(columns).union(narrower)
+        })
+    }
+
+    /// Allocate a document containing the shortest text which parses back to `value`.
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// assert_eq!(arena.float(0.1 + 0.2).1.pretty(80).to_string(), "0.30000000000000004");
+    /// assert_eq!(arena.float(1e300).1.pretty(80).to_string(), "1e300");
+    /// ```
+    #[cfg(feature = "ryu")]
     #[inline]
-    fn concat<I>(&'a self, docs: I) -> DocBuilder<'a, Self, A>
-    where
-        I: IntoIterator,
-        I::Item: Into<BuildDoc<'a, Self::Doc, A>>,
-    {
-        docs.into_iter().fold(self.nil(), |a, b| a.append(b))
+    fn float(&'a self, value: f64) -> DocBuilder<'a, Self, A> {
+        DocBuilder(self, Doc::float(value).into())
     }
 
-    /// Allocate a document that intersperses the given separator `S` between the given documents
-    /// `[A, B, C, ..., Z]`, yielding `[A, S, B, S, C, S, ..., S, Z]`.
+    /// Allocate a document containing the size `bytes` in binary units, such as `1.5 KiB`.
     ///
-    /// Compare [the `intersperse` method from the `itertools` crate](https://docs.rs/itertools/0.5.9/itertools/trait.Itertools.html#method.intersperse).
+    /// ```
+    /// use pretty::{Arena, DocAllocator};
     ///
-    /// NOTE: The separator type, `S` may need to be cloned. Consider using cheaply cloneable ptr
-    /// like `RefDoc` or `RcDoc`
+    /// let arena = Arena::<()>::new();
+    /// assert_eq!(arena.human_bytes(1536).1.pretty(80).to_string(), "1.5 KiB");
+    /// ```
+    #[cfg(feature = "humanize")]
     #[inline]
-    fn intersperse<I, S>(&'a self, docs: I, separator: S) -> DocBuilder<'a, Self, A>
+    fn human_bytes(&'a self, bytes: u64) -> DocBuilder<'a, Self, A> {
+        DocBuilder(self, Doc::human_bytes(bytes).into())
+    }
+
+    /// Allocate a document containing the duration using at most two units, such as `1h 5m`,
+    /// `1.5s` or `250ms`.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use pretty::{Arena, DocAllocator};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// let doc = arena.human_duration(Duration::from_secs(3900));
+    /// assert_eq!(doc.1.pretty(80).to_string(), "1h 5m");
+    /// ```
+    #[cfg(feature = "humanize")]
+    #[inline]
+    fn human_duration(&'a self, duration: std::time::Duration) -> DocBuilder<'a, Self, A> {
+        DocBuilder(self, Doc::human_duration(duration).into())
+    }
+
+    /// Allocate a document containing the given text.
+    ///
+    /// The given text must not contain line breaks.
+    #[inline]
+    fn text<U: Into<Cow<'a, str>>>(&'a self, data: U) -> DocBuilder<'a, Self, A> {
+        DocBuilder(self, Doc::text(data).into())
+    }
+
+    /// Allocate a document containing the given text with the escapes of a JSON string literal
+    /// applied.
+    ///
+    /// Nothing is allocated if the text does not need to be escaped.
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// let doc = arena.text_escaped_json("say \"hi\"\n").double_quotes();
+    /// assert_eq!(doc.1.pretty(80).to_string(), r#""say \"hi\"\n""#);
+    /// ```
+    #[inline]
+    fn text_escaped_json<U: Into<Cow<'a, str>>>(&'a self, data: U) -> DocBuilder<'a, Self, A> {
+        self.text(escape::json(data.into()))
+    }
+
+    /// Allocate a document containing the given text with the escapes of HTML text applied.
+    ///
+    /// Nothing is allocated if the text does not need to be escaped.
+    #[inline]
+    fn text_escaped_html<U: Into<Cow<'a, str>>>(&'a self, data: U) -> DocBuilder<'a, Self, A> {
+        self.text(escape::html(data.into()))
+    }
+
+    /// Allocate a document containing the given text with the escapes of a Rust string literal
+    /// applied.
+    ///
+    /// Nothing is allocated if the text does not need to be escaped.
+    #[inline]
+    fn text_escaped_rust_str<U: Into<Cow<'a, str>>>(&'a self, data: U) -> DocBuilder<'a, Self, A> {
+        self.text(escape::rust_str(data.into()))
+    }
+
+    /// Allocate a string literal containing `s`, quoted and escaped according to `style`.
+    ///
+    /// If `style` splits literals, a literal which does not fit the line continues with another
+    /// literal on the next line, aligned with the first one.
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator, QuoteStyle};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// let doc = arena.string_literal("it's \"quoted\"\n", QuoteStyle::FEWEST);
+    /// assert_eq!(doc.1.pretty(80).to_string(), r#"'it\'s "quoted"\n'"#);
+    ///
+    /// let style = QuoteStyle::DOUBLE.with_split(" +");
+    /// let doc = arena
+    ///     .text("msg = ")
+    ///     .append(arena.string_literal("the quick brown fox jumps over the lazy dog", style));
+    /// assert_eq!(
+    ///     doc.1.pretty(32).to_string(),
+    ///     "msg = \"the quick brown fox \" +\n      \"jumps over the lazy dog\"",
+    /// );
+    /// ```
+    fn string_literal(&'a self, s: &str, style: QuoteStyle) -> DocBuilder<'a, Self, A>
+    where
+        Self: Sized,
+        Self::Doc: Clone,
+        A: Clone,
+    {
+        let (quote, pieces) = escape::string_literal(s, style);
+        let joiner = match style.split {
+            Some(joiner) => joiner,
+            None => return self.text(format!("{}{}{}", quote, pieces[0], quote)),
+        };
+        let mut doc = self.text(quote);
+        for (i, piece) in pieces.into_iter().enumerate() {
+            if i > 0 {
+                let split = self
+                    .text(quote)
+                    .append(joiner)
+                    .append(self.hardline())
+                    .append(quote);
+                doc = doc.append(split.flat_alt(self.nil()).group());
+            }
+            doc = doc.append(piece);
+        }
+        doc.append(quote).align()
+    }
+
+    /// Allocate a document containing the identifier `name` converted to `snake_case`.
+    ///
+    /// `name` may be written in `camelCase`, `PascalCase`, `snake_case`, `SCREAMING_SNAKE_CASE` or
+    /// `kebab-case`. A run of capitals is kept as one word, so `HTTPServer` has the words `HTTP`
+    /// and `Server`.
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// assert_eq!(arena.ident_snake("parseHTTPRequest").1.pretty(80).to_string(), "parse_http_request");
+    /// ```
+    #[inline]
+    fn ident_snake(&'a self, name: &str) -> DocBuilder<'a, Self, A> {
+        self.text(case::snake(name))
+    }
+
+    /// Allocate a document containing the identifier `name` converted to `camelCase`.
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// assert_eq!(arena.ident_camel("user_id").1.pretty(80).to_string(), "userId");
+    /// ```
+    #[inline]
+    fn ident_camel(&'a self, name: &str) -> DocBuilder<'a, Self, A> {
+        self.text(case::camel(name))
+    }
+
+    /// Allocate a document containing the identifier `name` converted to `PascalCase`.
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// assert_eq!(arena.ident_pascal("content-type").1.pretty(80).to_string(), "ContentType");
+    /// ```
+    #[inline]
+    fn ident_pascal(&'a self, name: &str) -> DocBuilder<'a, Self, A> {
+        self.text(case::pascal(name))
+    }
+
+    /// Allocate a document containing the identifier `name` converted to `SCREAMING_SNAKE_CASE`.
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// assert_eq!(arena.ident_screaming_snake("maxSize").1.pretty(80).to_string(), "MAX_SIZE");
+    /// ```
+    #[inline]
+    fn ident_screaming_snake(&'a self, name: &str) -> DocBuilder<'a, Self, A> {
+        self.text(case::screaming_snake(name))
+    }
+
+    /// Allocate a document concatenating the given documents.
+    #[inline]
+    fn concat<I>(&'a self, docs: I) -> DocBuilder<'a, Self, A>
+    where
+        I: IntoIterator,
+        I::Item: Into<BuildDoc<'a, Self::Doc, A>>,
+    {
+        docs.into_iter().fold(self.nil(), |a, b| a.append(b))
+    }
+
+    /// Allocate a document that intersperses the given separator `S` between the given documents
+    /// `[A, B, C, ..., Z]`, yielding `[A, S, B, S, C, S, ..., S, Z]`.
+    ///
+    /// Compare [the `intersperse` method from the `itertools` crate](https://docs.rs/itertools/0.5.9/itertools/trait.Itertools.html#method.intersperse).
+    ///
+    /// NOTE: The separator type, `S` may need to be cloned. Consider using cheaply cloneable ptr
+    /// like `RefDoc` or `RcDoc`
+    #[inline]
+    fn intersperse<I, S>(&'a self, docs: I, separator: S) -> DocBuilder<'a, Self, A>
     where
         I: IntoIterator,
         I::Item: Into<BuildDoc<'a, Self::Doc, A>>,
@@ -754,6 +2007,123 @@ where
         result
     }
 
+    /// Like `intersperse`, but documents which are [empty](enum.Doc.html#method.is_empty) are
+    /// skipped together with their separator. This allows optional fragments such as modifiers to
+    /// be joined without doubled or dangling separators.
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// let visibility = arena.nil();
+    /// let doc = arena.join_non_empty(
+    ///     vec![visibility, arena.text("fn"), arena.text("f()")],
+    ///     arena.space(),
+    /// );
+    /// assert_eq!(doc.1.pretty(80).to_string(), "fn f()");
+    /// ```
+    #[inline]
+    fn join_non_empty<I, S>(&'a self, docs: I, separator: S) -> DocBuilder<'a, Self, A>
+    where
+        I: IntoIterator,
+        I::Item: Into<BuildDoc<'a, Self::Doc, A>>,
+        S: Into<BuildDoc<'a, Self::Doc, A>> + Clone,
+    {
+        self.intersperse(
+            docs.into_iter()
+                .map(Into::into)
+                .filter(|doc: &BuildDoc<'a, Self::Doc, A>| !doc.is_empty()),
+            separator,
+        )
+    }
+
+    /// Lays out each row of cells on its own line, with the cells at the same index starting at
+    /// the same column in all rows, such as the `=` of consecutive bindings. The cells are
+    /// separated by [`alignment_mark`](#method.alignment_mark)s, see
+    /// [`align_marks`](struct.DocBuilder.html#method.align_marks).
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// let doc = arena.alignment_group(vec![
+    ///     vec!["let x", " = 1;"],
+    ///     vec!["let longer", " = 2;"],
+    /// ]);
+    /// assert_eq!(doc.1.pretty(80).to_string(), "let x      = 1;\nlet longer = 2;");
+    /// ```
+    fn alignment_group<I, R>(&'a self, rows: I) -> DocBuilder<'a, Self, A>
+    where
+        I: IntoIterator<Item = R>,
+        R: IntoIterator,
+        R::Item: Into<BuildDoc<'a, Self::Doc, A>>,
+    {
+        let mut doc = self.nil();
+        for (i, row) in rows.into_iter().enumerate() {
+            if i != 0 {
+                doc = doc.append(self.hardline());
+            }
+            for (j, cell) in row.into_iter().enumerate() {
+                if j != 0 {
+                    doc = doc.append(self.alignment_mark());
+                }
+                doc = doc.append(cell);
+            }
+        }
+        doc.align_marks()
+    }
+
+    /// Like `intersperse`, but if there are more than `n` documents every group enclosing the
+    /// result is broken regardless of the width.
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// let list = |items: Vec<&'static str>| {
+    ///     let items = arena.expand_if_more_than(2, items, arena.text(",").append(arena.line()));
+    ///     arena
+    ///         .text("[")
+    ///         .append(arena.line_().append(items).nest(2))
+    ///         .append(arena.line_())
+    ///         .append("]")
+    ///         .group()
+    /// };
+    /// assert_eq!(list(vec!["a", "b"]).1.pretty(80).to_string(), "[a, b]");
+    /// assert_eq!(
+    ///     list(vec!["a", "b", "c"]).1.pretty(80).to_string(),
+    ///     "[\n  a,\n  b,\n  c\n]",
+    /// );
+    /// ```
+    #[inline]
+    fn expand_if_more_than<I, S>(
+        &'a self,
+        n: usize,
+        docs: I,
+        separator: S,
+    ) -> DocBuilder<'a, Self, A>
+    where
+        I: IntoIterator,
+        I::Item: Into<BuildDoc<'a, Self::Doc, A>>,
+        S: Into<BuildDoc<'a, Self::Doc, A>> + Clone,
+    {
+        let mut count = 0;
+        let doc = self.intersperse(docs.into_iter().inspect(|_| count += 1), separator);
+        if count > n {
+            doc.append(self.break_parent())
+        } else {
+            doc
+        }
+    }
+
+    /// Allocate a document which produces no output but forces every group enclosing it to be
+    /// broken, as if it contained a `hardline`.
+    #[inline]
+    fn break_parent(&'a self) -> DocBuilder<'a, Self, A> {
+        // Groups are only flattened if their flat layout contains no line break
+        self.nil().flat_alt(self.hardline())
+    }
+
     /// Allocate a document that acts differently based on the position and page layout
     ///
     /// ```rust
@@ -788,6 +2158,243 @@ where
         DocBuilder(self, Doc::Nesting(self.alloc_column_fn(f)).into())
     }
 
+    /// Allocate a document which is only produced by calling `f` the first time the layout reaches
+    /// it. The produced document is cached so `f` is called at most once.
+    ///
+    /// The layout also reaches the document while looking ahead to decide whether an enclosing
+    /// group fits, so `f` may be called for a document which ends up on a later line. Only the
+    /// text up to the next possible line break or the end of the page is looked at, so documents
+    /// past a `hardline` or far enough into a line are not produced until they are laid out, and
+    /// are never produced if rendering stops early. `f` must not lay out the document itself,
+    /// which panics.
+    ///
+    /// NOTE: The doc pointer type, `D` may need to be cloned. Consider using cheaply cloneable ptr
+    /// like `RefDoc` or `RcDoc`
+    ///
+    /// ```rust
+    /// use std::cell::Cell;
+    /// use pretty::DocAllocator;
+    ///
+    /// let arena = pretty::Arena::<()>::new();
+    /// let calls = Cell::new(0);
+    /// let doc = arena.text("a").append(arena.lazy(|| {
+    ///     calls.set(calls.get() + 1);
+    ///     arena.text(" b").into_doc()
+    /// }));
+    /// assert_eq!(calls.get(), 0);
+    /// assert_eq!(doc.1.pretty(80).to_string(), "a b");
+    /// assert_eq!(doc.1.pretty(80).to_string(), "a b");
+    /// assert_eq!(calls.get(), 1);
+    /// ```
+    #[inline]
+    fn lazy(&'a self, f: impl FnOnce() -> Self::Doc + 'a) -> DocBuilder<'a, Self, A>
+    where
+        Self::Doc: Clone,
+    {
+        let f = Cell::new(Some(f));
+        let doc = RefCell::new(None::<Self::Doc>);
+        self.column(move |_| {
+            if let Some(ref doc) = *doc.borrow() {
+                return doc.clone();
+            }
+            // `f` is called without borrowing `doc` so that laying out the document from inside of
+            // `f` reports the cycle instead of a borrow error
+            let produced = (f.take().expect("lazy document is being evaluated"))();
+            *doc.borrow_mut() = Some(produced.clone());
+            produced
+        })
+    }
+
+    /// Allocate a document which is loaded by calling `loader` with `key` whenever the layout
+    /// reaches it, so that a large document can be described up front by the keys of its parts
+    /// while each part is only materialized as it is rendered.
+    ///
+    /// Unlike [`lazy`](#method.lazy) the loaded document is not kept, which lets it be dropped
+    /// once it has been laid out. `loader` may be called more than once for the same key, as the
+    /// layout looks ahead to decide whether a group fits, so an included part should usually be
+    /// preceded by a `hardline` or cached by the loader if it is expensive to produce. A single
+    /// loader can be shared by passing a reference to it.
+    ///
+    /// ```rust
+    /// use pretty::{Arena, DocAllocator};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// let load = |chapter: &usize| {
+    ///     arena
+    ///         .text(format!("Chapter {}", chapter))
+    ///         .append(arena.hardline())
+    ///         .into_doc()
+    /// };
+    /// let doc = arena.concat((1..=3).map(|chapter| arena.include(chapter, &load)));
+    /// assert_eq!(
+    ///     doc.1.pretty(80).to_string(),
+    ///     "Chapter 1\nChapter 2\nChapter 3\n",
+    /// );
+    /// ```
+    #[inline]
+    fn include<K>(
+        &'a self,
+        key: K,
+        loader: impl Fn(&K) -> Self::Doc + 'a,
+    ) -> DocBuilder<'a, Self, A>
+    where
+        K: 'a,
+    {
+        self.column(move |_| loader(&key))
+    }
+
+    /// Allocate a document whose text is the value of the variable `key` at the time it is
+    /// rendered, or nothing if the variable is not set. The variables are passed to
+    /// [`Doc::render_with`](enum.Doc.html#method.render_with) or set with
+    /// [`with_vars`](fn.with_vars.html), so that a document can be built once and rendered
+    /// repeatedly with small values such as timestamps or counters filled in.
+    ///
+    /// ```rust
+    /// use pretty::{Arena, DocAllocator};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// let status = arena
+    ///     .text("processed")
+    ///     .append(arena.line())
+    ///     .append(arena.placeholder("count"))
+    ///     .append(" files")
+    ///     .group();
+    ///
+    /// let mut out = Vec::new();
+    /// status.1.render_with(80, [("count", "3")], &mut out).unwrap();
+    /// assert_eq!(String::from_utf8(out).unwrap(), "processed 3 files");
+    ///
+    /// // The value takes part in the layout
+    /// let mut out = Vec::new();
+    /// status.1.render_with(18, [("count", "1024")], &mut out).unwrap();
+    /// assert_eq!(String::from_utf8(out).unwrap(), "processed\n1024 files");
+    /// ```
+    #[inline]
+    fn placeholder<K>(&'a self, key: K) -> DocBuilder<'a, Self, A>
+    where
+        K: Into<String>,
+    {
+        let key = key.into();
+        self.column(move |_| match config::var(&key) {
+            Some(value) => self.text(value).into_doc(),
+            None => self.nil().into_doc(),
+        })
+    }
+
+    /// Embeds `doc`, a document in another language with its own annotations of type `B`, such
+    /// as a script in an HTML page or a query in a string literal. The annotations of `doc` are
+    /// converted with `map` and the whole document is annotated with `tag`, so that a renderer
+    /// can tell the languages apart.
+    ///
+    /// `doc` is laid out independently of the surrounding document: its indentation is relative
+    /// to the column it starts at and its lines are limited to `width` columns from there, unless
+    /// the page ends first. Pass `usize::MAX` to only be limited by the page.
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator, RcDoc};
+    ///
+    /// #[derive(Clone, Debug, PartialEq)]
+    /// enum Html {
+    ///     Tag,
+    ///     Script,
+    ///     Js(&'static str),
+    /// }
+    ///
+    /// // The JavaScript formatter uses its own annotations
+    /// let js: RcDoc<&'static str> = RcDoc::text("if")
+    ///     .annotate("keyword")
+    ///     .append(" (ready) {")
+    ///     .append(RcDoc::line().append("start();").nest(2))
+    ///     .append(RcDoc::line())
+    ///     .append("}")
+    ///     .group();
+    ///
+    /// let arena = Arena::new();
+    /// let doc = arena
+    ///     .text("<script>")
+    ///     .annotate(Html::Tag)
+    ///     .append(arena.embedded(Html::Script, 20, &js, |ann| Html::Js(ann)))
+    ///     .append(arena.text("</script>").annotate(Html::Tag));
+    /// assert_eq!(
+    ///     doc.1.pretty(80).to_string(),
+    ///     "<script>if (ready) {\n          start();\n        }</script>",
+    /// );
+    /// ```
+    fn embedded<'b, T, B, F>(
+        &'a self,
+        tag: A,
+        width: usize,
+        doc: &Doc<'b, T, B>,
+        map: F,
+    ) -> DocBuilder<'a, Self, A>
+    where
+        'b: 'a,
+        Self: Sized,
+        Self::Doc: Clone,
+        T: DocPtr<'b, B>,
+        A: Clone,
+        F: Fn(&B) -> A + 'a,
+    {
+        let f = deep::Mapper {
+            text: None,
+            annotation: Rc::new(map),
+        };
+        DocBuilder(self, BuildDoc::DocPtr(deep::map(doc, self, &f)))
+            .max_width(width)
+            .align()
+            .annotate(tag)
+    }
+
+    /// Embeds `text` which has already been rendered, such as the output of another tool. Each
+    /// line is placed on its own line indented to the current nesting, which forces any group
+    /// containing a multi-line block to break. Blank lines are not indented.
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// let doc = arena
+    ///     .text("output:")
+    ///     .append(arena.hardline())
+    ///     .append(arena.rendered_block("line 1\n\n  line 3\n"))
+    ///     .nest(4);
+    /// assert_eq!(
+    ///     doc.1.pretty(80).to_string(),
+    ///     "output:\n    line 1\n\n      line 3",
+    /// );
+    /// ```
+    fn rendered_block<U: Into<Cow<'a, str>>>(&'a self, text: U) -> DocBuilder<'a, Self, A> {
+        fn block<'a, 's, D, A>(
+            allocator: &'a D,
+            lines: impl Iterator<Item = &'s str>,
+            text: impl Fn(&'s str) -> DocBuilder<'a, D, A>,
+        ) -> DocBuilder<'a, D, A>
+        where
+            D: ?Sized + DocAllocator<'a, A>,
+        {
+            let mut doc = allocator.nil();
+            for (i, line) in lines.enumerate() {
+                if i != 0 {
+                    doc = if line.is_empty() {
+                        // Undo the nesting so that no trailing whitespace is produced
+                        doc.append(allocator.nesting(move |nesting| {
+                            allocator.hardline().nest(-(nesting as isize)).into_doc()
+                        }))
+                    } else {
+                        doc.append(allocator.hardline())
+                    };
+                }
+                doc = doc.append(text(line));
+            }
+            doc
+        }
+
+        match text.into() {
+            Cow::Borrowed(text) => block(self, text.lines(), |line| self.text(line)),
+            Cow::Owned(text) => block(self, text.lines(), |line| self.text(line.to_string())),
+        }
+    }
+
     /// Reflows `text` inserting `softline` in place of any whitespace
     #[inline]
     fn reflow(&'a self, text: &'a str) -> DocBuilder<'a, Self, A>
@@ -860,7 +2467,7 @@ where
     }
 }
 
-impl<'a, 's, D, A> DocBuilder<'a, D, A>
+impl<'a, D, A> DocBuilder<'a, D, A>
 where
     D: ?Sized + DocAllocator<'a, A>,
 {
@@ -913,7 +2520,7 @@ where
         let that = that.into();
         DocBuilder(
             allocator,
-            Doc::FlatAlt(allocator.alloc_cow(this.into()), allocator.alloc_cow(that)).into(),
+            Doc::FlatAlt(allocator.alloc_cow(this), allocator.alloc_cow(that)).into(),
         )
     }
 
@@ -929,11 +2536,176 @@ where
         DocBuilder(allocator, Doc::Group(allocator.alloc_cow(this)).into())
     }
 
-    /// Increase the indentation level of this document.
-    #[inline]
-    pub fn nest(self, offset: isize) -> DocBuilder<'a, D, A> {
-        if let Doc::Nil = &*self.1 {
-            return self;
+    /// Mark this document as a group which prefers to be laid out on a single line.
+    ///
+    /// The group is flattened if it fits within `weight` columns past the width, so its line may
+    /// overflow the page by up to `weight` columns. If the page has a
+    /// [`max_width`](struct.RenderConfig.html#structfield.max_width) the group is never flattened
+    /// past it. When unions are chosen by [cost](struct.Doc.html#method.render_raw_with_cost),
+    /// laying the group out on several lines adds `weight` to the cost of the alternative
+    /// containing it.
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// let doc = arena.text("a").append(arena.line()).append("b");
+    /// assert_eq!(doc.clone().group().1.pretty(2).to_string(), "a\nb");
+    /// assert_eq!(doc.prefer_flat(1).1.pretty(2).to_string(), "a b");
+    /// ```
+    #[inline]
+    pub fn prefer_flat(self, weight: usize) -> DocBuilder<'a, D, A> {
+        let DocBuilder(allocator, this) = self;
+        let bias = weight.try_into().unwrap_or(isize::MAX);
+        DocBuilder(
+            allocator,
+            Doc::BiasedGroup(bias, allocator.alloc_cow(this)).into(),
+        )
+    }
+
+    /// Mark this document as a group which prefers to be laid out on several lines.
+    ///
+    /// The group is only flattened if it fits with `weight` columns to spare. When unions are
+    /// chosen by [cost](struct.Doc.html#method.render_raw_with_cost), flattening the group adds
+    /// `weight` to the cost of the alternative containing it.
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// let doc = arena.text("a").append(arena.line()).append("b");
+    /// assert_eq!(doc.clone().group().1.pretty(3).to_string(), "a b");
+    /// assert_eq!(doc.prefer_broken(1).1.pretty(3).to_string(), "a\nb");
+    /// ```
+    #[inline]
+    pub fn prefer_broken(self, weight: usize) -> DocBuilder<'a, D, A> {
+        let DocBuilder(allocator, this) = self;
+        let bias = weight
+            .try_into()
+            .map_or(isize::MIN, |weight: isize| -weight);
+        DocBuilder(
+            allocator,
+            Doc::BiasedGroup(bias, allocator.alloc_cow(this)).into(),
+        )
+    }
+
+    /// Lays out this document as if the page ended `width` columns after the column it starts
+    /// at, unless the page ends before that. This gives regions such as table cells or side
+    /// panels a tighter width than the rest of the document.
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// let words = arena.intersperse(
+    ///     ["a", "b", "c", "d"].iter().map(|s| arena.text(*s)),
+    ///     arena.softline(),
+    /// );
+    /// let doc = arena.text("> ").append(words.max_width(5).nest(2));
+    /// assert_eq!(doc.1.pretty(80).to_string(), "> a b c\n  d");
+    /// ```
+    #[inline]
+    pub fn max_width(self, width: usize) -> DocBuilder<'a, D, A> {
+        let DocBuilder(allocator, this) = self;
+        DocBuilder(
+            allocator,
+            Doc::MaxWidth(width, allocator.alloc_cow(this)).into(),
+        )
+    }
+
+    /// Lays out this document as if the page had no width limit, so that its groups are only
+    /// broken by hard line breaks. Useful for fragments which must never be wrapped, such as URLs
+    /// or one line JSON, even inside a narrow layout.
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// let json = arena.text("{").append(arena.line()).append("}").group();
+    /// let doc = arena
+    ///     .text("data:")
+    ///     .append(arena.line().append(json.unbounded()).nest(2))
+    ///     .group();
+    /// assert_eq!(doc.1.pretty(4).to_string(), "data:\n  { }");
+    /// ```
+    #[inline]
+    pub fn unbounded(self) -> DocBuilder<'a, D, A> {
+        let DocBuilder(allocator, this) = self;
+        DocBuilder(allocator, Doc::Unbounded(allocator.alloc_cow(this)).into())
+    }
+
+    /// Pads the [`alignment_mark`](trait.DocAllocator.html#method.alignment_mark)s of this
+    /// document so that the first mark on each of its lines starts at the same column, and so on
+    /// for the second mark and the marks after it. The marks of nested `align_marks` are aligned
+    /// separately.
+    ///
+    /// The columns are found while rendering, by laying out the document once for every mark on
+    /// its longest line, so the cells between the marks may break or start at any column. Groups
+    /// inside the document decide whether to flatten with the marks they contain padded.
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// let binding = |name: &'static str, value: &'static str| {
+    ///     arena.text(name).append(arena.alignment_mark()).append(" = ").append(value)
+    /// };
+    /// let doc = arena.text("let ").append(
+    ///     binding("x", "1")
+    ///         .append(arena.hardline())
+    ///         .append(binding("longer", "2"))
+    ///         .align_marks()
+    ///         .align(),
+    /// );
+    /// assert_eq!(doc.1.pretty(80).to_string(), "let x      = 1\n    longer = 2");
+    /// ```
+    #[inline]
+    pub fn align_marks(self) -> DocBuilder<'a, D, A> {
+        let DocBuilder(allocator, this) = self;
+        DocBuilder(
+            allocator,
+            Doc::AlignmentGroup(allocator.alloc_cow(this)).into(),
+        )
+    }
+
+    /// Lays out this document flattened on a single line if it fits in the rest of the line,
+    /// otherwise only `marker` is laid out. As with `union`, the content following the document
+    /// up to the next possible line break has to fit as well. A document which can not be
+    /// flattened because it contains a `hardline` is always replaced by `marker`.
+    ///
+    /// Useful for single line summaries, such as the cells of a table, where breaking is not an
+    /// option.
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// let summary = |text: &'static str| {
+    ///     arena
+    ///         .text("| ")
+    ///         .append(arena.reflow(text).ellipsize("…"))
+    ///         .append(" |")
+    /// };
+    /// assert_eq!(summary("short text").1.pretty(16).to_string(), "| short text |");
+    /// assert_eq!(summary("a much longer text").1.pretty(16).to_string(), "| … |");
+    /// ```
+    #[inline]
+    pub fn ellipsize<E>(self, marker: E) -> DocBuilder<'a, D, A>
+    where
+        E: Into<BuildDoc<'a, D::Doc, A>>,
+    {
+        let DocBuilder(allocator, _) = self;
+        if render::flat_width(&self.1, 0).is_none() {
+            return DocBuilder(allocator, marker.into());
+        }
+        self.group().union(marker)
+    }
+
+    /// Increase the indentation level of this document.
+    #[inline]
+    pub fn nest(self, offset: isize) -> DocBuilder<'a, D, A> {
+        if let Doc::Nil = &*self.1 {
+            return self;
         }
         if offset == 0 {
             return self;
@@ -1087,7 +2859,8 @@ where
         })
     }
 
-    /// Puts `self` between `before` and `after`
+    /// Puts `self` between `before` and `after`. The delimiters are recorded so that
+    /// [`validate`](enum.Doc.html#method.validate) can check that they are balanced.
     #[inline]
     pub fn enclose<E, F>(self, before: E, after: F) -> DocBuilder<'a, D, A>
     where
@@ -1095,9 +2868,12 @@ where
         F: Into<BuildDoc<'a, D::Doc, A>>,
     {
         let DocBuilder(allocator, _) = self;
-        DocBuilder(allocator, before.into())
+        let delimiter = |doc: BuildDoc<'a, D::Doc, A>| {
+            DocBuilder(allocator, Doc::Delimiter(allocator.alloc_cow(doc)).into())
+        };
+        delimiter(before.into())
             .append(self)
-            .append(after)
+            .append(delimiter(after.into()))
     }
 
     pub fn single_quotes(self) -> DocBuilder<'a, D, A> {
@@ -1160,7 +2936,7 @@ impl<'a, A> Deref for RefDoc<'a, A> {
     type Target = Doc<'a, RefDoc<'a, A>, A>;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        self.0
     }
 }
 
@@ -1187,6 +2963,32 @@ impl<'a, A> Arena<'a, A> {
         }
     }
 
+    /// Calls `f` with a snapshot on top of this arena for building documents speculatively. Every
+    /// document allocated in the snapshot is freed, or rolled back, once `f` returns, except for
+    /// those moved into this arena with [`commit`](struct.Snapshot.html#method.commit).
+    pub fn snapshot<R>(&'a self, f: impl for<'s> FnOnce(&'s Snapshot<'a, 's, A>) -> R) -> R {
+        let snapshot = Box::new(Snapshot {
+            parent: self,
+            arena: Arena::new(),
+            adopted: Cell::new(false),
+        });
+        // The snapshot is borrowed through a pointer so that it can still be moved into this
+        // arena afterwards, which does not move the snapshot itself
+        let result = f(unsafe { &*(&*snapshot as *const Snapshot<'a, '_, A>) });
+        if snapshot.adopted.get() {
+            // Committed `column` and `nesting` functions may use the snapshot and its documents, so
+            // it is kept alive as long as this arena. See `alloc_any` for why the lifetime is cast
+            // away.
+            unsafe {
+                self.column_fns
+                    .alloc(std::mem::transmute::<Box<dyn DropT + '_>, Box<dyn DropT>>(
+                        snapshot,
+                    ));
+            }
+        }
+        result
+    }
+
     fn alloc_any<T>(&'a self, f: T) -> &'a T
     where
         T: 'a,
@@ -1272,10 +3074,130 @@ impl<'a, A> DocAllocator<'a, A> for Arena<'a, A> {
     }
 }
 
+/// A temporary arena for speculatively built documents, see
+/// [`Arena::snapshot`](struct.Arena.html#method.snapshot).
+///
+/// Since an `Arena` can not free individual documents, a builder which tries out layouts and
+/// abandons most of them would otherwise keep every abandoned document alive until the arena is
+/// dropped. Building in a snapshot instead keeps the memory bounded: everything allocated in the
+/// snapshot is freed at once when it is rolled back.
+///
+/// Documents of the parent arena are brought into the snapshot with
+/// [`clone_into_arena`](enum.Doc.html#method.clone_into_arena).
+///
+/// ```
+/// use pretty::{Arena, DocAllocator};
+///
+/// let arena = Arena::<()>::new();
+/// let items = ["alpha", "beta", "gamma"];
+///
+/// let chosen = [", ", ",", ""].iter().find_map(|sep| {
+///     arena.snapshot(|snapshot| {
+///         let attempt = snapshot.intersperse(items.iter().map(|s| snapshot.text(*s)), *sep);
+///         if attempt.1.pretty(80).to_string().len() <= 16 {
+///             Some(snapshot.commit(&attempt.1))
+///         } else {
+///             // `attempt` is freed when the closure returns
+///             None
+///         }
+///     })
+/// });
+/// assert_eq!(chosen.unwrap().1.pretty(80).to_string(), "alpha,beta,gamma");
+/// ```
+pub struct Snapshot<'a, 's, A = ()> {
+    parent: &'a Arena<'a, A>,
+    arena: Arena<'s, A>,
+    /// Set once a `column` or `nesting` function has been committed, which keeps the snapshot
+    /// from being rolled back
+    adopted: Cell<bool>,
+}
+
+impl<'a, 's, A> Snapshot<'a, 's, A> {
+    /// Copies `doc` into the parent arena so that it outlives the snapshot. Text borrowed for the
+    /// lifetime of the snapshot is copied as well. Documents which are shared are copied once and
+    /// stay shared, and documents of any depth can be copied without overflowing the stack.
+    ///
+    /// `column` and `nesting` functions (which are also used by `align`, `hang`, `indent` and
+    /// `width`) can not be copied as they may refer to the documents of the snapshot. If `doc`
+    /// contains any, nothing allocated in the snapshot is freed and it is kept alive as long as
+    /// the parent arena instead.
+    pub fn commit(&self, doc: &Doc<'s, RefDoc<'s, A>, A>) -> DocBuilder<'a, Arena<'a, A>, A>
+    where
+        A: Clone,
+    {
+        let committed = RefCell::new(std::collections::HashMap::new());
+        let key = |doc: &Doc<'s, RefDoc<'s, A>, A>| doc as *const Doc<'s, RefDoc<'s, A>, A>;
+        let leaf = |doc: &Doc<'s, RefDoc<'s, A>, A>| {
+            if let Some(&copy) = committed.borrow().get(&key(doc)) {
+                return Ok::<_, std::convert::Infallible>(Some(copy));
+            }
+            let copy = match *doc {
+                Doc::Nil => Doc::Nil,
+                Doc::Line => Doc::Line,
+                Doc::AlignmentMark => Doc::AlignmentMark,
+                Doc::OwnedText(ref s) => Doc::OwnedText(s.clone()),
+                Doc::BorrowedText(s) => Doc::OwnedText(s.into()),
+                Doc::SmallText(s) => Doc::SmallText(s),
+                Doc::Column(f) => {
+                    self.adopted.set(true);
+                    // SAFETY: The snapshot is kept alive as long as the parent arena once
+                    // `adopted` is set, and anything `f` borrows outlives `'a` since `'s` can be
+                    // any lifetime shorter than `'a` in `Arena::snapshot`
+                    Doc::Column(unsafe { adopt(f) })
+                }
+                Doc::Nesting(f) => {
+                    self.adopted.set(true);
+                    // SAFETY: See `Doc::Column` above
+                    Doc::Nesting(unsafe { adopt(f) })
+                }
+                _ => return Ok(None),
+            };
+            Ok(Some(self.parent.alloc(copy)))
+        };
+        let build = |doc: &Doc<'s, RefDoc<'s, A>, A>, children| {
+            let copy = self.parent.alloc(deep::rebuild(doc, children, A::clone));
+            committed.borrow_mut().insert(key(doc), copy);
+            copy
+        };
+        DocBuilder(
+            self.parent,
+            deep::infallible(deep::fold(doc, leaf, build)).into(),
+        )
+    }
+}
+
+/// Casts the lifetime of a function of a snapshot to that of the parent arena.
+unsafe fn adopt<'a, 's, A, Arg>(
+    f: &'s (dyn Fn(Arg) -> RefDoc<'s, A> + 's),
+) -> &'a (dyn Fn(Arg) -> RefDoc<'a, A> + 'a) {
+    std::mem::transmute(f)
+}
+
+impl<'a, 's, A> DocAllocator<'s, A> for Snapshot<'a, 's, A> {
+    type Doc = RefDoc<'s, A>;
+
+    #[inline]
+    fn alloc(&'s self, doc: Doc<'s, Self::Doc, A>) -> Self::Doc {
+        self.arena.alloc(doc)
+    }
+
+    fn alloc_column_fn(
+        &'s self,
+        f: impl Fn(usize) -> Self::Doc + 's,
+    ) -> <Self::Doc as DocPtr<'s, A>>::ColumnFn {
+        self.arena.alloc_column_fn(f)
+    }
+
+    fn alloc_width_fn(
+        &'s self,
+        f: impl Fn(isize) -> Self::Doc + 's,
+    ) -> <Self::Doc as DocPtr<'s, A>>::WidthFn {
+        self.arena.alloc_width_fn(f)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use difference;
-
     use super::*;
 
     #[cfg(target_pointer_width = "64")]
@@ -1470,7 +3392,7 @@ mod tests {
                 .append(BoxDoc::text("test")),
         );
 
-        test!(usize::max_value(), doc, "test test");
+        test!(usize::MAX, doc, "test test");
     }
 
     pub struct TestWriter<W> {
@@ -1520,4 +3442,2610 @@ mod tests {
             .unwrap();
         difference::assert_diff!(&s, "[[abc]]", "\n", 0);
     }
+
+    #[test]
+    fn layout_iter() {
+        let arena = Arena::new();
+        let doc = arena
+            .text("a")
+            .append(arena.line().append(arena.text("b").annotate(1)).nest(2))
+            .annotate(0)
+            .group();
+
+        let temp = LayoutArena::new();
+        let cmds: Vec<_> = LayoutIter::new(&temp, &doc.1, 2).collect();
+        assert_eq!(
+            cmds,
+            [
+                LayoutCmd::PushAnnotation(&0),
+                LayoutCmd::Text("a"),
+                LayoutCmd::Newline(2),
+                LayoutCmd::PushAnnotation(&1),
+                LayoutCmd::Text("b"),
+                LayoutCmd::PopAnnotation,
+                LayoutCmd::PopAnnotation,
+            ]
+        );
+    }
+
+    #[test]
+    fn token_sink_unbalanced() {
+        use crate::builder::{Token, TokenError, TokenSink};
+
+        let mut sink = TokenSink::<_, ()>::new(&BoxAllocator);
+        assert_eq!(sink.push(Token::Close), Err(TokenError::UnmatchedClose));
+
+        let mut sink = TokenSink::<_, ()>::new(&BoxAllocator);
+        sink.extend(vec![Token::Open(2), Token::Open(2), Token::Close])
+            .unwrap();
+        assert_eq!(sink.finish().err(), Some(TokenError::Unclosed(1)));
+    }
+
+    #[test]
+    fn owned_doc() {
+        let arena = Arena::new();
+        let doc = arena
+            .text("a")
+            .append(arena.line())
+            .append(arena.as_string(1).annotate(()))
+            .group();
+        let owned = doc.1.to_owned_doc().unwrap();
+        test!(1, owned, "a\n1");
+        test!(owned, "a 1");
+
+        assert!(doc.align().1.to_owned_doc().is_none());
+
+        let deep = (0..1_000_000).fold(BoxDoc::<i32>::text("x"), |doc, i| {
+            BoxDoc::text("y").append(doc).annotate(i)
+        });
+        let owned = deep.to_owned_doc().unwrap();
+        assert!(*owned.clone() == *owned);
+        assert_eq!(owned.pretty(80).to_string().len(), 1_000_001);
+    }
+
+    #[test]
+    fn clone_into_arena() {
+        let doc: RcDoc<i32> = RcDoc::text("a")
+            .append(RcDoc::line())
+            .append(RcDoc::text("b").annotate(1))
+            .group();
+        let doc = DocBuilder(&RcAllocator, doc.into()).align().1;
+
+        let arena = Arena::new();
+        let copy = arena.text("x ").append(doc.clone_into_arena(&arena));
+        test!(3, copy.1, "x a\n  b");
+
+        let deep = (0..1_000_000).fold(BoxDoc::<i32>::text("x"), |doc, i| {
+            BoxDoc::text("y").append(doc).annotate(i)
+        });
+        let copy = deep.clone_into_arena(&arena);
+        assert_eq!(copy.1.pretty(80).to_string().len(), 1_000_001);
+    }
+
+    #[test]
+    fn text_escaped() {
+        test!(
+            BoxDoc::<()>::text_escaped_json("a\u{1}\t\"é"),
+            "a\\u0001\\t\\\"é"
+        );
+        test!(
+            BoxDoc::<()>::text_escaped_html("<a href='x'>&</a>"),
+            "&lt;a href=&#39;x&#39;&gt;&amp;&lt;/a&gt;"
+        );
+        test!(
+            BoxDoc::<()>::text_escaped_rust_str("\\\0\u{7f}\n"),
+            "\\\\\\0\\u{7f}\\n"
+        );
+
+        match *BoxDoc::<()>::text_escaped_json("plain") {
+            Doc::BorrowedText("plain") => (),
+            ref doc => panic!("{:?}", doc),
+        }
+    }
+
+    #[test]
+    fn lazy_not_reached() {
+        let doc: RcDoc<()> = RcDoc::text("a").append(RcDoc::lazy(|| panic!("evaluated")));
+        let temp = LayoutArena::new();
+        let mut iter = LayoutIter::new(&temp, &doc, 80);
+        assert_eq!(iter.next(), Some(LayoutCmd::Text("a")));
+
+        let doc: RcDoc<()> = RcDoc::text("a")
+            .append(RcDoc::hardline())
+            .append(RcDoc::lazy(|| panic!("evaluated")))
+            .group();
+        let mut iter = LayoutIter::new(&temp, &doc, 80);
+        assert_eq!(iter.next(), Some(LayoutCmd::Text("a")));
+        assert_eq!(iter.next(), Some(LayoutCmd::Newline(0)));
+    }
+
+    #[test]
+    fn lazy_cycle() {
+        let slot = Cell::new(None);
+        let arena = Arena::<()>::new();
+        let doc = arena.lazy(|| {
+            let doc: RefDoc<()> = slot.get().unwrap();
+            doc.pretty(80).to_string();
+            arena.nil().into_doc()
+        });
+        slot.set(Some(doc.clone().into_doc()));
+
+        let panic = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            doc.1.pretty(80).to_string()
+        }))
+        .unwrap_err();
+        assert_eq!(
+            panic.downcast_ref::<String>().map(|s| &s[..]),
+            Some("lazy document is being evaluated")
+        );
+    }
+
+    #[test]
+    fn include() {
+        use std::cell::RefCell;
+
+        let loaded = RefCell::new(vec![]);
+        let loader = |part: &&str| {
+            loaded.borrow_mut().push(part.to_string());
+            RcDoc::text(part.to_uppercase()).append(RcDoc::hardline())
+        };
+        let doc: RcDoc<()> = RcDoc::text("report")
+            .append(RcDoc::hardline())
+            .append(RcDoc::include("intro", loader))
+            .append(RcDoc::include("body", loader));
+        assert!(loaded.borrow().is_empty());
+
+        let temp = LayoutArena::new();
+        let mut iter = LayoutIter::new(&temp, &doc, 80);
+        assert_eq!(iter.next(), Some(LayoutCmd::Text("report")));
+        assert!(loaded.borrow().is_empty());
+        let rest = iter.collect::<Vec<_>>();
+        assert!(rest.contains(&LayoutCmd::Text("BODY")));
+        assert_eq!(*loaded.borrow(), ["intro", "body"]);
+
+        test!(doc, "report\nINTRO\nBODY\n");
+    }
+
+    #[test]
+    fn render_best_of() {
+        let doc: RcDoc<()> = RcDoc::text("f(")
+            .append(
+                RcDoc::line_()
+                    .append(RcDoc::intersperse(
+                        ["x", "y"],
+                        RcDoc::text(",").append(RcDoc::line()),
+                    ))
+                    .nest(4),
+            )
+            .append(RcDoc::line_())
+            .append(")")
+            .group();
+        let widths = [4, 8, 80];
+        let layouts: Vec<_> = doc.render_widths(&widths).collect();
+        assert_eq!(layouts[0], (4, "f(\n    x,\n    y\n)".to_string()));
+        assert_eq!(layouts[1], (8, "f(x, y)".to_string()));
+        assert_eq!(layouts[2], (80, "f(x, y)".to_string()));
+
+        let mut tried = vec![];
+        let best = doc.render_best_of(&widths, |out| {
+            tried.push(out.len());
+            !out.contains('\n')
+        });
+        assert_eq!(best, Some((8, "f(x, y)".to_string())));
+        // Widths after the accepted one are not rendered
+        assert_eq!(tried.len(), 2);
+        assert_eq!(doc.render_best_of(&[], |_| true), None);
+    }
+
+    #[test]
+    fn render_limited() {
+        use std::time::Duration;
+
+        let doc = RcDoc::<()>::text("f(")
+            .append(
+                RcDoc::line_()
+                    .append("x,")
+                    .append(RcDoc::line())
+                    .append("y")
+                    .nest(4),
+            )
+            .append(RcDoc::line_())
+            .append(")")
+            .group();
+        let render = |config, limits| {
+            let mut s = String::new();
+            let result = doc.render_raw_limited(config, limits, &mut FmtWrite::new(&mut s));
+            (result, s)
+        };
+
+        let narrow = RenderConfig::new(4).with_indent(Indent::Tabs(4));
+        assert_eq!(
+            render(narrow, Limits::new()),
+            (Ok(()), "f(\n\tx,\n\ty\n)".into())
+        );
+        // The line breaks are counted with the indentation they are written with
+        assert_eq!(
+            render(narrow, Limits::new().with_max_bytes(11)),
+            (Ok(()), "f(\n\tx,\n\ty\n)".into())
+        );
+        assert_eq!(
+            render(narrow, Limits::new().with_max_bytes(10)),
+            (
+                Err(LimitError::Exceeded(Limit::Bytes)),
+                "f(\n\tx,\n\ty\n".into()
+            )
+        );
+
+        let wide = RenderConfig::new(80);
+        assert_eq!(render(wide, Limits::new().with_max_nodes(100)).0, Ok(()));
+        assert_eq!(
+            render(wide, Limits::new().with_max_nodes(3)),
+            (Err(LimitError::Exceeded(Limit::Nodes)), String::new())
+        );
+
+        let long = RcDoc::<()>::intersperse((0..10_000).map(|i| i.to_string()), RcDoc::line());
+        let mut s = String::new();
+        let limits = Limits::new().with_max_time(Duration::ZERO);
+        assert_eq!(
+            long.render_raw_limited(wide, limits, &mut FmtWrite::new(&mut s)),
+            Err(LimitError::Exceeded(Limit::Time))
+        );
+        assert!(s.len() < 10_000);
+
+        struct Failing;
+
+        impl Render for Failing {
+            type Error = fmt::Error;
+
+            fn write_str(&mut self, _: &str) -> Result<usize, fmt::Error> {
+                Err(fmt::Error)
+            }
+        }
+
+        impl RenderAnnotated<()> for Failing {
+            fn push_annotation(&mut self, _: &()) -> Result<(), fmt::Error> {
+                Ok(())
+            }
+
+            fn pop_annotation(&mut self) -> Result<(), fmt::Error> {
+                Ok(())
+            }
+        }
+
+        let error = doc.render_raw_limited(wide, Limits::new(), &mut Failing);
+        assert_eq!(error, Err(LimitError::Render(fmt::Error)));
+        assert_eq!(
+            LimitError::<fmt::Error>::Exceeded(Limit::Time).to_string(),
+            "rendering took too long"
+        );
+    }
+
+    #[test]
+    fn render_at() {
+        let arena = Arena::<()>::new();
+        let doc = arena
+            .text("f(")
+            .append(
+                arena
+                    .line_()
+                    .append("x,")
+                    .append(arena.line())
+                    .append("y")
+                    .nest(2),
+            )
+            .append(arena.line_())
+            .append(")")
+            .group();
+        let render = |width, column, indent| {
+            let mut out = Vec::new();
+            doc.1.render_at(width, column, indent, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(render(80, 0, 0), doc.1.pretty(80).to_string());
+        assert_eq!(render(12, 0, 0), "f(x, y)");
+        // Starting further right makes the group break, relative to the given indentation
+        assert_eq!(render(12, 6, 4), "f(\n      x,\n      y\n    )");
+
+        // Column and alignment see the actual start
+        let aligned = arena
+            .column(|col| arena.as_string(col).into_doc())
+            .append(arena.text(":").append(arena.hardline()).append("z").align());
+        let mut out = Vec::new();
+        aligned.1.render_at(80, 3, 0, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "3:\n    z");
+    }
+
+    #[test]
+    fn placeholder() {
+        let doc: RcDoc<()> = RcDoc::text("run")
+            .append(RcDoc::line().append(RcDoc::placeholder("n")).nest(2))
+            .append(RcDoc::line().append(RcDoc::placeholder("time")).nest(2))
+            .group();
+        // Without a value a placeholder is empty
+        test!(doc, "run  ");
+
+        let render = |width, n: &str| {
+            let mut out = Vec::new();
+            doc.render_with(width, [("n", n), ("time", "12:00")], &mut out)
+                .unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(render(80, "1"), "run 1 12:00");
+        assert_eq!(render(11, "10"), "run\n  10\n  12:00");
+        assert_eq!(render(80, "2"), "run 2 12:00");
+
+        // Nested variables shadow the outer ones until they are restored
+        let inner = with_vars([("n", "3")], || {
+            let inner = with_vars([("n", "4")], || doc.pretty(80).to_string());
+            assert_eq!(doc.pretty(80).to_string(), "run 3 ");
+            inner
+        });
+        assert_eq!(inner, "run 4 ");
+        test!(doc, "run  ");
+    }
+
+    #[test]
+    fn rust_literals() {
+        use crate::rust::{enum_variant, struct_lit, tuple_lit, Payload};
+
+        let arena = Arena::<()>::new();
+        let inner = tuple_lit(&arena, "Some", vec![arena.text("aaaa"), arena.text("bbbb")]);
+        let doc = struct_lit(
+            &arena,
+            "S",
+            vec![("field", inner), ("e", arena.text("E {}"))],
+        );
+        test!(doc.1, "S { field: Some(aaaa, bbbb), e: E {} }");
+        test!(
+            20,
+            doc.1,
+            "S {\n    field: Some(\n        aaaa,\n        bbbb,\n    ),\n    e: E {},\n}"
+        );
+
+        let empty = enum_variant(&arena, "E::A", Payload::Struct(vec![]));
+        test!(empty.1, "E::A {}");
+        let unit = enum_variant(&arena, "E::B", Payload::Unit);
+        test!(unit.1, "E::B");
+    }
+
+    #[test]
+    fn sql_subquery() {
+        use crate::sql::{clause, or, parens, query};
+
+        let arena = Arena::<()>::new();
+        let sub = query(
+            &arena,
+            vec![
+                clause(&arena, "SELECT", arena.text("id")),
+                clause(&arena, "FROM", arena.text("admins")),
+            ],
+        );
+        let cond = or(
+            &arena,
+            vec![
+                arena.text("owner_id IN ").append(parens(&arena, sub)),
+                arena.text("public"),
+            ],
+        );
+        let doc = query(
+            &arena,
+            vec![
+                clause(&arena, "SELECT", arena.text("*")),
+                clause(&arena, "FROM", arena.text("docs")),
+                clause(&arena, "WHERE", cond),
+            ],
+        );
+        test!(
+            30,
+            doc.1,
+            "SELECT *\nFROM docs\nWHERE\n  owner_id IN (\n    SELECT id FROM admins\n  )\n  OR public"
+        );
+    }
+
+    #[test]
+    fn yaml_nested() {
+        use crate::yaml::{block_scalar, mapping, scalar, sequence};
+
+        let arena = Arena::<()>::new();
+        let doc = sequence(
+            &arena,
+            vec![
+                mapping(
+                    &arena,
+                    vec![
+                        ("a", scalar(&arena, "- x: y")),
+                        ("b", mapping(&arena, Vec::<(&str, _)>::new())),
+                    ],
+                ),
+                sequence(&arena, vec![scalar(&arena, "1"), scalar(&arena, "")]),
+                block_scalar(&arena, "  x\n\ny\n\n"),
+            ],
+        );
+        // Never flattened, regardless of the width
+        test!(
+            usize::MAX,
+            doc.into_builder().1,
+            "- a: \"- x: y\"\n  b: {}\n- - \"1\"\n  - \"\"\n- |2+\n    x\n  \n  y\n  "
+        );
+    }
+
+    #[test]
+    fn dot_ids() {
+        use crate::dot::{edge, id};
+
+        let arena = Arena::<()>::new();
+        test!(id(&arena, "_a1").1, "_a1");
+        test!(id(&arena, "-1.5").1, "-1.5");
+        test!(id(&arena, "1a").1, "\"1a\"");
+        test!(id(&arena, ".5").1, ".5");
+        test!(id(&arena, "--1").1, "\"--1\"");
+        test!(id(&arena, "-").1, "\"-\"");
+        test!(id(&arena, "1.2.3").1, "\"1.2.3\"");
+        test!(id(&arena, "say \"hi\"").1, "\"say \\\"hi\\\"\"");
+        test!(id(&arena, r"C:\dir\").1, r#""C:\\dir\\""#);
+        test!(id(&arena, "node").1, "\"node\"");
+        test!(id(&arena, "Graph").1, "\"Graph\"");
+        test!(id(&arena, "STRICT").1, "\"STRICT\"");
+        test!(id(&arena, "nodes").1, "nodes");
+        test!(
+            edge(&arena, false, "a b", "c", vec![("color", "red")]).1,
+            "\"a b\" -- c [color=red];"
+        );
+    }
+
+    #[test]
+    fn deep_tree() {
+        use crate::tree::tree;
+
+        // A chain of nodes, each the only child of the one before it
+        let nodes: Vec<usize> = (0..5_000).collect();
+        let arena = Arena::<()>::new();
+        let doc = tree(
+            &arena,
+            &nodes[0],
+            &|n: &usize| n.to_string(),
+            &|n: &usize| nodes.get(&(n + 1)),
+        );
+
+        let mut expected = "0".to_string();
+        for n in 1..nodes.len() {
+            expected.push('\n');
+            expected.push_str(&"    ".repeat(n - 1));
+            expected.push_str(&format!("└── {}", n));
+        }
+        assert_eq!(doc.1.pretty(80).to_string(), expected);
+    }
+
+    #[test]
+    fn tree_glyph_width() {
+        use crate::tree::tree;
+
+        struct Node(&'static str, Vec<Node>);
+        let root = Node("root", vec![Node("a b", vec![Node("c d", vec![])])]);
+
+        // "└── " is counted as four columns rather than the ten bytes it takes up
+        let arena = Arena::<()>::new();
+        let doc = tree(&arena, &root, &|node: &Node| node.0, &|node: &Node| {
+            node.1.iter()
+        });
+        test!(11, doc.1, "root\n└── a b\n    └── c d");
+        let doc = tree(&arena, &root, &|node: &Node| node.0, &|node: &Node| {
+            node.1.iter()
+        });
+        test!(10, doc.1, "root\n└── a b\n    └── c\n        d");
+    }
+
+    #[cfg(feature = "diagnostics")]
+    #[test]
+    fn diagnostic_columns() {
+        use crate::diagnostics::{Diagnostic, Label, Severity};
+
+        // The secondary label starts inside of `é`
+        let source = "\tlet é = x;\n";
+        let diagnostic = Diagnostic::new(Severity::Error, "unknown")
+            .with_label(Label::secondary(6..7, "binding"))
+            .with_label(Label::primary(10..11, "here"));
+        let arena = Arena::<()>::new();
+        let doc = diagnostic.to_doc(&arena, "f", source, |_| ());
+        test!(
+            doc.1,
+            "error: unknown\n --> f:1:10\n  |\n1 |     let é = x;\n  |         - binding\n  |             ^ here"
+        );
+    }
+
+    #[cfg(feature = "diagnostics")]
+    #[test]
+    fn diagnostic_line_gap() {
+        use crate::diagnostics::{Diagnostic, Label, Severity};
+
+        let source = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n";
+        let diagnostic = Diagnostic::new(Severity::Warning, "unused")
+            .with_label(Label::secondary(0..1, ""))
+            .with_label(Label::primary(18..21, "here"));
+        let arena = Arena::<()>::new();
+        let doc = diagnostic.to_doc(&arena, "f", source, |_| ());
+        test!(
+            doc.1,
+            "warning: unused\n  --> f:10:1\n   |\n 1 | a\n   | -\n...\n10 | j\n   | ^ here"
+        );
+    }
+
+    #[cfg(feature = "humanize")]
+    #[test]
+    fn humanize() {
+        use std::time::Duration;
+
+        test!(BoxDoc::<()>::human_bytes(0), "0 B");
+        test!(BoxDoc::<()>::human_bytes(1023), "1023 B");
+        test!(BoxDoc::<()>::human_bytes(20 * 1024 * 1024), "20 MiB");
+        test!(BoxDoc::<()>::human_bytes(1024 * 1024 - 1), "1.0 MiB");
+        test!(BoxDoc::<()>::human_bytes(u64::MAX), "16 EiB");
+
+        test!(
+            BoxDoc::<()>::human_duration(Duration::from_secs(61)),
+            "1m 1s"
+        );
+        test!(
+            BoxDoc::<()>::human_duration(Duration::from_millis(1500)),
+            "1.5s"
+        );
+        test!(
+            BoxDoc::<()>::human_duration(Duration::from_millis(9_960)),
+            "10s"
+        );
+        test!(
+            BoxDoc::<()>::human_duration(Duration::from_millis(9_940)),
+            "9.9s"
+        );
+        test!(
+            BoxDoc::<()>::human_duration(Duration::from_millis(250)),
+            "250ms"
+        );
+        test!(
+            BoxDoc::<()>::human_duration(Duration::from_nanos(12_500)),
+            "12µs"
+        );
+        test!(BoxDoc::<()>::human_duration(Duration::from_nanos(5)), "5ns");
+    }
+
+    #[test]
+    fn number_formatter() {
+        use crate::number::{NumberFormatter, Separators};
+
+        // Groups of four digits, as used with East Asian numerals
+        struct Myriads;
+
+        impl NumberFormatter for Myriads {
+            fn group_separator(&self) -> Option<&str> {
+                Some(",")
+            }
+
+            fn group_size(&self) -> usize {
+                4
+            }
+
+            fn decimal_point(&self) -> &str {
+                "."
+            }
+        }
+
+        let arena = Arena::<()>::new();
+        test!(arena.number(&Separators::ENGLISH, 0).1, "0");
+        test!(arena.number(&Separators::ENGLISH, 999).1, "999");
+        test!(arena.number(&Separators::ENGLISH, -1000).1, "-1,000");
+        test!(
+            arena.number(&Separators::ENGLISH, 123456789u64).1,
+            "123,456,789"
+        );
+        test!(arena.number(&Separators::GERMAN, -0.5).1, "-0,5");
+        test!(arena.number(&Separators::PLAIN, 1234567.5).1, "1234567.5");
+        test!(arena.number(&Separators::SWISS, f64::NAN).1, "NaN");
+        test!(
+            arena.number(&Separators::GERMAN, "12345 apples").1,
+            "12.345 apples"
+        );
+        test!(arena.number(&Myriads, 123456789.5).1, "1,2345,6789.5");
+    }
+
+    #[test]
+    fn bidi_isolate() {
+        #[derive(Clone, Copy)]
+        enum Script {
+            Ltr,
+            Rtl,
+        }
+
+        let arena = Arena::new();
+        let doc = arena
+            .text("name")
+            .annotate(Script::Ltr)
+            .append(" = ")
+            .append(arena.text("שלום").annotate(Script::Rtl))
+            .append(";")
+            .group();
+
+        let mut s = String::new();
+        let mut out = BidiIsolate::new(FmtWrite::new(&mut s), |script: &Script| {
+            matches!(script, Script::Rtl)
+        });
+        doc.1.render_raw(80, &mut out).unwrap();
+        assert_eq!(s, "name = \u{2068}שלום\u{2069};");
+
+        // `name = שלום;` is 16 columns wide, as the width of text is measured in bytes, and the
+        // isolation characters do not count against the page width
+        let mut s = String::new();
+        let mut out = BidiIsolate::new(FmtWrite::new(&mut s), |script: &Script| {
+            matches!(script, Script::Rtl)
+        });
+        doc.1.render_raw(16, &mut out).unwrap();
+        assert_eq!(s, "name = \u{2068}שלום\u{2069};");
+
+        // Nor do isolation characters which are part of the text
+        let isolated = arena
+            .text("name")
+            .append(arena.line())
+            .append("= \u{2068}שלום\u{2069};")
+            .group();
+        test!(16, isolated.1, "name = \u{2068}שלום\u{2069};");
+        test!(15, isolated.1, "name\n= \u{2068}שלום\u{2069};");
+    }
+
+    #[test]
+    fn fits() {
+        let arena = Arena::<()>::new();
+        let list = arena
+            .intersperse(
+                ["a", "b", "c"].iter().copied(),
+                arena.text(",").append(arena.line()),
+            )
+            .nest(2)
+            .brackets();
+        assert!(list.1.fits(9));
+        assert!(!list.1.fits(8));
+        // `line_` flattens to nothing
+        assert!(arena
+            .text("ab")
+            .append(arena.line_())
+            .append("cd")
+            .1
+            .fits(4));
+        // Columns see the position of the flat layout
+        let column = arena
+            .text("abc")
+            .append(arena.column(|col| arena.as_string(col).into_doc()));
+        assert!(column.1.fits(4));
+        assert!(!column.1.fits(3));
+    }
+
+    #[test]
+    fn rendered_block() {
+        let block = "error[E0308]: mismatched types\r\n --> src/main.rs:2:18\r\n";
+        let doc = BoxDoc::<()>::text("cargo said:")
+            .append(BoxDoc::line())
+            .append(BoxDoc::rendered_block(block))
+            .nest(2)
+            .group();
+        test!(
+            doc,
+            "cargo said:\n  error[E0308]: mismatched types\n   --> src/main.rs:2:18"
+        );
+
+        let doc = RcDoc::<()>::text("[")
+            .append(RcDoc::rendered_block(String::from("a\n\nb")).nest(1))
+            .append("]");
+        test!(doc, "[a\n\n b]");
+        test!(BoxDoc::<()>::rendered_block(""), "");
+    }
+
+    #[test]
+    fn render_encoded() {
+        let doc = BoxDoc::<()>::text("a")
+            .append(BoxDoc::hardline())
+            .append("é")
+            .nest(1);
+
+        let mut bytes = Vec::new();
+        doc.render_encoded(80, Encoding::Utf8, &mut bytes).unwrap();
+        assert_eq!(bytes, "a\n é".as_bytes());
+
+        let mut bytes = Vec::new();
+        doc.render_encoded(80, Encoding::Utf16Le, &mut bytes)
+            .unwrap();
+        assert_eq!(bytes, b"\xFF\xFEa\0\n\0 \0\xE9\0");
+
+        let mut bytes = Vec::new();
+        BoxDoc::<()>::text("😀")
+            .render_encoded(80, Encoding::Utf16Le, &mut bytes)
+            .unwrap();
+        assert_eq!(bytes, b"\xFF\xFE\x3D\xD8\x00\xDE");
+    }
+
+    #[test]
+    fn json_trace() {
+        let doc = BoxDoc::<()>::text("a \"quoted\"\tword")
+            .append(BoxDoc::hardline())
+            .append("b")
+            .append(BoxDoc::hardline())
+            .nest(2);
+
+        let mut out = Vec::new();
+        crate::trace::json(&doc, 80, &mut out, |_| unreachable!()).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            r#"{"event":"text","line":0,"column":0,"text":"a \"quoted\"\tword"}
+{"event":"newline","indent":2}
+{"event":"text","line":1,"column":2,"text":"b"}
+{"event":"newline","indent":2}
+"#
+        );
+
+        let doc = BoxDoc::<()>::text("ab")
+            .append(BoxDoc::line())
+            .append(BoxDoc::text("long").union(BoxDoc::text("c")).group())
+            .group();
+        let mut out = Vec::new();
+        crate::trace::json(&doc, 4, &mut out, |_| unreachable!()).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            r#"{"event":"group","line":0,"column":0,"mode":"flat"}
+{"event":"text","line":0,"column":0,"text":"ab"}
+{"event":"text","line":0,"column":2,"text":" "}
+{"event":"group","line":0,"column":3,"mode":"flat"}
+{"event":"union","line":0,"column":3,"alternative":"second"}
+{"event":"text","line":0,"column":3,"text":"c"}
+"#
+        );
+    }
+
+    #[cfg(feature = "wasm-bindgen")]
+    #[test]
+    fn wasm_doc() {
+        use crate::wasm::WasmDoc;
+
+        let doc = WasmDoc::text("hello")
+            .append(&WasmDoc::line().append(&WasmDoc::text("world")).nest(2))
+            .group();
+        assert_eq!(doc.render(80), "hello world");
+        assert_eq!(doc.render(8), "hello\n  world");
+        assert_eq!(WasmDoc::text("a\nb").nest(2).render(80), "a\n  b");
+
+        // Combining handles does not copy them, so long chains are built in linear time
+        let mut chain = WasmDoc::nil();
+        let x = WasmDoc::text("x");
+        for _ in 0..200_000 {
+            chain = chain.append(&x);
+        }
+        assert_eq!(chain.render(80).len(), 200_000);
+    }
+
+    #[cfg(feature = "capi")]
+    #[test]
+    fn capi() {
+        use crate::capi::*;
+
+        unsafe {
+            let hello = pretty_doc_text("hello".as_ptr() as *const _, 5);
+            let line = pretty_doc_line();
+            let world = pretty_doc_text("world".as_ptr() as *const _, 5);
+            let tail = pretty_doc_append(line, world);
+            let nested = pretty_doc_nest(tail, 2);
+            let doc = pretty_doc_append(hello, nested);
+            let grouped = pretty_doc_group(doc);
+
+            let mut len = 0;
+            let out = pretty_doc_render(grouped, 8, &mut len);
+            assert_eq!(std::ffi::CStr::from_ptr(out).to_str(), Ok("hello\n  world"));
+            assert_eq!(len, 13);
+            pretty_string_free(out, len);
+            pretty_doc_free(grouped);
+
+            assert!(pretty_doc_text(b"\xFF".as_ptr() as *const _, 1).is_null());
+
+            let doc = pretty_doc_nest(pretty_doc_text("a\nb".as_ptr() as *const _, 3), 2);
+            let out = pretty_doc_render(doc, 80, &mut len);
+            assert_eq!(std::ffi::CStr::from_ptr(out).to_str(), Ok("a\n  b"));
+            pretty_string_free(out, len);
+            pretty_doc_free(doc);
+
+            // The combinators consume their arguments, so long chains are built in linear time
+            let mut chain = pretty_doc_nil();
+            for _ in 0..200_000 {
+                chain = pretty_doc_append(chain, pretty_doc_text("x".as_ptr() as *const _, 1));
+            }
+            let out = pretty_doc_render(chain, 80, &mut len);
+            assert_eq!(len, 200_000);
+            pretty_string_free(out, len);
+            pretty_doc_free(chain);
+        }
+    }
+
+    #[cfg(feature = "pyo3")]
+    #[test]
+    fn python_doc() {
+        use crate::python::PyDoc;
+
+        let doc = PyDoc::text("hello".into())
+            .append(&PyDoc::line().append(&PyDoc::text("world".into())).nest(2))
+            .group();
+        assert_eq!(doc.render(80), "hello world");
+        assert_eq!(doc.render(8), "hello\n  world");
+        assert_eq!(PyDoc::text("a\nb".into()).nest(2).render(80), "a\n  b");
+
+        // Combining documents does not copy them, so long chains are built in linear time
+        let mut chain = PyDoc::nil();
+        let x = PyDoc::text("x".into());
+        for _ in 0..200_000 {
+            chain = chain.append(&x);
+        }
+        assert_eq!(chain.render(80).len(), 200_000);
+    }
+
+    #[test]
+    fn display_adapter() {
+        use crate::display::{self, DisplayAdapter, ToDoc};
+
+        struct Words(&'static str);
+
+        impl ToDoc for Words {
+            fn to_doc(&self) -> RcDoc<'_> {
+                RcDoc::intersperse(self.0.split(' ').map(RcDoc::text), RcDoc::line()).group()
+            }
+        }
+
+        let words = Words("a b c");
+        assert_eq!(display::ambient_width(), display::DEFAULT_WIDTH);
+        assert_eq!(words.display().to_string(), "a b c");
+        let nested = display::with_width(4, || {
+            let inner = display::with_width(80, || words.display().to_string());
+            (inner, words.display().to_string())
+        });
+        assert_eq!(nested, ("a b c".to_string(), "a\nb\nc".to_string()));
+        assert_eq!(display::ambient_width(), display::DEFAULT_WIDTH);
+
+        // An explicit width takes precedence over the format specifier
+        assert_eq!(
+            format!("{:4}", DisplayAdapter::with_width(&words, 5)),
+            "a b c"
+        );
+        assert_eq!(display::to_string_width(&words, 3), "a\nb\nc");
+    }
+
+    #[test]
+    fn with_config() {
+        let doc = RcDoc::<()>::text("a")
+            .append(RcDoc::line())
+            .append("b")
+            .group();
+        assert_eq!(current_config(), RenderConfig::default());
+        assert_eq!(format!("{}", doc), "a b");
+        assert_eq!(format!("{:2}", doc), "a\nb");
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            crate::with_config(RenderConfig::new(2), || {
+                assert_eq!(doc.to_string(), "a\nb");
+                assert_eq!(crate::display::ambient_width(), 2);
+                panic!("restore the configuration");
+            })
+        }));
+        assert!(result.is_err());
+        assert_eq!(current_config(), RenderConfig::default());
+
+        // Renders which only take a width use the other scoped options
+        let config = RenderConfig::new(80).with_line_ending(LineEnding::CrLf);
+        crate::with_config(config, || {
+            assert_eq!(format!("{:2}", doc), "a\r\nb");
+            assert_eq!(doc.pretty(2).to_string(), "a\r\nb");
+            let mut out = Vec::new();
+            doc.render(2, &mut out).unwrap();
+            assert_eq!(out, b"a\r\nb");
+            let mut out = String::new();
+            doc.render_fmt(2, &mut out).unwrap();
+            assert_eq!(out, "a\r\nb");
+            struct Pair;
+            impl crate::display::ToDoc for Pair {
+                fn to_doc(&self) -> RcDoc<'_> {
+                    RcDoc::text("a").append(RcDoc::line()).append("b").group()
+                }
+            }
+            assert_eq!(crate::display::to_string_width(&Pair, 2), "a\r\nb");
+        });
+        assert_eq!(doc.pretty(2).to_string(), "a\nb");
+    }
+
+    #[test]
+    fn logging_lazy() {
+        use crate::logging;
+        use std::cell::Cell;
+
+        let calls = Cell::new(0);
+        let doc = logging::lazy(|| {
+            calls.set(calls.get() + 1);
+            BoxDoc::<()>::text("a")
+                .append(BoxDoc::line())
+                .append("b")
+                .append(BoxDoc::hardline())
+                .append(BoxDoc::hardline())
+                .append("c")
+                .group()
+        });
+        assert_eq!(calls.get(), 0);
+        assert_eq!(doc.indent(2).to_string(), "a\n  b\n\n  c");
+        assert_eq!(format!("{:3}", doc), "a\nb\n\nc");
+        assert_eq!(doc.width(80).to_string(), "a\nb\n\nc");
+        assert_eq!(calls.get(), 3);
+
+        // The other options of the current configuration apply as well
+        let config = RenderConfig::new(80).with_max_blank_lines(0);
+        crate::with_config(config, || {
+            assert_eq!(doc.to_string(), "a\nb\nc");
+            assert_eq!(doc.indent(2).to_string(), "a\n  b\n  c");
+            assert_eq!(doc.width(3).to_string(), "a\nb\nc");
+        });
+    }
+
+    #[test]
+    fn soft_max_width() {
+        let list = RcDoc::<()>::text("[")
+            .append(
+                RcDoc::line_()
+                    .append(RcDoc::intersperse(
+                        vec!["a", "b"],
+                        RcDoc::text(",").append(RcDoc::line()),
+                    ))
+                    .nest(2),
+            )
+            .append(RcDoc::line_())
+            .append("]")
+            .group();
+        let doc = RcDoc::text("let x = ").append(list.clone()).append(";");
+
+        let config = RenderConfig::new(14);
+        assert_eq!(
+            doc.pretty_with(config).to_string(),
+            "let x = [\n  a,\n  b\n];"
+        );
+        let config = RenderConfig::new(14).with_max_width(15);
+        assert_eq!(doc.pretty_with(config).to_string(), "let x = [a, b];");
+        // The content of the group itself must still fit in the preferred width
+        let config = RenderConfig::new(13).with_max_width(20);
+        assert_eq!(
+            doc.pretty_with(config).to_string(),
+            "let x = [\n  a,\n  b\n];"
+        );
+        // A hard maximum below the preferred width is ignored
+        let config = RenderConfig::new(15).with_max_width(10);
+        assert_eq!(doc.pretty_with(config).to_string(), "let x = [a, b];");
+    }
+
+    #[test]
+    fn union_cost() {
+        // The first alternative fits up to its line break but overflows afterwards
+        let doc = BoxDoc::<()>::text("a")
+            .append(BoxDoc::hardline())
+            .append("aaaaaaaaaa")
+            .union(BoxDoc::text("a b c"));
+        test!(5, doc, "a\naaaaaaaaaa");
+
+        let mut s = String::new();
+        doc.render_raw_with_cost(
+            RenderConfig::new(5),
+            &DefaultCost,
+            &mut FmtWrite::new(&mut s),
+        )
+        .unwrap();
+        assert_eq!(s, "a b c");
+
+        // The text following the union decides which alternative fits, while the lines after it
+        // do not matter
+        let render = |doc: &BoxDoc<()>| {
+            let mut s = String::new();
+            doc.render_raw_with_cost(
+                RenderConfig::new(5),
+                &DefaultCost,
+                &mut FmtWrite::new(&mut s),
+            )
+            .unwrap();
+            s
+        };
+        let union = || {
+            BoxDoc::<()>::text("aaa")
+                .union(BoxDoc::text("a").append(BoxDoc::hardline()).append("aa"))
+        };
+        assert_eq!(render(&union().append("bbb")), "a\naabbb");
+        assert_eq!(
+            render(
+                &union()
+                    .append("b")
+                    .append(BoxDoc::hardline())
+                    .append("cccccccc")
+            ),
+            "aaab\ncccccccc"
+        );
+
+        // Line breaks inside an annotated alternative are costed with their annotation
+        struct Heavy;
+        impl Cost<bool> for Heavy {
+            fn line_break(&self, tag: Option<&bool>) -> u64 {
+                if tag == Some(&true) {
+                    1000
+                } else {
+                    1
+                }
+            }
+        }
+        let render = |doc: &BoxDoc<bool>| {
+            let mut s = String::new();
+            doc.render_raw_with_cost(RenderConfig::new(10), &Heavy, &mut FmtWrite::new(&mut s))
+                .unwrap();
+            s
+        };
+        let heavy = BoxDoc::text("aaa")
+            .append(BoxDoc::hardline())
+            .append("bbb")
+            .annotate(true);
+        let doc = heavy.union(BoxDoc::text("cccccccccccc"));
+        assert_eq!(render(&doc), "cccccccccccc");
+        let doc = BoxDoc::text("aaa")
+            .append(BoxDoc::hardline().annotate(true))
+            .append("bbb")
+            .union(BoxDoc::text("cccccccccccc"));
+        assert_eq!(render(&doc), "cccccccccccc");
+        let light = BoxDoc::text("aaa")
+            .append(BoxDoc::hardline())
+            .append("bbb")
+            .annotate(false);
+        assert_eq!(
+            render(&light.union(BoxDoc::text("cccccccccccc"))),
+            "aaa\nbbb"
+        );
+    }
+
+    #[test]
+    fn group_hints_cost() {
+        let hinted = RcDoc::<()>::text("a")
+            .append(RcDoc::line())
+            .append("b")
+            .prefer_broken(5);
+        let broken = RcDoc::text("a").append(RcDoc::hardline()).append("b");
+        let doc = hinted.union(broken);
+        test!(80, doc, "a b");
+
+        // Flattening the hinted group costs more than the line break of the other alternative
+        let mut s = String::new();
+        doc.render_raw_with_cost(
+            RenderConfig::new(80),
+            &DefaultCost,
+            &mut FmtWrite::new(&mut s),
+        )
+        .unwrap();
+        assert_eq!(s, "a\nb");
+
+        let doc = RcDoc::<()>::text("aaaa")
+            .append(RcDoc::line())
+            .append("bbbb")
+            .prefer_flat(2);
+        test!(9, doc, "aaaa bbbb");
+        test!(6, doc, "aaaa\nbbbb");
+        test!(7, doc, "aaaa bbbb");
+
+        // The overflow is capped by the hard maximum width
+        let config = RenderConfig::new(7).with_max_width(8);
+        assert_eq!(doc.pretty_with(config).to_string(), "aaaa\nbbbb");
+        let config = RenderConfig::new(7).with_max_width(9);
+        assert_eq!(doc.pretty_with(config).to_string(), "aaaa bbbb");
+    }
+
+    #[test]
+    fn break_parent() {
+        let arena = Arena::<()>::new();
+        let inner = arena
+            .text("a")
+            .append(arena.line())
+            .append("b")
+            .append(arena.break_parent())
+            .group();
+        let doc = arena.text("x").append(arena.line()).append(inner).group();
+        test!(doc.1, "x\na\nb");
+
+        let empty = arena.expand_if_more_than(0, Vec::<&str>::new(), arena.line());
+        test!(empty.append(arena.line()).append("z").group().1, " z");
+
+        // The text of a broken parent is unaffected
+        let items =
+            arena.expand_if_more_than(2, vec!["a", "b", "c"], arena.text(",").append(arena.line()));
+        let doc = arena.text("f([").append(items.nest(2)).append("])");
+        assert_eq!(doc.1.collect_text(), "f([a, b, c])");
+        assert_eq!(
+            doc.1.iter_text().collect::<Vec<_>>(),
+            ["f([", "a", ",", " ", "b", ",", " ", "c", "])"]
+        );
+    }
+
+    #[test]
+    fn collect_text() {
+        let arena = Arena::<()>::new();
+        let doc = arena
+            .text("[")
+            .append(arena.column(|col| arena.as_string(col).into_doc()))
+            .append(arena.text("a").append(arena.line()).append("b").align())
+            .append("]")
+            .group();
+        assert_eq!(doc.1.collect_text(), "[1a b]");
+        assert!(!doc.1.is_empty());
+
+        let empty = arena
+            .text("")
+            .append(arena.nil().annotate(()))
+            .union(arena.nil());
+        assert_eq!(empty.1.collect_text(), "");
+        assert!(empty.1.is_empty());
+    }
+
+    #[test]
+    fn iter_text() {
+        let arena = Arena::<()>::new();
+        let doc = arena
+            .text("[")
+            .append(arena.column(|col| arena.as_string(col).into_doc()))
+            .append(
+                arena
+                    .text("a")
+                    .append(arena.line())
+                    .append(String::from("b"))
+                    .align(),
+            )
+            .append(arena.text("").append(arena.hardline().flat_alt("|")))
+            .append(arena.text("x").union(arena.text("y")))
+            .append("]")
+            .group();
+        assert_eq!(
+            doc.1.iter_text().collect::<Vec<_>>(),
+            ["[", "1", "a", " ", "b", "|", "x", "]"]
+        );
+
+        // Deep documents do not overflow the stack
+        let mut deep = BoxDoc::<()>::nil();
+        for i in 0..100_000 {
+            deep = BoxDoc::text(if i % 2 == 0 { "a" } else { "b" })
+                .append(deep)
+                .nest(1);
+        }
+        assert_eq!(deep.iter_text().count(), 100_000);
+        assert_eq!(deep.iter_text().take(3).collect::<String>(), "bab");
+        // Hardlines and column documents are treated the same as by `collect_text`
+        let doc = arena
+            .text("a")
+            .append(arena.hardline())
+            .append("b")
+            .append(arena.line())
+            .append("c")
+            .append(arena.column(|col| arena.as_string(col).into_doc()));
+        assert_eq!(
+            doc.1.iter_text().collect::<Vec<_>>(),
+            ["a", " ", "b", " ", "c", "5"]
+        );
+        assert_eq!(doc.1.collect_text(), "a b c5");
+
+        // Column documents see the column the layout would give them, not the byte offset
+        let doc = arena
+            .text("\u{2066}ab\u{2069}")
+            .append(arena.line())
+            .append(arena.column(|col| arena.as_string(col).into_doc()))
+            .group();
+        assert_eq!(
+            doc.1.iter_text().collect::<String>(),
+            "\u{2066}ab\u{2069} 3"
+        );
+        assert_eq!(doc.1.collect_text(), "\u{2066}ab\u{2069} 3");
+        assert_eq!(doc.1.pretty(80).to_string(), "\u{2066}ab\u{2069} 3");
+    }
+
+    #[test]
+    fn join_non_empty() {
+        let doc = BoxDoc::<()>::join_non_empty(
+            vec![
+                BoxDoc::nil(),
+                BoxDoc::text("a"),
+                BoxDoc::text(""),
+                BoxDoc::nil().group(),
+                BoxDoc::text("b"),
+                BoxDoc::nil(),
+            ],
+            BoxDoc::text(",").append(BoxDoc::space()),
+        );
+        test!(doc, "a, b");
+        test!(
+            BoxDoc::<()>::join_non_empty(vec![BoxDoc::nil()], BoxDoc::text(",")),
+            ""
+        );
+    }
+
+    #[test]
+    fn truncate_annotations() {
+        // Records the output with annotations written as brackets
+        struct Brackets(String);
+
+        impl Render for Brackets {
+            type Error = fmt::Error;
+
+            fn write_str(&mut self, s: &str) -> Result<usize, fmt::Error> {
+                self.0.push_str(s);
+                Ok(s.len())
+            }
+        }
+
+        impl RenderAnnotated<&'static str> for Brackets {
+            fn push_annotation(&mut self, ann: &&'static str) -> fmt::Result {
+                self.0.push_str(&format!("<{}>", ann));
+                Ok(())
+            }
+
+            fn pop_annotation(&mut self) -> fmt::Result {
+                self.0.push_str("</>");
+                Ok(())
+            }
+        }
+
+        let arena = Arena::new();
+        let doc = arena
+            .text("a")
+            .append(
+                arena
+                    .text("bc")
+                    .append(arena.text("de").annotate("i"))
+                    .annotate("o"),
+            )
+            .append(arena.hardline())
+            .append(arena.text("f").annotate("o"));
+
+        let render = |out: Truncate<Brackets, &'static str>| {
+            let mut out = out;
+            doc.1.render_raw(80, &mut out).unwrap();
+            (out.is_truncated(), out.into_inner().0)
+        };
+        let out = || Truncate::new(Brackets(String::new()));
+
+        assert_eq!(render(out()), (false, "a<o>bc<i>de</></>\n<o>f</>".into()));
+        assert_eq!(
+            render(out().max_bytes(4)),
+            (true, "a<o>bc<i>d</></>".into())
+        );
+        assert_eq!(
+            render(out().max_lines(1)),
+            (true, "a<o>bc<i>de</></>".into())
+        );
+        assert_eq!(render(out().max_lines(0)), (true, "".into()));
+        assert_eq!(
+            render(out().max_bytes(4).at_annotation_boundaries()),
+            (true, "a".into())
+        );
+        assert_eq!(
+            render(out().max_bytes(6).at_annotation_boundaries()),
+            (true, "a<o>bc<i>de</></>\n".into())
+        );
+    }
+
+    #[test]
+    fn hex_dump() {
+        let arena = Arena::<()>::new();
+        let bytes = (0..20).collect::<Vec<u8>>();
+        let doc = arena.hex_dump(&bytes);
+
+        test!(
+            30,
+            doc.1,
+            "00000000  00 01 02 03  |....|\n\
+             00000004  04 05 06 07  |....|\n\
+             00000008  08 09 0a 0b  |....|\n\
+             0000000c  0c 0d 0e 0f  |....|\n\
+             00000010  10 11 12 13  |....|"
+        );
+        test!(
+            80,
+            doc.1,
+            "00000000  00 01 02 03 04 05 06 07  08 09 0a 0b 0c 0d 0e 0f  |................|\n\
+             00000010  10 11 12 13                                       |....|"
+        );
+        test!(10, arena.hex_dump(&[]).1, "");
+    }
+
+    #[test]
+    fn render_step() {
+        let arena = Arena::new();
+        let doc = arena
+            .text("a")
+            .append(arena.text("b").annotate(()))
+            .append(arena.hardline())
+            .append("c");
+        let temp = LayoutArena::new();
+        let mut iter = LayoutIter::new(&temp, &doc.1, 80);
+
+        let mut s = String::new();
+        let mut steps = vec![];
+        loop {
+            let step = iter.render_step(1, &mut FmtWrite::new(&mut s)).unwrap();
+            steps.push((s.clone(), step));
+            if step == Step::Done {
+                break;
+            }
+        }
+        assert_eq!(
+            steps,
+            [
+                ("a".into(), Step::Pending),
+                ("a".into(), Step::Pending),
+                ("ab".into(), Step::Pending),
+                ("ab".into(), Step::Pending),
+                ("ab\n".into(), Step::Pending),
+                ("ab\nc".into(), Step::Done),
+            ]
+        );
+        assert_eq!(
+            iter.render_step(10, &mut FmtWrite::new(&mut s)),
+            Ok(Step::Done)
+        );
+    }
+
+    #[test]
+    fn stress() {
+        let arena = Arena::<()>::new();
+        let doc = arena
+            .text("short")
+            .append(arena.line().append("x").group())
+            .append(arena.hardline())
+            .append(
+                arena
+                    .text("a long line")
+                    .append(arena.line())
+                    .append("of text")
+                    .group(),
+            );
+        let render = |seed| {
+            let temp = LayoutArena::new();
+            let mut iter = LayoutIter::new(&temp, &doc.1, 20).with_stress(seed, 2);
+            let mut s = String::new();
+            iter.render_step(usize::MAX, &mut FmtWrite::new(&mut s))
+                .unwrap();
+            (s, iter.knife_edges().to_vec())
+        };
+
+        for seed in 0..16 {
+            let (s, knife_edges) = render(seed);
+            assert_eq!(render(seed).0, s);
+            // Only the second group is within 2 columns of the width
+            assert_eq!(knife_edges.len(), 1);
+            let edge = knife_edges[0];
+            assert_eq!((edge.line, edge.column), (1, 0));
+            assert_eq!(edge.flat, !edge.flipped);
+            let expected = if edge.flat {
+                "short x\na long line of text"
+            } else {
+                "short x\na long line\nof text"
+            };
+            assert_eq!(s, expected);
+        }
+    }
+
+    #[test]
+    fn space_or() {
+        let doc = RcDoc::<()>::intersperse(
+            vec!["a", "b"],
+            RcDoc::space_or(RcDoc::text(";").append(RcDoc::space())),
+        )
+        .nest(2)
+        .group();
+
+        test!(doc, "a; b");
+        test!(1, doc, "a\n  b");
+    }
+
+    #[cfg(feature = "termcolor")]
+    #[test]
+    fn keyed_colors() {
+        use termcolor::{Ansi, Color, ColorSpec};
+
+        const KEYWORD: u8 = 1;
+
+        let arena = Arena::new();
+        let doc = arena
+            .text("fn")
+            .annotate(KEYWORD)
+            .append(arena.text(" x").annotate(7));
+
+        let render = |color| {
+            let mut table = std::collections::HashMap::new();
+            table.insert(KEYWORD, ColorSpec::new().set_fg(Some(color)).clone());
+            let mut out = Keyed::new(TermColored::new(Ansi::new(Vec::new())), &table);
+            doc.1.render_raw(80, &mut out).unwrap();
+            String::from_utf8(out.into_inner().into_inner().into_inner()).unwrap()
+        };
+
+        assert_eq!(render(Color::Red), "\x1b[0m\x1b[31mfn\x1b[0m x");
+        assert_eq!(render(Color::Blue), "\x1b[0m\x1b[34mfn\x1b[0m x");
+    }
+
+    #[test]
+    fn theme_nested_styles() {
+        use crate::theme::{AnsiStyled, HtmlStyled, Tag, Theme};
+
+        let arena = Arena::new();
+        let doc = arena
+            .text("// a <b>")
+            .append(arena.text("!").annotate(Tag::Error))
+            .append(" c")
+            .annotate(Tag::Comment);
+
+        let mut s = String::new();
+        let theme = Theme::dark();
+        let mut out = Keyed::new(AnsiStyled::new(FmtWrite::new(&mut s)), &theme);
+        doc.1.render_raw(80, &mut out).unwrap();
+        assert_eq!(
+            s,
+            "\x1b[0;3;38;2;150;150;150m// a <b>\x1b[0;1;4;31m!\
+             \x1b[0;3;38;2;150;150;150m c\x1b[0m"
+        );
+
+        let mut s = String::new();
+        let theme = Theme::plain();
+        let mut out = Keyed::new(HtmlStyled::new(FmtWrite::new(&mut s)), &theme);
+        doc.1.render_raw(80, &mut out).unwrap();
+        assert_eq!(
+            s,
+            "<span style=\"\">// a &lt;b&gt;<span style=\"\">!</span> c</span>"
+        );
+    }
+
+    #[test]
+    fn html_collapsible() {
+        use crate::theme::{HtmlAnnotation, HtmlStyled, Style};
+
+        let summary = RcDoc::text("<main>")
+            .append(RcDoc::line())
+            .append(RcDoc::text("3 items").annotate(Style::new().italic()))
+            .group();
+        let items = RcDoc::intersperse(
+            ["a", "b", "c"]
+                .iter()
+                .map(|item| RcDoc::text(*item).annotate(Style::new().bold().into())),
+            RcDoc::hardline(),
+        );
+        let doc = RcDoc::text("items:")
+            .append(RcDoc::hardline())
+            .append(items.annotate(HtmlAnnotation::Collapsible {
+                summary,
+                open: true,
+            }))
+            .nest(2);
+
+        let mut s = String::new();
+        doc.render_raw(1, &mut HtmlStyled::new(FmtWrite::new(&mut s)))
+            .unwrap();
+        assert_eq!(
+            s,
+            "items:&#10;  <details open><summary>&lt;main&gt; \
+             <span style=\"font-style:italic\">3 items</span></summary>\
+             <span style=\"font-weight:bold\">a</span>&#10;  \
+             <span style=\"font-weight:bold\">b</span>&#10;  \
+             <span style=\"font-weight:bold\">c</span></details>"
+        );
+    }
+
+    #[test]
+    fn color_mode_env() {
+        use crate::config::resolve_colors;
+
+        let var = |s: &str| Some(s.into());
+        assert!(resolve_colors(ColorMode::Auto, None, None));
+        assert!(!resolve_colors(ColorMode::Auto, var("1"), None));
+        assert!(resolve_colors(ColorMode::Auto, var(""), None));
+        assert!(resolve_colors(ColorMode::Auto, var("1"), var("1")));
+        assert!(!resolve_colors(ColorMode::Auto, var("1"), var("0")));
+        assert!(!resolve_colors(ColorMode::Never, None, var("1")));
+        assert!(resolve_colors(ColorMode::Always, var("1"), None));
+    }
+
+    #[test]
+    fn string_literal() {
+        test!(RcDoc::<()>::string_literal("", QuoteStyle::DOUBLE), "\"\"");
+        test!(
+            RcDoc::<()>::string_literal("a\\b\t\u{1}\u{85}'\"", QuoteStyle::SINGLE),
+            r#"'a\\b\t\x01\x85\'"'"#
+        );
+        test!(
+            RcDoc::<()>::string_literal("\"'\"", QuoteStyle::FEWEST),
+            r#"'"\'"'"#
+        );
+        test!(
+            RcDoc::<()>::string_literal("'\"", QuoteStyle::FEWEST),
+            r#""'\"""#
+        );
+
+        let style = QuoteStyle::DOUBLE.with_split("");
+        let doc = RcDoc::<()>::text("f(")
+            .append(RcDoc::string_literal("one two three four five six", style))
+            .append(")");
+        test!(80, doc, r#"f("one two three four five six")"#);
+        test!(
+            12,
+            doc,
+            "f(\"one two \"\n  \"three \"\n  \"four \"\n  \"five \"\n  \"six\")"
+        );
+    }
+
+    #[test]
+    fn string_literal_dialects() {
+        // Decodes the escapes the way the languages do, with octal escapes of up to three digits
+        fn unescape(literal: &str) -> String {
+            let mut bytes = vec![];
+            let mut rest = &literal[1..literal.len() - 1];
+            while let Some(i) = rest.find('\\') {
+                bytes.extend_from_slice(&rest.as_bytes()[..i]);
+                let escape = &rest[i + 1..];
+                let hex = |digits: &str| u32::from_str_radix(digits, 16).unwrap();
+                let mut push = |c: u32| {
+                    let c = char::from_u32(c).unwrap();
+                    bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes())
+                };
+                let len = match escape.as_bytes()[0] {
+                    b'n' | b'r' | b't' | b'\\' | b'"' | b'\'' => {
+                        push(match escape.as_bytes()[0] {
+                            b'n' => '\n',
+                            b'r' => '\r',
+                            b't' => '\t',
+                            c => c as char,
+                        } as u32);
+                        1
+                    }
+                    b'x' => {
+                        push(hex(&escape[1..3]));
+                        3
+                    }
+                    b'u' if escape[1..].starts_with('{') => {
+                        let end = escape.find('}').unwrap();
+                        push(hex(&escape[2..end]));
+                        end + 1
+                    }
+                    b'u' => {
+                        push(hex(&escape[1..5]));
+                        5
+                    }
+                    _ => {
+                        let digits = escape
+                            .bytes()
+                            .take(3)
+                            .take_while(|b| (b'0'..=b'7').contains(b))
+                            .count();
+                        bytes.push(u8::from_str_radix(&escape[..digits], 8).unwrap());
+                        digits
+                    }
+                };
+                rest = &escape[len..];
+            }
+            bytes.extend_from_slice(rest.as_bytes());
+            String::from_utf8(bytes).unwrap()
+        }
+
+        let text = "\u{0}1\u{1}23\u{7f}4\u{85}5\u{1b}[0m\"'\\";
+        for &dialect in &[
+            Dialect::C,
+            Dialect::Java,
+            Dialect::JavaScript,
+            Dialect::Json,
+            Dialect::Python,
+            Dialect::Rust,
+        ] {
+            for &style in &[QuoteStyle::DOUBLE, QuoteStyle::SINGLE] {
+                let style = style.with_dialect(dialect);
+                let literal = RcDoc::<()>::string_literal(text, style)
+                    .pretty(80)
+                    .to_string();
+                assert_eq!(unescape(&literal), text, "{:?} {}", dialect, literal);
+            }
+        }
+
+        let literal = |dialect| {
+            let style = QuoteStyle::SINGLE.with_dialect(dialect);
+            RcDoc::<()>::string_literal("\u{0}1\u{85}", style)
+                .pretty(80)
+                .to_string()
+        };
+        assert_eq!(literal(Dialect::C), r"'\0001\302\205'");
+        assert_eq!(literal(Dialect::Java), r"'\u00001\u0085'");
+        assert_eq!(literal(Dialect::JavaScript), r"'\x001\x85'");
+        assert_eq!(literal(Dialect::Json), r#""\u00001\u0085""#);
+        assert_eq!(literal(Dialect::Python), r"'\x001\x85'");
+        assert_eq!(literal(Dialect::Rust), r"'\u{0}1\u{85}'");
+    }
+
+    #[test]
+    fn ident_case() {
+        let cases = [
+            (
+                "parseHTTPRequest",
+                "parse_http_request",
+                "parseHttpRequest",
+                "ParseHttpRequest",
+            ),
+            (
+                "XMLHttp2Request",
+                "xml_http2_request",
+                "xmlHttp2Request",
+                "XmlHttp2Request",
+            ),
+            ("__user__id", "user_id", "userId", "UserId"),
+            ("content-type", "content_type", "contentType", "ContentType"),
+            ("MAX_SIZE", "max_size", "maxSize", "MaxSize"),
+            ("x", "x", "x", "X"),
+            ("", "", "", ""),
+        ];
+        for &(name, snake, camel, pascal) in &cases {
+            test!(RcDoc::<()>::ident_snake(name), snake);
+            test!(RcDoc::<()>::ident_camel(name), camel);
+            test!(RcDoc::<()>::ident_pascal(name), pascal);
+            test!(
+                RcDoc::<()>::ident_screaming_snake(name),
+                &snake.to_uppercase()
+            );
+        }
+    }
+
+    #[cfg(feature = "ryu")]
+    #[test]
+    fn float() {
+        test!(RcDoc::<()>::float(1.0), "1.0");
+        test!(RcDoc::<()>::float(-2.5e-8), "-2.5e-8");
+        test!(RcDoc::<()>::float(f64::NAN), "NaN");
+        test!(RcDoc::<()>::float(f64::NEG_INFINITY), "-inf");
+        for &x in &[0.1, 1.0 / 3.0, 123456789.125, f64::MAX, f64::MIN_POSITIVE] {
+            let s = RcDoc::<()>::float(x).pretty(80).to_string();
+            assert_eq!(s.parse::<f64>(), Ok(x));
+        }
+    }
+
+    #[test]
+    fn alignment_group() {
+        let arena = Arena::<()>::new();
+        let doc = arena.alignment_group(vec![
+            vec![arena.text("a"), arena.text(": "), arena.text("x")],
+            vec![
+                arena.text("bcd"),
+                arena.text(":").append(arena.line()).group(),
+                arena.text("yy"),
+            ],
+            vec![arena.text("ef")],
+        ]);
+        test!(doc.1, "a  : x\nbcd: yy\nef");
+
+        let nested = arena.text("{").append(
+            arena
+                .hardline()
+                .append(arena.alignment_group(vec![vec!["k", " = 1"], vec!["key", " = 2"]]))
+                .nest(4),
+        );
+        test!(nested.1, "{\n    k   = 1\n    key = 2");
+
+        // The marks are aligned as the cells are laid out, even if they break
+        let cell = arena.text("abc").append(arena.line()).append("def").group();
+        let doc = arena.alignment_group(vec![
+            vec![cell, arena.text(" = 1")],
+            vec![arena.text("g"), arena.text(" = 2")],
+        ]);
+        test!(5, doc.1, "abc\ndef = 1\ng   = 2");
+        test!(80, doc.1, "abc def = 1\ng       = 2");
+
+        // Groups after a mark are only flattened if they fit with the padding
+        let value = arena.text(" = x").append(arena.line()).append("y").group();
+        let doc = arena.alignment_group(vec![
+            vec![arena.text("a"), value],
+            vec![arena.text("bbbbbb"), arena.text(" = z")],
+        ]);
+        test!(12, doc.1, "a      = x y\nbbbbbb = z");
+        test!(11, doc.1, "a      = x\ny\nbbbbbb = z");
+
+        // The marks of nested alignment groups are aligned separately
+        let inner = arena
+            .alignment_group(vec![vec!["p", ":1"], vec!["ppp", ":2"]])
+            .align();
+        let doc = arena.alignment_group(vec![
+            vec![arena.text("a"), arena.text(" = ").append(inner)],
+            vec![arena.text("bbb"), arena.text(" = 2")],
+        ]);
+        test!(doc.1, "a   = p  :1\n      ppp:2\nbbb = 2");
+
+        // Marks outside of `align_marks` take up no space
+        test!(
+            arena.text("a").append(arena.alignment_mark()).append("b").1,
+            "ab"
+        );
+
+        let long = "c".repeat(200);
+        let doc = arena.alignment_group(vec![vec!["a", "b"], vec![&long[..], "d"]]);
+        test!(doc.1, &format!("a{}b\n{}d", " ".repeat(199), long));
+    }
+
+    #[test]
+    fn max_width() {
+        let arena = Arena::<()>::new();
+        let cell = |s: &'static str| {
+            arena
+                .intersperse(s.split(' ').map(|w| arena.text(w)), arena.softline())
+                .max_width(6)
+        };
+        let doc = arena
+            .text("|")
+            .append(cell("aa bb cc"))
+            .append(arena.text("|"))
+            .append(arena.text("x").append(arena.line()).append("y").group());
+
+        // The group after the cell sees the full width again
+        test!(12, doc.1, "|aa bb\ncc|x y");
+        // A narrower page still wins
+        test!(4, doc.1, "|aa\nbb\ncc|x\ny");
+
+        let nested = arena.text("a b c d").append(
+            arena
+                .line()
+                .append(arena.text("e").append(arena.line()).append("f").group())
+                .group()
+                .max_width(3),
+        );
+        // The width is counted from the column at which the `max_width` starts
+        test!(20, nested.1, "a b c d\ne f");
+    }
+
+    #[test]
+    fn unbounded() {
+        let arena = Arena::<()>::new();
+        let words = |ws: &[&'static str]| {
+            arena.intersperse(ws.iter().map(|w| arena.text(*w)), arena.softline())
+        };
+        let doc = words(&["see", "https://example.com/a/long/path", "for", "details"])
+            .unbounded()
+            .append(arena.line())
+            .append(words(&["and", "more"]))
+            .group();
+
+        test!(
+            10,
+            doc.1,
+            "see https://example.com/a/long/path for details\nand more"
+        );
+
+        let hard = arena
+            .text("a")
+            .append(arena.hardline())
+            .append(arena.text("b").append(arena.line()).append("c").group())
+            .unbounded();
+        test!(1, hard.1, "a\nb c");
+
+        let biased = arena
+            .text("x")
+            .append(arena.line())
+            .append("y")
+            .prefer_broken(1)
+            .unbounded();
+        test!(1, biased.1, "x y");
+    }
+
+    #[test]
+    fn recover_text_error() {
+        // Fails to write text containing `!` while an annotation is open
+        #[derive(Default)]
+        struct Picky {
+            out: String,
+            open: usize,
+        }
+
+        impl Render for Picky {
+            type Error = &'static str;
+
+            fn write_str(&mut self, s: &str) -> Result<usize, &'static str> {
+                if self.open > 0 && s.contains('!') {
+                    return Err("unsupported");
+                }
+                self.out.push_str(s);
+                Ok(s.len())
+            }
+        }
+
+        impl RenderAnnotated<()> for Picky {
+            fn push_annotation(&mut self, _: &()) -> Result<(), &'static str> {
+                self.open += 1;
+                self.out.push('<');
+                Ok(())
+            }
+
+            fn pop_annotation(&mut self) -> Result<(), &'static str> {
+                if self.open == 0 {
+                    return Err("fatal");
+                }
+                self.open -= 1;
+                self.out.push('>');
+                Ok(())
+            }
+        }
+
+        let arena = Arena::new();
+        let doc = arena
+            .text("a")
+            .append(arena.text("b!").annotate(()))
+            .append(arena.text("c").annotate(()))
+            .annotate(());
+
+        let mut out = Recover::new(Picky::default(), |err: &&str| *err == "unsupported");
+        doc.1.render_raw(80, &mut out).unwrap();
+        assert_eq!(out.recovered(), ["unsupported"]);
+        assert_eq!(out.into_inner().out, "<a<>>b!<c>");
+
+        let mut out = Recover::new(Picky::default(), |_: &&str| false);
+        assert_eq!(doc.1.render_raw(80, &mut out), Err("unsupported"));
+    }
+
+    #[test]
+    fn bench_support() {
+        let arena = Arena::new();
+
+        let deep = crate::bench_support::deep_nesting(&arena, 3);
+        test!(80, deep.1, "([(x)])");
+        test!(1, deep.1, "(\n  [\n    (\n      x\n    )\n  ]\n)");
+
+        let annotated = crate::bench_support::annotated(&arena, 2, ());
+        test!(annotated.1, "let value = 42\nlet value = 42");
+
+        let random = |seed| {
+            crate::bench_support::random(&arena, seed, 200)
+                .1
+                .pretty(40)
+                .to_string()
+        };
+        assert_eq!(random(1), random(1));
+        assert_ne!(random(1), random(2));
+        assert!(random(1).lines().count() > 1);
+    }
+
+    #[test]
+    fn strict_fitting() {
+        let arena = Arena::<()>::new();
+        let group =
+            |a: &'static str, b: &'static str| arena.text(a).append(arena.line()).append(b).group();
+        // The first group fits up to the line of the second group, which is flattened as well
+        let doc = group("a", "b")
+            .append(group("c", "d"))
+            .append(arena.hardline())
+            .append(group("e", "f"));
+
+        let render = |config| {
+            let mut s = String::new();
+            doc.1
+                .render_raw_with(config, &mut FmtWrite::new(&mut s))
+                .unwrap();
+            s
+        };
+        assert_eq!(render(RenderConfig::new(4)), "a bc\nd\ne f");
+        assert_eq!(
+            render(RenderConfig::new(4).with_strict_fitting(true)),
+            "a\nbc d\ne f"
+        );
+        assert_eq!(
+            render(RenderConfig::new(6).with_strict_fitting(true)),
+            "a bc d\ne f"
+        );
+    }
+
+    #[test]
+    fn set_mode() {
+        struct Events(Vec<String>);
+
+        impl Render for Events {
+            type Error = ();
+
+            fn write_str(&mut self, s: &str) -> Result<usize, ()> {
+                self.0.push(s.to_string());
+                Ok(s.len())
+            }
+        }
+
+        impl RenderAnnotated<&'static str> for Events {
+            fn push_annotation(&mut self, ann: &&'static str) -> Result<(), ()> {
+                self.0.push(format!("<{}>", ann));
+                Ok(())
+            }
+
+            fn pop_annotation(&mut self) -> Result<(), ()> {
+                self.0.push("</>".into());
+                Ok(())
+            }
+
+            fn set_mode(&mut self, mode: Mode) -> Result<(), ()> {
+                self.0.push(format!("{:?}", mode));
+                Ok(())
+            }
+        }
+
+        let arena = Arena::new();
+        let doc = arena
+            .text("a")
+            .append(arena.line())
+            .append(arena.text("b").append(arena.line()).append("c").group())
+            .annotate("x")
+            .append(arena.hardline())
+            .append("d");
+
+        let mut out = Events(vec![]);
+        doc.1.render_raw(3, &mut out).unwrap();
+        assert_eq!(
+            out.0,
+            ["<x>", "a", "\n", "Flat", "b", " ", "c", "</>", "Break", "\n", "d"]
+        );
+
+        // The mode changes are buffered together with the annotated span
+        let mut out = Truncate::new(Events(vec![])).at_annotation_boundaries();
+        doc.1.render_raw(3, &mut out).unwrap();
+        assert_eq!(
+            out.into_inner().0,
+            ["<x>", "a", "\n", "Flat", "b", " ", "c", "</>", "Break", "\n", "d"]
+        );
+    }
+
+    #[test]
+    fn snapshot() {
+        let arena = Arena::new();
+        let shared = arena.text("shared").annotate(1);
+
+        let text = String::from("borrowed");
+        let committed = arena.snapshot(|snapshot| {
+            let doc = shared
+                .1
+                .clone_into_arena(snapshot)
+                .append(snapshot.line())
+                .append(snapshot.text(&text[..]))
+                .group();
+            snapshot.commit(&doc.1)
+        });
+        test!(committed.1, "shared borrowed");
+        assert_eq!(
+            format!("{:?}", *committed.1),
+            format!(
+                "{:?}",
+                shared
+                    .append(arena.line())
+                    .append("borrowed")
+                    .group()
+                    .into_doc()
+            )
+        );
+
+        // Column functions keep the snapshot alive
+        let aligned = arena.snapshot(|snapshot| {
+            let doc = snapshot
+                .text("a")
+                .append(snapshot.line())
+                .append("b")
+                .align();
+            snapshot.commit(&snapshot.text("x ").append(doc).1)
+        });
+        test!(1, aligned.1, "x a\n  b");
+
+        // Shared documents stay shared, so this does not take 2^64 copies
+        let shared = arena.snapshot(|snapshot| {
+            let doc = (0..64).fold(snapshot.text("x").into_doc(), |doc, _| {
+                snapshot.alloc(Doc::Append(doc, doc))
+            });
+            snapshot.commit(&doc)
+        });
+        match *shared.1 {
+            Doc::Append(ref l, ref r) => assert!(std::ptr::eq(l.0, r.0)),
+            _ => panic!("expected an append"),
+        }
+
+        let deep = arena.snapshot(|snapshot| {
+            let doc = (0..1_000_000).fold(snapshot.text("x"), |doc, _| {
+                snapshot.text("y").append(doc).group()
+            });
+            snapshot.commit(&doc.1)
+        });
+        assert_eq!(deep.1.pretty(80).to_string().len(), 1_000_001);
+    }
+
+    #[test]
+    fn deep_clone_eq_drop() {
+        fn chain<'a>(depth: usize, last: &'static str) -> BoxDoc<'a, i32> {
+            (0..depth).fold(BoxDoc::text(last), |doc, i| match i % 3 {
+                0 => BoxDoc::text("x").append(doc),
+                1 => doc.nest(1),
+                _ => doc.annotate(i as i32),
+            })
+        }
+
+        let doc = chain(1_000_000, "end");
+        let copy = doc.clone();
+        assert!(doc == copy);
+        assert!(doc != chain(1_000_000, "END"));
+        assert!(doc != chain(999_999, "end"));
+
+        let doc: RcDoc<()> = (0..1_000_000).fold(RcDoc::nil(), |doc, _| doc.group());
+        assert!(doc.clone() == doc);
+    }
+
+    #[test]
+    fn break_heat_map() {
+        use crate::stats::{Annotation, GroupStats};
+
+        let arena = Arena::new();
+        let inner = arena
+            .text("b")
+            .append(arena.line())
+            .append("c")
+            .group()
+            .annotate("inner");
+        let doc = arena
+            .text("a")
+            .append(arena.line().append(inner.clone()).nest(2))
+            .append(arena.line())
+            .append(inner)
+            .append(arena.line())
+            .append(arena.text("x").append(arena.hardline()).group())
+            .group();
+
+        let heat_arena = Arena::new();
+        let heat = stats::break_heat_map(&doc.1, RenderConfig::new(6), &heat_arena);
+        let mut out = Vec::new();
+        trace::json(&heat.1, 6, &mut out, |ann| match ann {
+            Annotation::Group(GroupStats {
+                broke,
+                flat_width,
+                column,
+            }) => format!("{} {:?} {}", broke, flat_width, column),
+            Annotation::Doc(ann) => ann.to_string(),
+        })
+        .unwrap();
+        let groups: Vec<_> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .filter_map(|line| line.split("\"annotation\":\"").nth(1))
+            .map(|ann| ann.trim_end_matches("\"}").to_string())
+            .collect();
+        // The inner group is shared, so both copies report its first layout
+        assert_eq!(
+            groups,
+            [
+                "true None 0",
+                "inner",
+                "false Some(3) 2",
+                "inner",
+                "false Some(3) 2",
+                "true None 0",
+            ]
+        );
+
+        let deep = (0..1_000_000).fold(arena.text("x"), |doc, i| {
+            arena
+                .text("y")
+                .append(doc)
+                .annotate(if i % 2 == 0 { "a" } else { "b" })
+        });
+        let heat = stats::break_heat_map(&deep.group().1, RenderConfig::new(6), &heat_arena);
+        assert_eq!(heat.1.pretty(6).to_string().len(), 1_000_001);
+    }
+
+    #[test]
+    fn suggest_width() {
+        use crate::stats::{self, WidthSuggestion};
+
+        let arena = Arena::<()>::new();
+        let items = arena.intersperse(
+            ["alpha", "beta", "gamma"],
+            arena.text(",").append(arena.line()),
+        );
+        let list = arena
+            .text("[")
+            .append(arena.line_().append(items).nest(4))
+            .append(arena.line_())
+            .append("]")
+            .group();
+        let doc = arena
+            .text("let xs = ")
+            .append(list)
+            .append(";")
+            .append(arena.hardline())
+            .append(arena.text("x").append(arena.line()).append("y").group())
+            .append(arena.text("a").append(arena.hardline()).append("b").group());
+
+        let suggestion = stats::suggest_width(&doc.1);
+        assert_eq!(
+            suggestion,
+            WidthSuggestion {
+                longest_run: 10,
+                group_widths: vec![3, 29],
+                min_width: 10,
+            }
+        );
+        let overflows = |width| {
+            doc.1
+                .pretty(width)
+                .to_string()
+                .lines()
+                .any(|l| l.len() > width)
+        };
+        assert!((0..suggestion.min_width).all(overflows));
+        assert!(!overflows(suggestion.min_width));
+    }
+
+    #[test]
+    fn eq_modulo_whitespace() {
+        use crate::testing::eq_modulo_whitespace;
+
+        let doc = RcDoc::<()>::text("let x =")
+            .append(RcDoc::line().append("[").nest(4))
+            .append(
+                RcDoc::line_()
+                    .append(RcDoc::intersperse(
+                        ["1", "2"],
+                        RcDoc::text(",").append(RcDoc::line()),
+                    ))
+                    .nest(8),
+            )
+            .append(RcDoc::line_().append("];").nest(4))
+            .group();
+        let outputs: Vec<_> = [0, 10, 80]
+            .iter()
+            .map(|&w| doc.pretty(w).to_string())
+            .collect();
+        assert_eq!(outputs[2], "let x = [1, 2];");
+        for a in &outputs {
+            for b in &outputs {
+                assert!(eq_modulo_whitespace(a, b), "{:?} {:?}", a, b);
+            }
+        }
+
+        assert!(eq_modulo_whitespace("  a  b\n", "a b"));
+        assert!(eq_modulo_whitespace("a\r\n  b", "ab"));
+        assert!(!eq_modulo_whitespace("a b", "ab"));
+        assert!(!eq_modulo_whitespace("ab", "a b"));
+        assert!(!eq_modulo_whitespace("a b", "a b c"));
+        assert!(!eq_modulo_whitespace("a\nb", "a\nc"));
+    }
+
+    #[test]
+    fn verify_tokens() {
+        use crate::verify::{self, TokenError};
+
+        let arena = Arena::new();
+        let token = |s: &'static str| arena.text(s).annotate(true);
+        let doc = token("f")
+            .append(token("("))
+            .append(arena.line_().append(token("x")).nest(2))
+            .append(arena.line_())
+            .append(token(")"))
+            .group();
+        let is_token = |ann: &bool| *ann;
+        let source = ["f", "(", "x", ")"];
+        assert_eq!(
+            verify::tokens(&doc.1, RenderConfig::new(2), source, is_token),
+            Ok(())
+        );
+
+        let duplicated = doc.clone().append(token(")"));
+        let error = verify::tokens(&duplicated.1, RenderConfig::new(2), source, is_token);
+        assert_eq!(
+            error,
+            Err(TokenError::Mismatch {
+                index: 4,
+                line: 2,
+                column: 1,
+                expected: None,
+                found: Some(")".into()),
+            })
+        );
+        assert_eq!(
+            error.unwrap_err().to_string(),
+            "3:2: token 4: unexpected \")\""
+        );
+
+        let stray = doc.append(arena.hardline()).append("// comment");
+        assert_eq!(
+            verify::tokens(&stray.1, RenderConfig::new(2), source, is_token),
+            Err(TokenError::Stray {
+                line: 3,
+                column: 0,
+                text: "// comment".into(),
+            })
+        );
+    }
+
+    #[test]
+    fn validate() {
+        let arena = Arena::<()>::new();
+        let doc = arena
+            .text("x")
+            .append(arena.text("y").enclose("[", ")").nest(2).group());
+        let error = doc.1.validate().unwrap_err();
+        assert_eq!(error.message, "`)` closes `[`");
+        assert_eq!(
+            error.path,
+            "root > Append.1 > Group > Nest(2) > Append.1 > Delimiter > Text(\")\")"
+        );
+
+        let doc = arena
+            .text("x")
+            .enclose("{", "")
+            .append(arena.text("y").braces());
+        let error = doc.1.validate().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "`{` is never closed at root > Append.0 > Append.0 > Append.0 > Delimiter > Text(\"{\")"
+        );
+
+        // Only the delimiters inserted by `enclose` are checked
+        let doc = arena
+            .text("\"(\"")
+            .append(arena.text("]"))
+            .parens()
+            .append(arena.line_().nest(-1))
+            .nest(1);
+        assert_eq!(doc.1.validate(), Ok(()));
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "invalid document: `]` has no opening delimiter")]
+    fn debug_validate() {
+        let arena = Arena::<()>::new();
+        arena.nil().enclose("", "]").1.debug_validate();
+    }
+
+    #[test]
+    fn max_blank_lines() {
+        let arena = Arena::new();
+        let doc = arena
+            .hardline()
+            .append("a")
+            .append(arena.hardline())
+            .append(arena.hardline())
+            .append(arena.hardline())
+            .append(arena.text("b").annotate(()))
+            .append(arena.hardline().append(arena.hardline()).nest(2))
+            .append("c")
+            .append(arena.hardline())
+            .append(arena.hardline());
+        let render = |config: RenderConfig| {
+            let mut s = String::new();
+            doc.1
+                .render_raw_with(config, &mut FmtWrite::new(&mut s))
+                .unwrap();
+            s
+        };
+        assert_eq!(render(RenderConfig::new(80)), "\na\n\n\nb\n  \n  c\n\n");
+        assert_eq!(
+            render(RenderConfig::new(80).with_max_blank_lines(1)),
+            "\na\n\nb\n  \n  c\n\n"
+        );
+        assert_eq!(
+            render(RenderConfig::new(80).with_max_blank_lines(0)),
+            "\na\nb\n  c\n"
+        );
+    }
+
+    #[test]
+    fn render_into() {
+        let doc = BoxDoc::<()>::text("a").append(BoxDoc::hardline().append("b").nest(4));
+        let mut buf = [0; 7];
+        assert_eq!(doc.render_into(80, &mut buf), Ok(7));
+        assert_eq!(&buf, b"a\n    b");
+
+        let mut buf = [0; 4];
+        assert_eq!(doc.render_into(80, &mut buf), Err(Overflow { written: 4 }));
+        assert_eq!(&buf, b"a\n  ");
+        assert_eq!(doc.render_into(80, &mut []), Err(Overflow { written: 0 }));
+    }
+
+    #[test]
+    fn verify_idempotent() {
+        use crate::verify::{self, IdempotenceError};
+
+        let format = |source: &str| -> Result<RcDoc<()>, String> {
+            if source.contains('!') {
+                return Err("unexpected `!`".into());
+            }
+            // Sorts the lines, except that the last one is moved to the front
+            let mut lines = source.lines().map(String::from).collect::<Vec<_>>();
+            let last = lines.pop();
+            lines.sort();
+            lines.splice(0..0, last);
+            Ok(RcDoc::intersperse(
+                lines.into_iter().map(RcDoc::text),
+                RcDoc::hardline(),
+            ))
+        };
+        let config = RenderConfig::new(80);
+
+        let doc = format("a").unwrap();
+        assert_eq!(verify::idempotent(&doc, config, format), Ok(()));
+
+        let doc = format("x\nc\nb\nd").unwrap();
+        match verify::idempotent(&doc, config, format) {
+            Err(IdempotenceError::Changed {
+                first,
+                second,
+                diff,
+            }) => {
+                assert_eq!(first, "d\nb\nc\nx");
+                assert_eq!(second, "x\nb\nc\nd");
+                assert_eq!(diff, "@@ line 1 @@\n-d\n+x\n b\n c\n-x\n+d\n");
+            }
+            result => panic!("{:?}", result),
+        }
+
+        let doc = RcDoc::<()>::text("!");
+        assert_eq!(
+            verify::idempotent(&doc, config, format),
+            Err(IdempotenceError::Parse("unexpected `!`".into()))
+        );
+    }
+
+    #[test]
+    fn preserve_whitespace() {
+        // Tokens annotated with the whitespace before them, `None` for other annotations
+        let doc = BoxDoc::text("a")
+            .annotate(Some(String::new()))
+            .append(BoxDoc::line())
+            .append(BoxDoc::text("b").annotate(Some("\t".to_string())))
+            .append(BoxDoc::line())
+            .append(BoxDoc::text("c").annotate(None))
+            .group()
+            .append(BoxDoc::hardline());
+        let render = |width| {
+            let mut s = String::new();
+            let mut out =
+                PreserveWhitespace::new(FmtWrite::new(&mut s), |ws: &Option<String>| ws.clone());
+            doc.render_raw(width, &mut out).unwrap();
+            out.finish().unwrap();
+            s
+        };
+        assert_eq!(render(80), "a\tb c\n");
+        assert_eq!(render(2), "a\nb\nc\n");
+    }
+
+    #[test]
+    fn embedded() {
+        let sql: BoxDoc<bool> = BoxDoc::text("SELECT")
+            .annotate(true)
+            .append(
+                BoxDoc::line()
+                    .append("a,")
+                    .append(BoxDoc::line())
+                    .append("b")
+                    .nest(2),
+            )
+            .append(BoxDoc::line())
+            .append(BoxDoc::text("FROM").annotate(true))
+            .append(" t")
+            .group();
+        let arena = Arena::new();
+        let doc = arena
+            .text("query(\"")
+            .append(arena.embedded("sql", usize::MAX, &sql, |&keyword| {
+                if keyword {
+                    "keyword"
+                } else {
+                    "other"
+                }
+            }))
+            .append("\")")
+            .nest(4);
+        test!(80, doc.1, "query(\"SELECT a, b FROM t\")");
+        test!(
+            20,
+            doc.1,
+            "query(\"SELECT\n         a,\n         b\n       FROM t\")"
+        );
+
+        let narrow = arena
+            .text("query(\"")
+            .append(arena.embedded("sql", 14, &sql, |_| "other"));
+        test!(
+            80,
+            narrow.1,
+            "query(\"SELECT\n         a,\n         b\n       FROM t"
+        );
+
+        struct Anns(Vec<&'static str>);
+        impl Render for Anns {
+            type Error = ();
+            fn write_str(&mut self, s: &str) -> Result<usize, ()> {
+                Ok(s.len())
+            }
+        }
+        impl RenderAnnotated<&'static str> for Anns {
+            fn push_annotation(&mut self, ann: &&'static str) -> Result<(), ()> {
+                self.0.push(ann);
+                Ok(())
+            }
+            fn pop_annotation(&mut self) -> Result<(), ()> {
+                Ok(())
+            }
+        }
+        let mut anns = Anns(vec![]);
+        doc.1.render_raw(80, &mut anns).unwrap();
+        assert_eq!(anns.0, ["sql", "keyword", "keyword"]);
+    }
+
+    #[cfg(feature = "proc-macro2")]
+    #[test]
+    fn token_stream() {
+        use crate::token_stream::token_stream;
+
+        let arena = Arena::<()>::new();
+        let format = |source: &str, width| {
+            let tokens = source.parse().unwrap();
+            token_stream(&arena, tokens).1.pretty(width).to_string()
+        };
+        assert_eq!(
+            format("impl<'a, T: Clone> From<&'a T> for Wrapper<T> {}", 80),
+            "impl<'a, T: Clone> From<&'a T> for Wrapper<T> {}"
+        );
+        assert_eq!(
+            format(
+                "#[derive(Debug)] struct Point { x: Vec<Vec<u8>>, y: i32 }",
+                80
+            ),
+            "#[derive(Debug)]\nstruct Point { x: Vec<Vec<u8>>, y: i32 }"
+        );
+        assert_eq!(
+            format(
+                "#[derive(Debug)] struct Point { x: Vec<Vec<u8>>, y: i32 }",
+                30
+            ),
+            "#[derive(Debug)]\nstruct Point {\n    x: Vec<Vec<u8>>,\n    y: i32\n}"
+        );
+        assert_eq!(
+            format("let v = xs.iter().map(|x| -*x).collect::<Vec<_>>()?;", 80),
+            "let v = xs.iter().map(|x| -*x).collect::<Vec<_>>()?;"
+        );
+        assert_eq!(
+            format("if !done && a < b { println!(\"{}\", a); }", 80),
+            "if !done && a < b {\n    println!(\"{}\", a);\n}"
+        );
+        assert_eq!(
+            format("call(first_argument, second_argument)", 20),
+            "call(\n    first_argument,\n    second_argument\n)"
+        );
+        // The lines of a multi-line literal are not indented, and it does not fit on one line
+        assert_eq!(
+            format("fn f() { g(\"a\n  b\") }", 80),
+            "fn f() {\n    g(\n        \"a\n  b\"\n    )\n}"
+        );
+    }
+
+    #[test]
+    fn detect_indent() {
+        let detect = |source| RenderConfig::new(80).with_detected(source);
+        assert_eq!(detect("").indent, Indent::Spaces(4));
+        assert_eq!(detect("a\nb\n").indent, Indent::Spaces(4));
+        // The step between levels is used rather than the indentation of each line
+        let source = "a\n   b\n      c\n         d\n   e\n\n   f\n";
+        assert_eq!(detect(source).indent, Indent::Spaces(3));
+        assert_eq!(detect(source).line_ending, LineEnding::Lf);
+        let source = "a\r\n\tb\r\n\t\tc\r\n  d\n";
+        assert_eq!(
+            RenderConfig::new(80)
+                .with_indent(Indent::Tabs(8))
+                .with_detected(source),
+            RenderConfig::new(80)
+                .with_indent(Indent::Tabs(8))
+                .with_line_ending(LineEnding::CrLf)
+        );
+
+        let doc = BoxDoc::<()>::text("{")
+            .append(
+                BoxDoc::hardline()
+                    .append("a")
+                    .append(BoxDoc::hardline().append("b").nest(6))
+                    .nest(4),
+            )
+            .append(BoxDoc::hardline())
+            .append("}");
+        let config = RenderConfig::new(80)
+            .with_indent(Indent::Tabs(4))
+            .with_line_ending(LineEnding::CrLf);
+        assert_eq!(
+            doc.pretty_with(config).to_string(),
+            "{\r\n\ta\r\n\t\t  b\r\n}"
+        );
+    }
+
+    #[test]
+    fn render_hash() {
+        use std::collections::hash_map::DefaultHasher;
+
+        let words = (0..2000).map(|i| format!("word{}", i)).collect::<Vec<_>>();
+        let doc =
+            BoxDoc::<()>::intersperse(words.iter().map(|w| w.as_str()), BoxDoc::line()).group();
+        let joined = BoxDoc::<()>::text(words.join(" "));
+        let hash = doc.render_hash(usize::MAX, DefaultHasher::new());
+        assert_eq!(hash, joined.render_hash(80, DefaultHasher::new()));
+        assert_ne!(hash, doc.render_hash(80, DefaultHasher::new()));
+
+        let mut out = Hashed::new(FmtWrite::new(String::new()), DefaultHasher::new());
+        doc.render_raw(80, &mut out).unwrap();
+        let (_, hasher) = out.into_inner();
+        assert_eq!(hasher.finish(), doc.render_hash(80, DefaultHasher::new()));
+    }
+
+    #[test]
+    fn ellipsize() {
+        let title = BoxDoc::<()>::text("a")
+            .append(BoxDoc::line())
+            .append("title")
+            .ellipsize("...");
+        let doc = BoxDoc::text("1234").append(title.clone());
+        test!(11, doc, "1234a title");
+        let doc = BoxDoc::text("12345").append(title);
+        test!(11, doc, "12345...");
+
+        let doc = BoxDoc::<()>::text("a")
+            .append(BoxDoc::hardline())
+            .append("b")
+            .ellipsize("...");
+        test!(80, doc, "...");
+    }
+
+    #[test]
+    fn map_annotations() {
+        use crate::theme::{HtmlStyled, Style, Tag, Theme};
+
+        let doc: RcDoc<'static, usize> = RcDoc::text("fn")
+            .annotate(0)
+            .append(" ")
+            .append(RcDoc::text("main").annotate(5));
+        let theme = Theme::plain().with(Tag::Keyword, Style::new().bold());
+        let render = |source: &str| {
+            let mut s = String::new();
+            let out = HtmlStyled::new(FmtWrite::new(&mut s));
+            let mut out = MapAnnotations::new(out, |&start: &usize| {
+                match source[start..].split(' ').next() {
+                    Some("fn") => theme.style(Tag::Keyword),
+                    _ => Style::new(),
+                }
+            });
+            doc.render_raw(80, &mut out).unwrap();
+            s
+        };
+        // The source only needs to live while rendering
+        let source = String::from("fn main");
+        assert_eq!(
+            render(&source),
+            "<span style=\"font-weight:bold\">fn</span> <span style=\"\">main</span>"
+        );
+    }
+
+    #[test]
+    fn map_text() {
+        let arena = Arena::<()>::new();
+        let doc = arena
+            .text("a")
+            .append(arena.column(|col| arena.as_string(col).into_doc()))
+            .annotate(())
+            .group();
+        let mapped = doc.1.map_text(&BoxAllocator, |s| format!("<{}>", s));
+        test!(mapped.1, "<a><3>");
+
+        let deep = (0..1_000_000).fold(arena.text("x"), |doc, _| arena.text("y").append(doc));
+        let mapped = deep.1.map_text(&arena, |s| s.repeat(2));
+        assert_eq!(mapped.1.pretty(80).to_string().len(), 2_000_002);
+    }
+
+    #[test]
+    fn doc_eq() {
+        let arena = Arena::<()>::new();
+        let owned = arena.text(String::from("text"));
+        let borrowed = arena.text("text");
+        assert_eq!(*owned.1, *borrowed.1);
+        assert_ne!(*borrowed.1, *arena.text("other").1);
+        assert_ne!(*arena.line().1, *arena.line_().1);
+
+        let column = arena.column(|_| arena.nil().into_doc());
+        assert_eq!(*column.1, *column.1.clone());
+        assert_ne!(*column.1, *arena.column(|_| arena.nil().into_doc()).1);
+    }
 }
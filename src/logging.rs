@@ -0,0 +1,150 @@
+//! Helpers for rendering documents in log messages.
+//!
+//! [`lazy`](fn.lazy.html) wraps a function producing a document in a `Display` implementation
+//! which only builds and renders the document when it is formatted. Logging macros such as
+//! `log::debug!` and `tracing::debug!` only format their arguments when the level is enabled, so
+//! the cost of creating the document is only paid for messages which are actually emitted.
+//!
+//! Continuation lines can be indented with [`indent`](struct.LazyDoc.html#method.indent) so that
+//! they line up under the text following the prefix of the log record. Blank lines are not
+//! indented.
+//!
+//! ```
+//! use pretty::{logging, BoxDoc};
+//!
+//! let doc = logging::lazy(|| {
+//!     BoxDoc::<()>::text("config:")
+//!         .append(BoxDoc::hardline().append("width = 80").nest(2))
+//! });
+//! let message = format!("[INFO] {}", doc.indent(7));
+//! assert_eq!(message, "[INFO] config:\n         width = 80");
+//! ```
+
+use std::{fmt, ops::Deref};
+
+use crate::{config::current_config_with_width, current_config, Doc, DocPtr, FmtWrite};
+
+/// A document which is created and rendered each time it is formatted.
+#[derive(Clone, Copy)]
+pub struct LazyDoc<F> {
+    f: F,
+    width: Option<usize>,
+    indent: usize,
+}
+
+/// Creates a `Display` implementation which calls `f` and renders the document it returns when
+/// formatted. The width is taken from the format specifier or the
+/// [`current_config`](../fn.current_config.html), which also provides the other options.
+pub fn lazy<F>(f: F) -> LazyDoc<F> {
+    LazyDoc {
+        f,
+        width: None,
+        indent: 0,
+    }
+}
+
+impl<F> LazyDoc<F> {
+    /// Renders the document at `width` instead of the width of the format specifier or the
+    /// current configuration.
+    pub fn width(self, width: usize) -> Self {
+        LazyDoc {
+            width: Some(width),
+            ..self
+        }
+    }
+
+    /// Indents every line after the first by `indent` spaces, the indentation is subtracted from
+    /// the width available to the document.
+    pub fn indent(self, indent: usize) -> Self {
+        LazyDoc { indent, ..self }
+    }
+}
+
+/// Inserts the indentation after each line break, unless the line is empty.
+struct Indented<'f, 'b> {
+    upstream: &'f mut fmt::Formatter<'b>,
+    indent: usize,
+    pending: bool,
+}
+
+impl fmt::Write for Indented<'_, '_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for (i, line) in s.split('\n').enumerate() {
+            if i != 0 {
+                self.upstream.write_str("\n")?;
+                self.pending = true;
+            }
+            if self.pending && !line.is_empty() {
+                write!(self.upstream, "{:1$}", "", self.indent)?;
+                self.pending = false;
+            }
+            self.upstream.write_str(line)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, F, D, T, A> fmt::Display for LazyDoc<F>
+where
+    F: Fn() -> D,
+    D: Deref<Target = Doc<'a, T, A>>,
+    T: DocPtr<'a, A> + 'a,
+    A: 'a,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut config = match self.width.or_else(|| f.width()) {
+            Some(width) => current_config_with_width(width),
+            None => current_config(),
+        };
+        let doc = (self.f)();
+        if self.indent == 0 {
+            return doc.render_raw_with(config, &mut FmtWrite::new(f));
+        }
+        config.width = config.width.saturating_sub(self.indent);
+        config.max_width = config
+            .max_width
+            .map(|max_width| max_width.saturating_sub(self.indent));
+        doc.render_raw_with(
+            config,
+            &mut FmtWrite::new(&mut Indented {
+                upstream: f,
+                indent: self.indent,
+                pending: false,
+            }),
+        )
+    }
+}
+
+/// Logs the document returned by `f` with `log` at `level`. `f` is only called if the level is
+/// enabled.
+///
+/// ```
+/// use pretty::{log::Level, log_doc, BoxDoc};
+///
+/// log_doc!(Level::Debug, || BoxDoc::<()>::text("expensive"));
+/// ```
+#[cfg(feature = "log")]
+#[macro_export]
+macro_rules! log_doc {
+    ($level: expr, $f: expr) => {
+        $crate::log::log!($level, "{}", $crate::logging::lazy($f))
+    };
+}
+
+#[cfg(feature = "tracing")]
+impl<F> LazyDoc<F> {
+    /// Returns a `tracing` field value which renders the document when the event is recorded.
+    ///
+    /// ```
+    /// use pretty::{logging, tracing, BoxDoc};
+    ///
+    /// let plan = logging::lazy(|| BoxDoc::<()>::text("scan").append(BoxDoc::line()).append("sort"));
+    /// tracing::debug!(plan = plan.value(), "planned query");
+    /// ```
+    pub fn value(&self) -> tracing::field::DisplayValue<&Self>
+    where
+        Self: fmt::Display,
+    {
+        tracing::field::display(self)
+    }
+}
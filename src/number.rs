@@ -0,0 +1,130 @@
+//! Formatting of numbers with locale specific digit grouping and decimal points.
+//!
+//! A [`NumberFormatter`](trait.NumberFormatter.html) rewrites the output of a number's `Display`
+//! implementation, it is used by [`DocAllocator::number`](../trait.DocAllocator.html#method.number).
+//!
+//! ```
+//! use pretty::number::Separators;
+//! use pretty::{Arena, DocAllocator};
+//!
+//! let arena = Arena::<()>::new();
+//! let doc = arena
+//!     .number(&Separators::ENGLISH, 1234567.25)
+//!     .append(" / ")
+//!     .append(arena.number(&Separators::GERMAN, 1234567.25));
+//! assert_eq!(doc.1.pretty(80).to_string(), "1,234,567.25 / 1.234.567,25");
+//! ```
+
+/// Decides how the digits of a formatted number are grouped and which decimal point is used.
+///
+/// Only `group_separator`, `group_size` and `decimal_point` need to be implemented in most
+/// cases, `format` can be overridden for formats which can not be expressed with those.
+pub trait NumberFormatter {
+    /// The separator placed between groups of digits in the integer part, `None` disables
+    /// grouping.
+    fn group_separator(&self) -> Option<&str>;
+
+    /// The number of digits in each group.
+    fn group_size(&self) -> usize {
+        3
+    }
+
+    /// The separator between the integer and the fractional part.
+    fn decimal_point(&self) -> &str;
+
+    /// Reformats `number`, the output of a `Display` implementation such as `-1234.5`. Any text
+    /// which does not follow the leading digits and the fractional part is kept as is.
+    fn format(&self, number: &str) -> String {
+        let sign_len = number.len() - number.trim_start_matches(&['-', '+'][..]).len();
+        let (sign, rest) = number.split_at(sign_len);
+        let digits_len = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let (digits, rest) = rest.split_at(digits_len);
+
+        let mut out = String::with_capacity(number.len() + number.len() / 2);
+        out.push_str(sign);
+        match self.group_separator() {
+            Some(separator) if self.group_size() > 0 => {
+                let size = self.group_size();
+                for (i, digit) in digits.char_indices() {
+                    if i != 0 && (digits.len() - i) % size == 0 {
+                        out.push_str(separator);
+                    }
+                    out.push(digit);
+                }
+            }
+            _ => out.push_str(digits),
+        }
+        match rest.strip_prefix('.') {
+            Some(fraction) if !digits.is_empty() => {
+                out.push_str(self.decimal_point());
+                out.push_str(fraction);
+            }
+            _ => out.push_str(rest),
+        }
+        out
+    }
+}
+
+impl<F> NumberFormatter for &'_ F
+where
+    F: ?Sized + NumberFormatter,
+{
+    fn group_separator(&self) -> Option<&str> {
+        (**self).group_separator()
+    }
+
+    fn group_size(&self) -> usize {
+        (**self).group_size()
+    }
+
+    fn decimal_point(&self) -> &str {
+        (**self).decimal_point()
+    }
+
+    fn format(&self, number: &str) -> String {
+        (**self).format(number)
+    }
+}
+
+/// A `NumberFormatter` using fixed separators.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Separators<'s> {
+    pub group: Option<&'s str>,
+    pub decimal_point: &'s str,
+}
+
+impl<'s> Separators<'s> {
+    /// No grouping and `.` as the decimal point, the same output as `Display`.
+    pub const PLAIN: Separators<'static> = Separators::new(None, ".");
+    /// `1,234.5`
+    pub const ENGLISH: Separators<'static> = Separators::new(Some(","), ".");
+    /// `1.234,5`
+    pub const GERMAN: Separators<'static> = Separators::new(Some("."), ",");
+    /// `1'234.5`
+    pub const SWISS: Separators<'static> = Separators::new(Some("'"), ".");
+
+    pub const fn new(group: Option<&'s str>, decimal_point: &'s str) -> Self {
+        Separators {
+            group,
+            decimal_point,
+        }
+    }
+}
+
+impl Default for Separators<'_> {
+    fn default() -> Self {
+        Separators::PLAIN
+    }
+}
+
+impl NumberFormatter for Separators<'_> {
+    fn group_separator(&self) -> Option<&str> {
+        self.group
+    }
+
+    fn decimal_point(&self) -> &str {
+        self.decimal_point
+    }
+}
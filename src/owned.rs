@@ -0,0 +1,222 @@
+#[cfg(any(feature = "wasm-bindgen", feature = "pyo3"))]
+use std::rc::Rc;
+use std::{fmt, marker::PhantomData, ops::Deref};
+
+use crate::{deep, Doc, DocPtr};
+
+enum Void {}
+
+/// The function type of `OwnedDoc`. Owned documents can not contain `column` or `nesting`
+/// functions so no value of this type can ever be created.
+pub struct NoFn<T, Arg>(Void, PhantomData<fn(Arg) -> T>);
+
+impl<T, Arg> Clone for NoFn<T, Arg> {
+    fn clone(&self) -> Self {
+        match self.0 {}
+    }
+}
+
+impl<T, Arg> Deref for NoFn<T, Arg> {
+    type Target = dyn Fn(Arg) -> T;
+
+    fn deref(&self) -> &Self::Target {
+        match self.0 {}
+    }
+}
+
+/// A self-contained document which does not borrow from anything.
+///
+/// All text is owned and children are boxed, which makes it possible to store `OwnedDoc` in
+/// structs, cache it or send it to other threads (given that the annotations allow it). Since
+/// `column` and `nesting` functions may borrow arbitrary data they can not be part of an
+/// `OwnedDoc`, and neither can documents built with `align`, `hang`, `indent` or `width`, which
+/// are implemented with them. Use `nest` for indentation which does not depend on the column.
+///
+/// ```
+/// use pretty::{BoxDoc, Doc, OwnedDoc};
+///
+/// fn make() -> OwnedDoc {
+///     let source = String::from("hello world");
+///     let doc = BoxDoc::<()>::intersperse(source.split(' '), Doc::line()).group();
+///     doc.to_owned_doc().unwrap()
+/// }
+/// let doc = std::thread::spawn(make).join().unwrap();
+/// assert_eq!(doc.pretty(5).to_string(), "hello\nworld");
+/// ```
+pub struct OwnedDoc<A: 'static = ()>(Box<Doc<'static, OwnedDoc<A>, A>>);
+
+// `Clone` and `Drop` do not recurse so that deep documents do not overflow the stack
+impl<A> Clone for OwnedDoc<A>
+where
+    A: Clone + 'static,
+{
+    fn clone(&self) -> Self {
+        deep::clone(&self.0)
+    }
+}
+
+impl<A: 'static> Drop for OwnedDoc<A> {
+    fn drop(&mut self) {
+        let mut stack = vec![];
+        deep::take_children(&mut self.0, &mut stack);
+        while let Some(mut doc) = stack.pop() {
+            deep::take_children(&mut doc.0, &mut stack);
+        }
+    }
+}
+
+impl<A> fmt::Debug for OwnedDoc<A>
+where
+    A: fmt::Debug + 'static,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<A: 'static> OwnedDoc<A> {
+    pub fn new(doc: Doc<'static, OwnedDoc<A>, A>) -> OwnedDoc<A> {
+        OwnedDoc(Box::new(doc))
+    }
+}
+
+impl<A: 'static> From<Doc<'static, OwnedDoc<A>, A>> for OwnedDoc<A> {
+    fn from(doc: Doc<'static, OwnedDoc<A>, A>) -> OwnedDoc<A> {
+        OwnedDoc::new(doc)
+    }
+}
+
+impl<A: 'static> Deref for OwnedDoc<A> {
+    type Target = Doc<'static, OwnedDoc<A>, A>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<A> DocPtr<'static, A> for OwnedDoc<A>
+where
+    A: 'static,
+{
+    type ColumnFn = NoFn<Self, usize>;
+    type WidthFn = NoFn<Self, isize>;
+}
+
+impl<'a, T, A> Doc<'a, T, A>
+where
+    T: DocPtr<'a, A>,
+{
+    /// Copies this document into an `OwnedDoc`. Documents of any depth can be copied without
+    /// overflowing the stack.
+    ///
+    /// Returns `None` if the document contains `column` or `nesting` functions, which can not be
+    /// copied. These are also used by `align`, `hang`, `indent` and `width`, so documents built
+    /// with them must be kept as a `BoxDoc` or `RcDoc` instead.
+    pub fn to_owned_doc(&self) -> Option<OwnedDoc<A>>
+    where
+        A: Clone + 'static,
+    {
+        let leaf = |doc: &Doc<'a, T, A>| {
+            Ok(Some(OwnedDoc::new(match *doc {
+                Doc::Nil => Doc::Nil,
+                Doc::Line => Doc::Line,
+                Doc::AlignmentMark => Doc::AlignmentMark,
+                Doc::OwnedText(ref s) => Doc::OwnedText(s.clone()),
+                Doc::BorrowedText(s) => Doc::OwnedText(s.into()),
+                Doc::SmallText(s) => Doc::SmallText(s),
+                Doc::Column(_) | Doc::Nesting(_) => return Err(()),
+                _ => return Ok(None),
+            })))
+        };
+        deep::fold(self, leaf, |doc, children| {
+            OwnedDoc::new(deep::rebuild(doc, children, A::clone))
+        })
+        .ok()
+    }
+}
+
+/// A document whose children are reference counted, so that it can be cloned in constant time.
+///
+/// The language bindings expose documents as immutable handles which may be combined any number
+/// of times, copying an `RcDoc` for each combinator would make building a document quadratic.
+#[cfg(any(feature = "wasm-bindgen", feature = "pyo3"))]
+#[derive(Clone)]
+pub(crate) struct SharedDoc(Rc<Doc<'static, SharedDoc, ()>>);
+
+#[cfg(any(feature = "wasm-bindgen", feature = "pyo3"))]
+impl Drop for SharedDoc {
+    fn drop(&mut self) {
+        // Only the children of nodes which are not shared with another handle are released here
+        let mut stack = vec![];
+        if let Some(doc) = Rc::get_mut(&mut self.0) {
+            deep::take_children(doc, &mut stack);
+        }
+        while let Some(mut doc) = stack.pop() {
+            if let Some(doc) = Rc::get_mut(&mut doc.0) {
+                deep::take_children(doc, &mut stack);
+            }
+        }
+    }
+}
+
+#[cfg(any(feature = "wasm-bindgen", feature = "pyo3"))]
+impl From<Doc<'static, SharedDoc, ()>> for SharedDoc {
+    fn from(doc: Doc<'static, SharedDoc, ()>) -> SharedDoc {
+        SharedDoc(Rc::new(doc))
+    }
+}
+
+#[cfg(any(feature = "wasm-bindgen", feature = "pyo3"))]
+impl Deref for SharedDoc {
+    type Target = Doc<'static, SharedDoc, ()>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[cfg(any(feature = "wasm-bindgen", feature = "pyo3"))]
+impl DocPtr<'static, ()> for SharedDoc {
+    type ColumnFn = NoFn<Self, usize>;
+    type WidthFn = NoFn<Self, isize>;
+}
+
+#[cfg(any(feature = "wasm-bindgen", feature = "pyo3"))]
+impl SharedDoc {
+    /// A line break which is laid out as `flat` when the enclosing group is flattened.
+    pub(crate) fn line(flat: Doc<'static, SharedDoc, ()>) -> SharedDoc {
+        Doc::FlatAlt(Doc::hardline().into(), flat.into()).into()
+    }
+
+    /// `text` with each line break replaced by a `hardline`.
+    pub(crate) fn text(text: &str) -> SharedDoc {
+        let mut doc = SharedDoc::from(Doc::nil());
+        for (i, line) in text.split('\n').enumerate() {
+            if i != 0 {
+                doc = doc.append(&Doc::hardline().into());
+            }
+            doc = doc.append(&Doc::text(line.to_string()).into());
+        }
+        doc
+    }
+
+    pub(crate) fn append(&self, that: &SharedDoc) -> SharedDoc {
+        match (&**self, &**that) {
+            (Doc::Nil, _) => that.clone(),
+            (_, Doc::Nil) => self.clone(),
+            _ => Doc::Append(self.clone(), that.clone()).into(),
+        }
+    }
+
+    pub(crate) fn group(&self) -> SharedDoc {
+        Doc::Group(self.clone()).into()
+    }
+
+    pub(crate) fn nest(&self, offset: isize) -> SharedDoc {
+        match **self {
+            Doc::Nil => self.clone(),
+            _ if offset == 0 => self.clone(),
+            _ => Doc::Nest(offset, self.clone()).into(),
+        }
+    }
+}
@@ -0,0 +1,103 @@
+//! Python bindings built with `pyo3`.
+//!
+//! The `pretty` Python module exposes an immutable `Doc` class whose combinators return new
+//! documents. A loadable extension module can be built with `maturin` or by building this crate
+//! as a `cdylib` with the `pyo3` feature.
+//!
+//! ```python
+//! from pretty import Doc
+//!
+//! doc = (Doc.text("hello") + (Doc.line() + Doc.text("world")).nest(2)).group()
+//! print(doc.render(80))  # hello world
+//! print(doc.render(8))   # hello\n  world
+//! ```
+
+use pyo3::prelude::*;
+
+use crate::{owned::SharedDoc, Doc};
+
+/// An immutable document. Documents share their parts, so combining them takes constant time
+/// however large they are.
+#[pyclass(name = "Doc", module = "pretty", unsendable)]
+pub struct PyDoc(SharedDoc);
+
+#[pymethods]
+impl PyDoc {
+    /// The empty document.
+    #[staticmethod]
+    pub fn nil() -> PyDoc {
+        PyDoc(Doc::nil().into())
+    }
+
+    /// The given text, with each line break laid out as a hard line break.
+    #[staticmethod]
+    pub fn text(text: String) -> PyDoc {
+        PyDoc(SharedDoc::text(&text))
+    }
+
+    /// A single space.
+    #[staticmethod]
+    pub fn space() -> PyDoc {
+        PyDoc(Doc::space().into())
+    }
+
+    /// A line break which is never flattened.
+    #[staticmethod]
+    pub fn hardline() -> PyDoc {
+        PyDoc(Doc::hardline().into())
+    }
+
+    /// A line break, or a space when the enclosing group is flattened.
+    #[staticmethod]
+    pub fn line() -> PyDoc {
+        PyDoc(SharedDoc::line(Doc::space()))
+    }
+
+    /// A line break, or nothing when the enclosing group is flattened.
+    #[staticmethod]
+    pub fn line_() -> PyDoc {
+        PyDoc(SharedDoc::line(Doc::nil()))
+    }
+
+    /// A space if the rest of the line fits, otherwise a line break.
+    #[staticmethod]
+    pub fn softline() -> PyDoc {
+        PyDoc(SharedDoc::line(Doc::space()).group())
+    }
+
+    /// Nothing if the rest of the line fits, otherwise a line break.
+    #[staticmethod]
+    pub fn softline_() -> PyDoc {
+        PyDoc(SharedDoc::line(Doc::nil()).group())
+    }
+
+    pub fn append(&self, other: &PyDoc) -> PyDoc {
+        PyDoc(self.0.append(&other.0))
+    }
+
+    fn __add__(&self, other: &PyDoc) -> PyDoc {
+        self.append(other)
+    }
+
+    /// Lays out the document on a single line if it fits.
+    pub fn group(&self) -> PyDoc {
+        PyDoc(self.0.group())
+    }
+
+    /// Increases the indentation of the line breaks in the document by `offset`.
+    pub fn nest(&self, offset: isize) -> PyDoc {
+        PyDoc(self.0.nest(offset))
+    }
+
+    /// Renders the document with lines of at most `width` columns where possible.
+    pub fn render(&self, width: usize) -> String {
+        self.0.pretty(width).to_string()
+    }
+}
+
+/// The `pretty` Python module.
+#[pymodule]
+#[pyo3(name = "pretty")]
+pub fn pymodule(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyDoc>()
+}
@@ -1,10 +1,14 @@
 use std::cmp;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
 use std::fmt;
+use std::hash::Hasher;
 use std::io;
+use std::time::{Duration, Instant};
 #[cfg(feature = "termcolor")]
 use termcolor::{ColorSpec, WriteColor};
 
-use crate::{Doc, DocPtr};
+use crate::{Doc, DocPtr, Indent, LineEnding, RenderConfig};
 
 /// Trait representing the operations necessary to render a document
 pub trait Render {
@@ -73,10 +77,335 @@ where
     }
 }
 
+/// The encoding of the bytes written by [`EncodedWrite`](struct.EncodedWrite.html).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    /// UTF-16 in little endian byte order, preceded by a byte order mark.
+    Utf16Le,
+    /// ISO-8859-1, characters outside of it are replaced by `?`.
+    Latin1,
+}
+
+impl Encoding {
+    /// The byte order mark written before the document.
+    pub fn bom(self) -> &'static [u8] {
+        match self {
+            Encoding::Utf16Le => b"\xFF\xFE",
+            Encoding::Utf8 | Encoding::Latin1 => b"",
+        }
+    }
+}
+
+/// Writes to something implementing `std::io::Write`, encoding the text with `Encoding`
+pub struct EncodedWrite<W> {
+    upstream: W,
+    encoding: Encoding,
+    buf: Vec<u8>,
+}
+
+impl<W> EncodedWrite<W> {
+    pub fn new(upstream: W, encoding: Encoding) -> EncodedWrite<W> {
+        EncodedWrite {
+            upstream,
+            encoding,
+            buf: Vec::new(),
+        }
+    }
+}
+
+impl<W> Render for EncodedWrite<W>
+where
+    W: io::Write,
+{
+    type Error = io::Error;
+
+    fn write_str(&mut self, s: &str) -> io::Result<usize> {
+        self.write_str_all(s).map(|_| s.len())
+    }
+
+    fn write_str_all(&mut self, s: &str) -> io::Result<()> {
+        match self.encoding {
+            Encoding::Utf8 => return self.upstream.write_all(s.as_bytes()),
+            Encoding::Utf16Le => {
+                self.buf.clear();
+                self.buf
+                    .extend(s.encode_utf16().flat_map(|unit| unit.to_le_bytes()));
+            }
+            Encoding::Latin1 => {
+                self.buf.clear();
+                self.buf
+                    .extend(s.chars().map(|c| u8::try_from(c).unwrap_or(b'?')));
+            }
+        }
+        self.upstream.write_all(&self.buf)
+    }
+}
+
+impl<A, W> RenderAnnotated<A> for EncodedWrite<W>
+where
+    W: io::Write,
+{
+    fn push_annotation(&mut self, _: &A) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn pop_annotation(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// The error of [`Doc::render_into`](enum.Doc.html#method.render_into) when the rendered
+/// document does not fit in the buffer.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Overflow {
+    /// The number of bytes which were written to the buffer. The written bytes end at a character
+    /// boundary so they are valid UTF-8.
+    pub written: usize,
+}
+
+impl fmt::Display for Overflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the document did not fit in the buffer after {} bytes",
+            self.written
+        )
+    }
+}
+
+impl std::error::Error for Overflow {}
+
+/// Limits on the resources used to render a document, see
+/// [`Doc::render_raw_limited`](enum.Doc.html#method.render_raw_limited). No limit is set by
+/// default.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct Limits {
+    /// The number of document nodes the layout may visit, including the nodes visited while
+    /// checking whether groups fit.
+    pub max_nodes: Option<usize>,
+    /// The number of bytes which may be written.
+    pub max_bytes: Option<usize>,
+    /// The time the layout may take. Ignored on platforms without a clock, such as
+    /// `wasm32-unknown-unknown`.
+    pub max_time: Option<Duration>,
+}
+
+impl Limits {
+    pub fn new() -> Self {
+        Limits::default()
+    }
+
+    pub const fn with_max_nodes(mut self, max_nodes: usize) -> Self {
+        self.max_nodes = Some(max_nodes);
+        self
+    }
+
+    pub const fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    pub const fn with_max_time(mut self, max_time: Duration) -> Self {
+        self.max_time = Some(max_time);
+        self
+    }
+}
+
+/// A limit of [`Limits`](struct.Limits.html).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Limit {
+    Nodes,
+    Bytes,
+    Time,
+}
+
+/// The error of [`Doc::render_raw_limited`](enum.Doc.html#method.render_raw_limited).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LimitError<E> {
+    /// Rendering was aborted as it exceeded a limit. The output written so far is left as is.
+    Exceeded(Limit),
+    /// The renderer failed.
+    Render(E),
+}
+
+impl<E> fmt::Display for LimitError<E>
+where
+    E: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitError::Exceeded(Limit::Nodes) => write!(f, "the document has too many nodes"),
+            LimitError::Exceeded(Limit::Bytes) => write!(f, "the output is too long"),
+            LimitError::Exceeded(Limit::Time) => write!(f, "rendering took too long"),
+            LimitError::Render(err) => err.fmt(f),
+        }
+    }
+}
+
+impl<E> std::error::Error for LimitError<E> where E: fmt::Debug + fmt::Display {}
+
+/// The nodes and time left of [`Limits`](struct.Limits.html).
+struct Budget {
+    nodes: usize,
+    deadline: Option<Instant>,
+    /// The nodes left until the clock is checked again
+    until_clock: usize,
+    exceeded: Option<Limit>,
+}
+
+impl Budget {
+    /// The clock is only checked once per this many nodes as it is comparatively slow.
+    const CLOCK_INTERVAL: usize = 1024;
+
+    fn new(limits: Limits) -> Self {
+        Budget {
+            nodes: limits.max_nodes.unwrap_or(usize::MAX),
+            deadline: limits.max_time.and_then(Budget::deadline),
+            until_clock: Budget::CLOCK_INTERVAL,
+            exceeded: None,
+        }
+    }
+
+    /// The instant `time` from now, if there is a clock to measure it with and it can be
+    /// represented.
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    fn deadline(time: Duration) -> Option<Instant> {
+        Instant::now().checked_add(time)
+    }
+
+    /// `Instant::now` panics on this target
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+    fn deadline(_: Duration) -> Option<Instant> {
+        None
+    }
+
+    /// Spends a node, returning `false` once a limit is exceeded.
+    fn visit(&mut self) -> bool {
+        if self.exceeded.is_some() {
+            return false;
+        }
+        if self.nodes == 0 {
+            self.exceeded = Some(Limit::Nodes);
+            return false;
+        }
+        self.nodes -= 1;
+        if let Some(deadline) = self.deadline {
+            self.until_clock -= 1;
+            if self.until_clock == 0 {
+                self.until_clock = Budget::CLOCK_INTERVAL;
+                if Instant::now() >= deadline {
+                    self.exceeded = Some(Limit::Time);
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+/// Writes to a byte slice, failing once it is full
+pub(crate) struct SliceWrite<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+}
+
+impl<'b> SliceWrite<'b> {
+    pub(crate) fn new(buf: &'b mut [u8]) -> SliceWrite<'b> {
+        SliceWrite { buf, len: 0 }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+}
+
+impl Render for SliceWrite<'_> {
+    type Error = Overflow;
+
+    fn write_str(&mut self, s: &str) -> Result<usize, Overflow> {
+        self.write_str_all(s).map(|_| s.len())
+    }
+
+    fn write_str_all(&mut self, s: &str) -> Result<(), Overflow> {
+        let available = self.buf.len() - self.len;
+        let mut count = cmp::min(available, s.len());
+        while !s.is_char_boundary(count) {
+            count -= 1;
+        }
+        self.buf[self.len..self.len + count].copy_from_slice(&s.as_bytes()[..count]);
+        self.len += count;
+        if count < s.len() {
+            return Err(Overflow { written: self.len });
+        }
+        Ok(())
+    }
+}
+
+impl<A> RenderAnnotated<A> for SliceWrite<'_> {
+    fn push_annotation(&mut self, _: &A) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn pop_annotation(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
 /// Trait representing the operations necessary to write an annotated document.
 pub trait RenderAnnotated<A>: Render {
     fn push_annotation(&mut self, annotation: &A) -> Result<(), Self::Error>;
     fn pop_annotation(&mut self) -> Result<(), Self::Error>;
+
+    /// Called before the next command whenever the mode in which the content is laid out
+    /// changes. Rendering starts in `Mode::Break`, so the first call is made when the content of
+    /// a flattened group is reached. Does nothing by default.
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator, Mode, Render, RenderAnnotated};
+    ///
+    /// // Marks content which was laid out on a single line
+    /// struct Marker(String);
+    ///
+    /// impl Render for Marker {
+    ///     type Error = ();
+    ///
+    ///     fn write_str(&mut self, s: &str) -> Result<usize, ()> {
+    ///         self.0.push_str(s);
+    ///         Ok(s.len())
+    ///     }
+    /// }
+    ///
+    /// impl RenderAnnotated<()> for Marker {
+    ///     fn push_annotation(&mut self, _: &()) -> Result<(), ()> {
+    ///         Ok(())
+    ///     }
+    ///
+    ///     fn pop_annotation(&mut self) -> Result<(), ()> {
+    ///         Ok(())
+    ///     }
+    ///
+    ///     fn set_mode(&mut self, mode: Mode) -> Result<(), ()> {
+    ///         self.write_str_all(if mode == Mode::Flat { "<" } else { ">" })
+    ///     }
+    /// }
+    ///
+    /// let arena = Arena::new();
+    /// let doc = arena
+    ///     .text("a")
+    ///     .append(arena.hardline())
+    ///     .append(arena.text("b").append(arena.line()).append("c").group())
+    ///     .append(arena.hardline())
+    ///     .append("d");
+    /// let mut out = Marker(String::new());
+    /// doc.1.render_raw(80, &mut out).unwrap();
+    /// assert_eq!(out.0, "a\n<b c>\nd");
+    /// ```
+    fn set_mode(&mut self, mode: Mode) -> Result<(), Self::Error> {
+        let _ = mode;
+        Ok(())
+    }
 }
 
 impl<A, W> RenderAnnotated<A> for IoWrite<W>
@@ -87,38 +416,969 @@ where
         Ok(())
     }
 
-    fn pop_annotation(&mut self) -> Result<(), Self::Error> {
+    fn pop_annotation(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl<A, W> RenderAnnotated<A> for FmtWrite<W>
+where
+    W: fmt::Write,
+{
+    fn push_annotation(&mut self, _: &A) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn pop_annotation(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Wraps the text of selected annotations in the Unicode bidi isolation characters FSI (U+2068)
+/// and PDI (U+2069).
+///
+/// Isolating right-to-left segments such as Arabic or Hebrew identifiers keeps terminals from
+/// reordering the punctuation around them. The isolation characters are inserted while
+/// rendering, after the layout has been decided, so they never count against the page width.
+/// Isolation characters which are already part of the text of a document, such as text which
+/// was isolated by the program it came from, take up no columns in the layout either.
+pub struct BidiIsolate<W, F> {
+    isolated: Vec<bool>,
+    upstream: W,
+    predicate: F,
+}
+
+impl<W, F> BidiIsolate<W, F> {
+    /// Isolates the text of every annotation for which `predicate` returns `true` before
+    /// passing it on to `upstream`.
+    pub fn new(upstream: W, predicate: F) -> BidiIsolate<W, F> {
+        BidiIsolate {
+            isolated: Vec::new(),
+            upstream,
+            predicate,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.upstream
+    }
+}
+
+impl<W, F> Render for BidiIsolate<W, F>
+where
+    W: Render,
+{
+    type Error = W::Error;
+
+    fn write_str(&mut self, s: &str) -> Result<usize, W::Error> {
+        self.upstream.write_str(s)
+    }
+
+    fn write_str_all(&mut self, s: &str) -> Result<(), W::Error> {
+        self.upstream.write_str_all(s)
+    }
+}
+
+impl<A, W, F> RenderAnnotated<A> for BidiIsolate<W, F>
+where
+    W: RenderAnnotated<A>,
+    F: FnMut(&A) -> bool,
+{
+    fn push_annotation(&mut self, annotation: &A) -> Result<(), Self::Error> {
+        let isolate = (self.predicate)(annotation);
+        self.isolated.push(isolate);
+        if isolate {
+            self.upstream.write_str_all("\u{2068}")?;
+        }
+        self.upstream.push_annotation(annotation)
+    }
+
+    fn set_mode(&mut self, mode: Mode) -> Result<(), Self::Error> {
+        self.upstream.set_mode(mode)
+    }
+
+    fn pop_annotation(&mut self) -> Result<(), Self::Error> {
+        self.upstream.pop_annotation()?;
+        if self.isolated.pop() == Some(true) {
+            self.upstream.write_str_all("\u{2069}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A table mapping keys to annotations, see [`Keyed`](struct.Keyed.html).
+pub trait AnnotationTable<K> {
+    type Value;
+
+    fn get(&self, key: &K) -> Option<&Self::Value>;
+}
+
+impl<V> AnnotationTable<usize> for [V] {
+    type Value = V;
+
+    fn get(&self, key: &usize) -> Option<&V> {
+        <[V]>::get(self, *key)
+    }
+}
+
+impl<V> AnnotationTable<usize> for Vec<V> {
+    type Value = V;
+
+    fn get(&self, key: &usize) -> Option<&V> {
+        <[V]>::get(self, *key)
+    }
+}
+
+impl<K, V, S> AnnotationTable<K> for std::collections::HashMap<K, V, S>
+where
+    K: Eq + std::hash::Hash,
+    S: std::hash::BuildHasher,
+{
+    type Value = V;
+
+    fn get(&self, key: &K) -> Option<&V> {
+        std::collections::HashMap::get(self, key)
+    }
+}
+
+/// Resolves annotations which are keys into `table` before passing them on to `upstream`.
+///
+/// Documents annotated with small keys instead of full styles stay small and can be rendered
+/// with different tables, such as a light and a dark color scheme, without being rebuilt. Keys
+/// which are missing from the table are ignored.
+///
+/// ```
+/// use pretty::{Arena, DocAllocator, Keyed, Render, RenderAnnotated};
+///
+/// struct Html(String);
+///
+/// impl Render for Html {
+///     type Error = ();
+///
+///     fn write_str(&mut self, s: &str) -> Result<usize, ()> {
+///         self.0.push_str(s);
+///         Ok(s.len())
+///     }
+/// }
+///
+/// impl RenderAnnotated<&'static str> for Html {
+///     fn push_annotation(&mut self, class: &&'static str) -> Result<(), ()> {
+///         self.write_str_all(&format!("<span class=\"{}\">", class))
+///     }
+///
+///     fn pop_annotation(&mut self) -> Result<(), ()> {
+///         self.write_str_all("</span>")
+///     }
+/// }
+///
+/// const KEYWORD: usize = 0;
+///
+/// let arena = Arena::new();
+/// let doc = arena.text("fn").annotate(KEYWORD).append(" main");
+///
+/// let mut out = Keyed::new(Html(String::new()), &["kw"][..]);
+/// doc.1.render_raw(80, &mut out).unwrap();
+/// assert_eq!(out.into_inner().0, "<span class=\"kw\">fn</span> main");
+/// ```
+pub struct Keyed<'t, W, T: ?Sized> {
+    upstream: W,
+    table: &'t T,
+    resolved: Vec<bool>,
+}
+
+impl<'t, W, T: ?Sized> Keyed<'t, W, T> {
+    pub fn new(upstream: W, table: &'t T) -> Keyed<'t, W, T> {
+        Keyed {
+            upstream,
+            table,
+            resolved: Vec::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.upstream
+    }
+}
+
+impl<W, T> Render for Keyed<'_, W, T>
+where
+    W: Render,
+    T: ?Sized,
+{
+    type Error = W::Error;
+
+    fn write_str(&mut self, s: &str) -> Result<usize, W::Error> {
+        self.upstream.write_str(s)
+    }
+
+    fn write_str_all(&mut self, s: &str) -> Result<(), W::Error> {
+        self.upstream.write_str_all(s)
+    }
+}
+
+impl<W, T, K> RenderAnnotated<K> for Keyed<'_, W, T>
+where
+    T: ?Sized + AnnotationTable<K>,
+    W: RenderAnnotated<T::Value>,
+{
+    fn push_annotation(&mut self, key: &K) -> Result<(), Self::Error> {
+        match self.table.get(key) {
+            Some(annotation) => {
+                self.resolved.push(true);
+                self.upstream.push_annotation(annotation)
+            }
+            None => {
+                self.resolved.push(false);
+                Ok(())
+            }
+        }
+    }
+
+    fn pop_annotation(&mut self) -> Result<(), Self::Error> {
+        if self.resolved.pop() == Some(true) {
+            self.upstream.pop_annotation()?;
+        }
+        Ok(())
+    }
+
+    fn set_mode(&mut self, mode: Mode) -> Result<(), Self::Error> {
+        self.upstream.set_mode(mode)
+    }
+}
+
+/// Converts each annotation with `f` before passing it on to `upstream`.
+///
+/// Since the converted annotations only exist while rendering, they can borrow data which lives
+/// shorter than the document. A document annotated with spans can be kept around, in an `RcDoc`
+/// or a long lived arena, and still be rendered to a sink which receives slices of a source file
+/// that is only loaded for rendering.
+///
+/// ```
+/// use std::ops::Range;
+/// use pretty::{MapAnnotations, RcDoc, Render, RenderAnnotated};
+///
+/// // Receives the source text of each annotated span
+/// struct Spans<'s>(Vec<&'s str>);
+///
+/// impl Render for Spans<'_> {
+///     type Error = ();
+///
+///     fn write_str(&mut self, s: &str) -> Result<usize, ()> {
+///         Ok(s.len())
+///     }
+/// }
+///
+/// impl<'s> RenderAnnotated<&'s str> for Spans<'s> {
+///     fn push_annotation(&mut self, source: &&'s str) -> Result<(), ()> {
+///         self.0.push(source);
+///         Ok(())
+///     }
+///
+///     fn pop_annotation(&mut self) -> Result<(), ()> {
+///         Ok(())
+///     }
+/// }
+///
+/// let doc: RcDoc<Range<usize>> = RcDoc::text("x").annotate(4..5).append(" = 1");
+///
+/// let source = String::from("let x = 1;");
+/// let mut out = MapAnnotations::new(Spans(vec![]), |span: &Range<usize>| &source[span.clone()]);
+/// doc.render_raw(80, &mut out).unwrap();
+/// assert_eq!(out.into_inner().0, ["x"]);
+/// ```
+pub struct MapAnnotations<W, F> {
+    upstream: W,
+    f: F,
+}
+
+impl<W, F> MapAnnotations<W, F> {
+    pub fn new(upstream: W, f: F) -> MapAnnotations<W, F> {
+        MapAnnotations { upstream, f }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.upstream
+    }
+}
+
+impl<W, F> Render for MapAnnotations<W, F>
+where
+    W: Render,
+{
+    type Error = W::Error;
+
+    fn write_str(&mut self, s: &str) -> Result<usize, W::Error> {
+        self.upstream.write_str(s)
+    }
+
+    fn write_str_all(&mut self, s: &str) -> Result<(), W::Error> {
+        self.upstream.write_str_all(s)
+    }
+}
+
+impl<A, B, W, F> RenderAnnotated<A> for MapAnnotations<W, F>
+where
+    W: RenderAnnotated<B>,
+    F: FnMut(&A) -> B,
+{
+    fn push_annotation(&mut self, annotation: &A) -> Result<(), Self::Error> {
+        let annotation = (self.f)(annotation);
+        self.upstream.push_annotation(&annotation)
+    }
+
+    fn pop_annotation(&mut self) -> Result<(), Self::Error> {
+        self.upstream.pop_annotation()
+    }
+
+    fn set_mode(&mut self, mode: Mode) -> Result<(), Self::Error> {
+        self.upstream.set_mode(mode)
+    }
+}
+
+/// Feeds everything written to `upstream` into a `Hasher`, so that a change of the output can be
+/// detected without keeping the previous output around.
+///
+/// The output is passed to the hasher in blocks of a fixed size, which makes the hash depend only
+/// on the text which was written and not on how it was split into writes.
+///
+/// ```
+/// use std::collections::hash_map::DefaultHasher;
+/// use std::hash::Hasher;
+///
+/// use pretty::{BoxDoc, Hashed, IoWrite};
+///
+/// let a = BoxDoc::<()>::text("hello").append(" world");
+/// let b = BoxDoc::<()>::text("hello world");
+///
+/// let mut written = Vec::new();
+/// let mut out = Hashed::new(IoWrite::new(&mut written), DefaultHasher::new());
+/// a.render_raw(80, &mut out).unwrap();
+/// let (_, hasher) = out.into_inner();
+/// assert_eq!(written, b"hello world");
+/// assert_eq!(hasher.finish(), b.render_hash(80, DefaultHasher::new()));
+/// ```
+pub struct Hashed<W, H> {
+    upstream: W,
+    hasher: H,
+    block: Vec<u8>,
+}
+
+impl<W, H> Hashed<W, H>
+where
+    H: Hasher,
+{
+    const BLOCK: usize = 4096;
+
+    pub fn new(upstream: W, hasher: H) -> Hashed<W, H> {
+        Hashed {
+            upstream,
+            hasher,
+            block: Vec::new(),
+        }
+    }
+
+    /// Returns `upstream` and the hasher, which has been fed all of the output.
+    pub fn into_inner(mut self) -> (W, H) {
+        self.hasher.write(&self.block);
+        (self.upstream, self.hasher)
+    }
+
+    fn hash(&mut self, mut s: &[u8]) {
+        while !s.is_empty() {
+            let count = cmp::min(Self::BLOCK - self.block.len(), s.len());
+            self.block.extend_from_slice(&s[..count]);
+            s = &s[count..];
+            if self.block.len() == Self::BLOCK {
+                self.hasher.write(&self.block);
+                self.block.clear();
+            }
+        }
+    }
+}
+
+impl<W, H> Render for Hashed<W, H>
+where
+    W: Render,
+    H: Hasher,
+{
+    type Error = W::Error;
+
+    fn write_str(&mut self, s: &str) -> Result<usize, W::Error> {
+        let count = self.upstream.write_str(s)?;
+        self.hash(&s.as_bytes()[..count]);
+        Ok(count)
+    }
+
+    fn write_str_all(&mut self, s: &str) -> Result<(), W::Error> {
+        self.upstream.write_str_all(s)?;
+        self.hash(s.as_bytes());
+        Ok(())
+    }
+}
+
+impl<A, W, H> RenderAnnotated<A> for Hashed<W, H>
+where
+    W: RenderAnnotated<A>,
+    H: Hasher,
+{
+    fn push_annotation(&mut self, annotation: &A) -> Result<(), Self::Error> {
+        self.upstream.push_annotation(annotation)
+    }
+
+    fn pop_annotation(&mut self) -> Result<(), Self::Error> {
+        self.upstream.pop_annotation()
+    }
+
+    fn set_mode(&mut self, mode: Mode) -> Result<(), Self::Error> {
+        self.upstream.set_mode(mode)
+    }
+}
+
+/// Reproduces the whitespace of the source a document was formatted from, so that reformatting
+/// only changes the lines it has to.
+///
+/// `whitespace` returns the whitespace which preceded an annotated token in the source, or `None`
+/// for annotations which are not tokens. The whitespace which the layout put before such a token
+/// is replaced by the source whitespace if the layout decision is unchanged, that is if both are
+/// on a single line or both contain a line break. Between single-line whitespace the source
+/// whitespace is written verbatim, while for a line break the blank lines of the source are kept
+/// and the indentation of the layout is used. Whitespace is only replaced where both the layout
+/// and the source have some, and the source whitespace is not taken into account by the layout
+/// so it may push a line past the page width.
+///
+/// [`finish`](#method.finish) must be called after rendering to write whitespace at the end of
+/// the output.
+///
+/// ```
+/// use pretty::{Arena, DocAllocator, FmtWrite, PreserveWhitespace};
+///
+/// let arena = Arena::new();
+/// // The tokens of `let  x =\n\n\n  1;`, annotated with their leading whitespace
+/// let token = |ws, text| arena.text(text).annotate(ws);
+/// let doc = token("", "let")
+///     .append(arena.line())
+///     .append(token("  ", "x"))
+///     .append(arena.line())
+///     .append(token(" ", "="))
+///     .append(arena.line().append(token("\n\n\n  ", "1")).nest(4))
+///     .append(token("", ";"))
+///     .group();
+///
+/// let mut s = String::new();
+/// let mut out = PreserveWhitespace::new(FmtWrite::new(&mut s), |ws: &&'static str| Some(*ws));
+/// doc.1.render_raw(80, &mut out).unwrap();
+/// out.finish().unwrap();
+/// // `1` moved onto the first line so its whitespace is laid out anew
+/// assert_eq!(s, "let  x = 1;");
+///
+/// let mut s = String::new();
+/// let mut out = PreserveWhitespace::new(FmtWrite::new(&mut s), |ws: &&'static str| Some(*ws));
+/// doc.1.render_raw(8, &mut out).unwrap();
+/// out.finish().unwrap();
+/// assert_eq!(s, "let\nx\n=\n\n\n    1;");
+/// ```
+pub struct PreserveWhitespace<W, F> {
+    upstream: W,
+    whitespace: F,
+    pending: String,
+}
+
+impl<W, F> PreserveWhitespace<W, F> {
+    pub fn new(upstream: W, whitespace: F) -> PreserveWhitespace<W, F> {
+        PreserveWhitespace {
+            upstream,
+            whitespace,
+            pending: String::new(),
+        }
+    }
+}
+
+impl<W, F> PreserveWhitespace<W, F>
+where
+    W: Render,
+{
+    fn flush(&mut self) -> Result<(), W::Error> {
+        if !self.pending.is_empty() {
+            self.upstream.write_str_all(&self.pending)?;
+            self.pending.clear();
+        }
+        Ok(())
+    }
+
+    /// Writes the whitespace which is still held back and returns `upstream`.
+    pub fn finish(mut self) -> Result<W, W::Error> {
+        self.flush()?;
+        Ok(self.upstream)
+    }
+}
+
+impl<W, F> Render for PreserveWhitespace<W, F>
+where
+    W: Render,
+{
+    type Error = W::Error;
+
+    fn write_str(&mut self, s: &str) -> Result<usize, W::Error> {
+        self.write_str_all(s).map(|_| s.len())
+    }
+
+    fn write_str_all(&mut self, s: &str) -> Result<(), W::Error> {
+        // Trailing whitespace is held back until it is known whether a token follows
+        let text = s.trim_end();
+        if !text.is_empty() {
+            self.flush()?;
+            self.upstream.write_str_all(text)?;
+        }
+        self.pending.push_str(&s[text.len()..]);
+        Ok(())
+    }
+}
+
+impl<A, W, F, S> RenderAnnotated<A> for PreserveWhitespace<W, F>
+where
+    W: RenderAnnotated<A>,
+    F: FnMut(&A) -> Option<S>,
+    S: AsRef<str>,
+{
+    fn push_annotation(&mut self, annotation: &A) -> Result<(), Self::Error> {
+        if let Some(source) = (self.whitespace)(annotation) {
+            let source = source.as_ref();
+            let broken = self.pending.contains('\n');
+            if !self.pending.is_empty() && !source.is_empty() && broken == source.contains('\n') {
+                let whitespace = if broken {
+                    let indent = &self.pending[self.pending.rfind('\n').unwrap() + 1..];
+                    let newlines = source.matches('\n').count();
+                    "\n".repeat(newlines) + indent
+                } else {
+                    source.to_string()
+                };
+                self.pending = whitespace;
+            }
+        }
+        self.flush()?;
+        self.upstream.push_annotation(annotation)
+    }
+
+    fn pop_annotation(&mut self) -> Result<(), Self::Error> {
+        self.flush()?;
+        self.upstream.pop_annotation()
+    }
+
+    fn set_mode(&mut self, mode: Mode) -> Result<(), Self::Error> {
+        self.upstream.set_mode(mode)
+    }
+}
+
+/// Recovers from errors of `upstream` for which `recoverable` returns `true` by stripping
+/// annotations instead of aborting the render.
+///
+/// An annotation which can not be pushed is skipped together with its pop. If writing text fails
+/// all annotations which are still open are popped and the text is written again without them.
+/// The errors which were recovered from can be inspected with
+/// [`recovered`](#method.recovered).
+///
+/// ```
+/// use pretty::{Arena, DocAllocator, Recover, Render, RenderAnnotated};
+///
+/// // Only supports the "bold" annotation
+/// struct Markdown(String);
+///
+/// impl Render for Markdown {
+///     type Error = String;
+///
+///     fn write_str(&mut self, s: &str) -> Result<usize, String> {
+///         self.0.push_str(s);
+///         Ok(s.len())
+///     }
+/// }
+///
+/// impl RenderAnnotated<&'static str> for Markdown {
+///     fn push_annotation(&mut self, ann: &&'static str) -> Result<(), String> {
+///         match *ann {
+///             "bold" => self.write_str_all("**"),
+///             ann => Err(format!("unsupported annotation `{}`", ann)),
+///         }
+///     }
+///
+///     fn pop_annotation(&mut self) -> Result<(), String> {
+///         self.write_str_all("**")
+///     }
+/// }
+///
+/// let arena = Arena::new();
+/// let doc = arena
+///     .text("a")
+///     .annotate("bold")
+///     .append(arena.text("b").annotate("blink"));
+///
+/// let mut out = Recover::new(Markdown(String::new()), |_: &String| true);
+/// doc.1.render_raw(80, &mut out).unwrap();
+/// assert_eq!(out.recovered(), ["unsupported annotation `blink`"]);
+/// assert_eq!(out.into_inner().0, "**a**b");
+/// ```
+pub struct Recover<W, F, A>
+where
+    W: Render,
+{
+    upstream: W,
+    recoverable: F,
+    pushed: Vec<bool>,
+    recovered: Vec<W::Error>,
+    _marker: std::marker::PhantomData<fn(&A)>,
+}
+
+impl<W, F, A> Recover<W, F, A>
+where
+    W: RenderAnnotated<A>,
+    F: FnMut(&W::Error) -> bool,
+{
+    pub fn new(upstream: W, recoverable: F) -> Self {
+        Recover {
+            upstream,
+            recoverable,
+            pushed: Vec::new(),
+            recovered: Vec::new(),
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    /// The errors which were recovered from so far.
+    pub fn recovered(&self) -> &[W::Error] {
+        &self.recovered
+    }
+
+    pub fn into_inner(self) -> W {
+        self.upstream
+    }
+
+    /// Returns whether `result` is `Ok`, or the error if it is not recoverable.
+    fn recover<T>(&mut self, result: Result<T, W::Error>) -> Result<Option<T>, W::Error> {
+        match result {
+            Ok(x) => Ok(Some(x)),
+            Err(err) if (self.recoverable)(&err) => {
+                self.recovered.push(err);
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    fn strip_annotations(&mut self) -> Result<(), W::Error> {
+        for i in (0..self.pushed.len()).rev() {
+            if self.pushed[i] {
+                self.pushed[i] = false;
+                let result = self.upstream.pop_annotation();
+                self.recover(result)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<W, F, A> Render for Recover<W, F, A>
+where
+    W: RenderAnnotated<A>,
+    F: FnMut(&W::Error) -> bool,
+{
+    type Error = W::Error;
+
+    fn write_str(&mut self, s: &str) -> Result<usize, W::Error> {
+        let result = self.upstream.write_str(s);
+        match self.recover(result)? {
+            Some(count) => Ok(count),
+            None => {
+                self.strip_annotations()?;
+                self.upstream.write_str(s)
+            }
+        }
+    }
+
+    fn write_str_all(&mut self, s: &str) -> Result<(), W::Error> {
+        let result = self.upstream.write_str_all(s);
+        if self.recover(result)?.is_none() {
+            self.strip_annotations()?;
+            self.upstream.write_str_all(s)?;
+        }
+        Ok(())
+    }
+}
+
+impl<W, F, A> RenderAnnotated<A> for Recover<W, F, A>
+where
+    W: RenderAnnotated<A>,
+    F: FnMut(&W::Error) -> bool,
+{
+    fn push_annotation(&mut self, annotation: &A) -> Result<(), Self::Error> {
+        let result = self.upstream.push_annotation(annotation);
+        let pushed = self.recover(result)?.is_some();
+        self.pushed.push(pushed);
+        Ok(())
+    }
+
+    fn pop_annotation(&mut self) -> Result<(), Self::Error> {
+        if self.pushed.pop() == Some(true) {
+            let result = self.upstream.pop_annotation();
+            self.recover(result)?;
+        }
+        Ok(())
+    }
+
+    fn set_mode(&mut self, mode: Mode) -> Result<(), Self::Error> {
+        let result = self.upstream.set_mode(mode);
+        self.recover(result)?;
+        Ok(())
+    }
+}
+
+enum Buffered<A> {
+    Text(String),
+    Push(A),
+    Pop,
+    Mode(Mode),
+}
+
+/// Limits the output written to `upstream` to a budget of bytes and lines.
+///
+/// Output past the budget is dropped while every annotation which has been opened upstream is
+/// closed, so that truncated HTML or ANSI output stays well formed. With
+/// [`at_annotation_boundaries`](struct.Truncate.html#method.at_annotation_boundaries) annotated
+/// spans are never cut in half, a span which does not fit in the remaining budget is dropped
+/// entirely.
+///
+/// ```
+/// use pretty::{Arena, DocAllocator, FmtWrite, Truncate};
+///
+/// let arena = Arena::new();
+/// let doc = arena
+///     .text("let ")
+///     .append(arena.text("identifier").annotate("ident"))
+///     .append(" = 1;");
+///
+/// let mut s = String::new();
+/// let mut out = Truncate::new(FmtWrite::new(&mut s)).max_bytes(8);
+/// doc.1.render_raw(80, &mut out).unwrap();
+/// assert!(out.is_truncated());
+/// assert_eq!(s, "let iden");
+///
+/// let mut s = String::new();
+/// let mut out = Truncate::new(FmtWrite::new(&mut s))
+///     .max_bytes(8)
+///     .at_annotation_boundaries();
+/// doc.1.render_raw(80, &mut out).unwrap();
+/// assert_eq!(s, "let ");
+/// ```
+pub struct Truncate<W, A> {
+    upstream: W,
+    bytes: usize,
+    newlines: usize,
+    boundaries: bool,
+    truncated: bool,
+    open: usize,
+    depth: usize,
+    buffer: Vec<Buffered<A>>,
+    buffered_bytes: usize,
+    buffered_newlines: usize,
+}
+
+impl<W, A> Truncate<W, A> {
+    /// Passes all output on to `upstream` until a budget is set.
+    pub fn new(upstream: W) -> Truncate<W, A> {
+        Truncate {
+            upstream,
+            bytes: usize::MAX,
+            newlines: usize::MAX,
+            boundaries: false,
+            truncated: false,
+            open: 0,
+            depth: 0,
+            buffer: Vec::new(),
+            buffered_bytes: 0,
+            buffered_newlines: 0,
+        }
+    }
+
+    /// Writes at most `bytes` bytes, including line breaks and indentation.
+    pub fn max_bytes(mut self, bytes: usize) -> Self {
+        self.bytes = bytes;
+        self
+    }
+
+    /// Writes at most `lines` lines.
+    pub fn max_lines(mut self, lines: usize) -> Self {
+        match lines.checked_sub(1) {
+            Some(newlines) => self.newlines = newlines,
+            None => self.bytes = 0,
+        }
+        self
+    }
+
+    /// Only truncates outside of annotations. The text of an annotated span is held back until
+    /// the outermost annotation is closed and dropped entirely if it does not fit.
+    pub fn at_annotation_boundaries(mut self) -> Self {
+        self.boundaries = true;
+        self
+    }
+
+    /// Returns `true` if any output has been dropped.
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    pub fn into_inner(self) -> W {
+        self.upstream
+    }
+
+    /// The length of the longest prefix of `s` which fits in the given budget.
+    fn fitting_len(s: &str, bytes: usize, mut newlines: usize) -> usize {
+        for (i, c) in s.char_indices() {
+            if i + c.len_utf8() > bytes {
+                return i;
+            }
+            if c == '\n' {
+                if newlines == 0 {
+                    return i;
+                }
+                newlines -= 1;
+            }
+        }
+        s.len()
+    }
+
+    fn spend(&mut self, s: &str) {
+        self.bytes -= s.len();
+        self.newlines -= s.matches('\n').count();
+    }
+}
+
+impl<W, A> Truncate<W, A>
+where
+    W: RenderAnnotated<A>,
+{
+    fn truncate(&mut self) -> Result<(), W::Error> {
+        self.truncated = true;
+        self.buffer.clear();
+        while self.open > 0 {
+            self.open -= 1;
+            self.upstream.pop_annotation()?;
+        }
+        Ok(())
+    }
+}
+
+impl<W, A> Render for Truncate<W, A>
+where
+    W: RenderAnnotated<A>,
+{
+    type Error = W::Error;
+
+    fn write_str(&mut self, s: &str) -> Result<usize, W::Error> {
+        self.write_str_all(s).map(|_| s.len())
+    }
+
+    fn write_str_all(&mut self, s: &str) -> Result<(), W::Error> {
+        if self.truncated {
+            return Ok(());
+        }
+        if self.depth > 0 {
+            self.buffered_bytes += s.len();
+            self.buffered_newlines += s.matches('\n').count();
+            if self.buffered_bytes > self.bytes || self.buffered_newlines > self.newlines {
+                return self.truncate();
+            }
+            self.buffer.push(Buffered::Text(s.to_string()));
+            return Ok(());
+        }
+        let len = Self::fitting_len(s, self.bytes, self.newlines);
+        self.spend(&s[..len]);
+        self.upstream.write_str_all(&s[..len])?;
+        if len < s.len() {
+            self.truncate()?;
+        }
         Ok(())
     }
 }
 
-impl<A, W> RenderAnnotated<A> for FmtWrite<W>
+impl<W, A> RenderAnnotated<A> for Truncate<W, A>
 where
-    W: fmt::Write,
+    W: RenderAnnotated<A>,
+    A: Clone,
 {
-    fn push_annotation(&mut self, _: &A) -> Result<(), Self::Error> {
-        Ok(())
+    fn push_annotation(&mut self, annotation: &A) -> Result<(), Self::Error> {
+        if self.truncated {
+            return Ok(());
+        }
+        if self.boundaries {
+            self.depth += 1;
+            self.buffer.push(Buffered::Push(annotation.clone()));
+            return Ok(());
+        }
+        self.open += 1;
+        self.upstream.push_annotation(annotation)
     }
 
     fn pop_annotation(&mut self) -> Result<(), Self::Error> {
+        if self.truncated {
+            return Ok(());
+        }
+        if !self.boundaries {
+            self.open -= 1;
+            return self.upstream.pop_annotation();
+        }
+
+        self.depth -= 1;
+        self.buffer.push(Buffered::Pop);
+        if self.depth == 0 {
+            // The outermost span is complete and fits in the budget
+            self.buffered_bytes = 0;
+            self.buffered_newlines = 0;
+            for buffered in std::mem::take(&mut self.buffer) {
+                match buffered {
+                    Buffered::Text(s) => {
+                        self.spend(&s);
+                        self.upstream.write_str_all(&s)?;
+                    }
+                    Buffered::Push(annotation) => self.upstream.push_annotation(&annotation)?,
+                    Buffered::Pop => self.upstream.pop_annotation()?,
+                    Buffered::Mode(mode) => self.upstream.set_mode(mode)?,
+                }
+            }
+        }
         Ok(())
     }
+
+    fn set_mode(&mut self, mode: Mode) -> Result<(), Self::Error> {
+        if self.truncated {
+            return Ok(());
+        }
+        if self.depth > 0 {
+            self.buffer.push(Buffered::Mode(mode));
+            return Ok(());
+        }
+        self.upstream.set_mode(mode)
+    }
 }
 
 #[cfg(feature = "termcolor")]
 pub struct TermColored<W> {
     color_stack: Vec<ColorSpec>,
+    colors: bool,
     upstream: W,
 }
 
 #[cfg(feature = "termcolor")]
 impl<W> TermColored<W> {
+    /// Writes colors if [`colors_enabled`](fn.colors_enabled.html) returns `true`.
     pub fn new(upstream: W) -> TermColored<W> {
         TermColored {
             color_stack: Vec::new(),
+            colors: crate::colors_enabled(),
             upstream,
         }
     }
+
+    pub fn into_inner(self) -> W {
+        self.upstream
+    }
 }
 
 #[cfg(feature = "termcolor")]
@@ -143,11 +1403,17 @@ where
     W: WriteColor,
 {
     fn push_annotation(&mut self, color: &ColorSpec) -> Result<(), Self::Error> {
+        if !self.colors {
+            return Ok(());
+        }
         self.color_stack.push(color.clone());
         self.upstream.set_color(color)
     }
 
     fn pop_annotation(&mut self) -> Result<(), Self::Error> {
+        if !self.colors {
+            return Ok(());
+        }
         self.color_stack.pop();
         match self.color_stack.last() {
             Some(previous) => self.upstream.set_color(previous),
@@ -163,59 +1429,577 @@ macro_rules! make_spaces {
 
 pub(crate) const SPACES: &str = make_spaces!(,,,,,,,,,,);
 
-#[inline]
-pub fn best<'a, W, T, A>(doc: &Doc<'a, T, A>, width: usize, out: &mut W) -> Result<(), W::Error>
+/// Whether content is laid out with its line breaks or flattened onto a single line.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Mode {
+    /// The line breaks are taken.
+    Break,
+    /// The content is part of a group which was flattened.
+    Flat,
+}
+
+type Cmd<'d, 'a, T, A> = (usize, Mode, &'d Doc<'a, T, A>);
+
+/// A group document together with its mode and the column it started at.
+pub(crate) type GroupMode<'a, T, A> = (*const Doc<'a, T, A>, Mode, usize);
+
+/// An alignment group which is being laid out.
+#[derive(Clone, Debug)]
+struct Alignment {
+    /// The length of `bcmds` once the group has been laid out, or `None` if the group is closed
+    /// by whoever is measuring it
+    level: Option<usize>,
+    /// The column each mark on a line is padded to, in order
+    targets: Vec<usize>,
+    /// The index of the next mark on the current line
+    mark: usize,
+    /// The largest column of the first mark without a target
+    measured: Option<usize>,
+}
+
+/// A choice made while laying out a document, together with the column it was made at.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Decision {
+    Group(Mode, usize),
+    /// Whether the first alternative of a union was chosen
+    Union(bool, usize),
+}
+
+/// A single placed command produced by [`LayoutIter`](struct.LayoutIter.html).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LayoutCmd<'d, A> {
+    /// Text to write at the current position.
+    Text(&'d str),
+    /// A line break followed by the given number of spaces of indentation.
+    Newline(usize),
+    /// Start of an annotated region.
+    PushAnnotation(&'d A),
+    /// End of the innermost annotated region.
+    PopAnnotation,
+}
+
+/// The progress of [`LayoutIter::render_step`](struct.LayoutIter.html#method.render_step).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Step {
+    /// Some of the layout has not been rendered yet.
+    Pending,
+    /// The whole layout has been rendered.
+    Done,
+}
+
+/// A group whose layout changes when the width changes by at most the margin given to
+/// [`LayoutIter::with_stress`](struct.LayoutIter.html#method.with_stress).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct KnifeEdge {
+    /// The line of the output on which the group starts, counting from 0.
+    pub line: usize,
+    /// The column of the output at which the group starts.
+    pub column: usize,
+    /// Whether the group was laid out on a single line.
+    pub flat: bool,
+    /// Whether the layout was the opposite of the one chosen without stress testing.
+    pub flipped: bool,
+}
+
+struct Stress {
+    state: u64,
+    margin: usize,
+    line: usize,
+    knife_edges: Vec<KnifeEdge>,
+}
+
+impl Stress {
+    /// xorshift64*, which is good enough to pick layouts and deterministic across platforms
+    fn next_bool(&mut self) -> bool {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 63 == 1
+    }
+}
+
+/// Penalties used to choose between the alternatives of a `union`, see
+/// [`LayoutIter::with_cost`](struct.LayoutIter.html#method.with_cost).
+///
+/// Annotations act as tags for the line breaks they enclose, so that breaking inside of some
+/// constructs can be made more expensive than breaking inside of others.
+pub trait Cost<A> {
+    /// The penalty for a line which extends `overflow` columns past the width.
+    fn overflow(&self, overflow: usize) -> u64 {
+        (overflow as u64).saturating_mul(100)
+    }
+
+    /// The penalty for a line break, `tag` is the innermost annotation enclosing the break.
+    fn line_break(&self, tag: Option<&A>) -> u64 {
+        let _ = tag;
+        1
+    }
+}
+
+/// The default penalties of `Cost`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultCost;
+
+impl<A> Cost<A> for DefaultCost {}
+
+/// Scratch storage for the documents produced by `column` and `nesting` while laying out a
+/// document. It must outlive the [`LayoutIter`](struct.LayoutIter.html) that uses it.
+pub struct LayoutArena<T>(typed_arena::Arena<T>);
+
+impl<T> LayoutArena<T> {
+    /// Creates an empty arena. It can be reused for several layouts of the same document type.
+    pub fn new() -> Self {
+        LayoutArena(typed_arena::Arena::new())
+    }
+}
+
+impl<T> Default for LayoutArena<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An iterator over the placed commands of the best layout of a document.
+///
+/// This is the state machine which drives all of the `render` methods and can be used directly
+/// to interleave rendering with other work, stop rendering early or to write to sinks which do
+/// not fit the `Render` trait.
+///
+/// ```
+/// use pretty::{Arena, DocAllocator, LayoutArena, LayoutCmd, LayoutIter};
+///
+/// let arena = Arena::<()>::new();
+/// let doc = arena.text("hello").append(arena.line()).append("world").group();
+/// let temp = LayoutArena::new();
+/// let cmds: Vec<_> = LayoutIter::new(&temp, &doc.1, 5).collect();
+/// assert_eq!(
+///     cmds,
+///     [LayoutCmd::Text("hello"), LayoutCmd::Newline(0), LayoutCmd::Text("world")]
+/// );
+/// ```
+pub struct LayoutIter<'d, 'a, T, A>
+where
+    T: DocPtr<'a, A> + 'a,
+{
+    temp_arena: &'d typed_arena::Arena<T>,
+    width: usize,
+    max_width: usize,
+    /// Whether `max_width` was set explicitly, which caps how far `prefer_flat` may overflow
+    max_width_set: bool,
+    pos: usize,
+    cmd: Option<Cmd<'d, 'a, T, A>>,
+    bcmds: Vec<Cmd<'d, 'a, T, A>>,
+    fcmds: Vec<(Mode, &'d Doc<'a, T, A>)>,
+    annotation_levels: Vec<(usize, &'d A)>,
+    /// The widths to restore once the child of a `max_width` or `unbounded` at each level has been
+    /// laid out
+    width_levels: Vec<(usize, usize, usize)>,
+    /// The alignment groups being laid out, innermost last
+    alignments: Vec<Alignment>,
+    cost: Option<&'d dyn Cost<A>>,
+    check_annotations: bool,
+    strict_fitting: bool,
+    penalty: u64,
+    stress: Option<Stress>,
+    budget: Option<Budget>,
+    mode: Mode,
+    /// The mode last passed to `RenderAnnotated::set_mode`
+    reported_mode: Mode,
+    /// The group documents laid out so far
+    groups: Option<Vec<GroupMode<'a, T, A>>>,
+    /// The decisions made since they were last taken
+    pub(crate) decisions: Option<Vec<Decision>>,
+    max_blank_lines: Option<usize>,
+    /// The line breaks which have not been returned yet
+    newlines: VecDeque<usize>,
+    newlines_since_text: usize,
+    /// The command following `newlines`, together with its mode
+    held: Option<(LayoutCmd<'d, A>, Mode)>,
+    indent: Indent,
+    line_ending: LineEnding,
+}
+
+impl<'d, 'a, T, A> LayoutIter<'d, 'a, T, A>
 where
     T: DocPtr<'a, A> + 'a,
-    W: ?Sized + RenderAnnotated<A>,
 {
-    #[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
-    enum Mode {
-        Break,
-        Flat,
+    /// Lays out `doc` to fit within `width` columns, storing the documents produced while laying
+    /// it out in `temp_arena`.
+    pub fn new(temp_arena: &'d LayoutArena<T>, doc: &'d Doc<'a, T, A>, width: usize) -> Self {
+        Self::with_config(temp_arena, doc, RenderConfig::new(width))
+    }
+
+    /// Lays out `doc` using the widths of `config`.
+    pub fn with_config(
+        temp_arena: &'d LayoutArena<T>,
+        doc: &'d Doc<'a, T, A>,
+        config: RenderConfig,
+    ) -> Self {
+        LayoutIter {
+            temp_arena: &temp_arena.0,
+            width: config.width,
+            max_width: config.hard_width(),
+            max_width_set: config.max_width.is_some(),
+            pos: 0,
+            cmd: None,
+            bcmds: vec![(0, Mode::Break, doc)],
+            fcmds: vec![],
+            annotation_levels: vec![],
+            width_levels: vec![],
+            alignments: vec![],
+            check_annotations: false,
+            strict_fitting: config.strict_fitting,
+            cost: None,
+            penalty: 0,
+            stress: None,
+            budget: None,
+            mode: Mode::Break,
+            reported_mode: Mode::Break,
+            groups: None,
+            decisions: None,
+            max_blank_lines: config.max_blank_lines,
+            newlines: VecDeque::new(),
+            newlines_since_text: 0,
+            held: None,
+            indent: config.indent,
+            line_ending: config.line_ending,
+        }
+    }
+
+    /// Lays out the document as if it started at `column` of a line with `indent` as the nesting
+    /// of its line breaks, such as after a prefix which has already been written. Must be called
+    /// before the first command is taken.
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator, LayoutArena, LayoutCmd, LayoutIter};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// let doc = arena.text("a").append(arena.line()).append("b").group();
+    /// let temp = LayoutArena::new();
+    /// let cmds: Vec<_> = LayoutIter::new(&temp, &doc.1, 4).starting_at(2, 2).collect();
+    /// assert_eq!(
+    ///     cmds,
+    ///     [LayoutCmd::Text("a"), LayoutCmd::Newline(2), LayoutCmd::Text("b")]
+    /// );
+    /// ```
+    pub fn starting_at(mut self, column: usize, indent: usize) -> Self {
+        self.pos = column;
+        if let Some(cmd) = self.bcmds.first_mut() {
+            cmd.0 = indent;
+        }
+        self
     }
 
-    type Cmd<'d, 'a, T, A> = (usize, Mode, &'d Doc<'a, T, A>);
+    /// Chooses between the alternatives of each `union` by comparing their cost instead of
+    /// picking the first alternative which fits.
+    ///
+    /// The cost of an alternative is found by laying it out on its own starting at the current
+    /// position, with any unions inside of it chosen the usual way. Ties are resolved in favour of
+    /// the first alternative.
+    pub fn with_cost(mut self, cost: &'d dyn Cost<A>) -> Self {
+        self.cost = Some(cost);
+        self
+    }
+
+    /// Randomly flips the layout of groups which are within `margin` columns of being laid out
+    /// differently, so that tools consuming the output can be tested against both layouts. The
+    /// same `seed` always produces the same layout. The groups which were considered are reported
+    /// by [`knife_edges`](#method.knife_edges).
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator, LayoutArena, LayoutIter, Step, FmtWrite};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// let doc = arena.text("hello").append(arena.line()).append("world").group();
+    /// let temp = LayoutArena::new();
+    ///
+    /// let mut layouts = std::collections::BTreeSet::new();
+    /// for seed in 0..8 {
+    ///     let mut iter = LayoutIter::new(&temp, &doc.1, 12).with_stress(seed, 2);
+    ///     let mut s = String::new();
+    ///     iter.render_step(usize::MAX, &mut FmtWrite::new(&mut s)).unwrap();
+    ///     assert_eq!(iter.knife_edges().len(), 1);
+    ///     layouts.insert(s);
+    /// }
+    /// assert_eq!(layouts.len(), 2);
+    /// ```
+    pub fn with_stress(mut self, seed: u64, margin: usize) -> Self {
+        self.stress = Some(Stress {
+            // The state of xorshift must not be zero
+            state: seed ^ 0x9e37_79b9_7f4a_7c15,
+            margin,
+            line: 0,
+            knife_edges: vec![],
+        });
+        self
+    }
+
+    /// The groups which were found to be within the margin of
+    /// [`with_stress`](#method.with_stress) so far.
+    pub fn knife_edges(&self) -> &[KnifeEdge] {
+        self.stress
+            .as_ref()
+            .map_or(&[], |stress| &stress.knife_edges)
+    }
+
+    /// The mode in which the text, line break or annotation returned last by the iterator is laid
+    /// out.
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
 
-    fn write_newline<W>(ind: usize, out: &mut W) -> Result<(), W::Error>
+    /// Renders at most `budget` commands of the layout to `out`, so that a large document can be
+    /// rendered in slices, for instance across the frames of an interactive application. Returns
+    /// `Step::Pending` while there may be more commands to render.
+    ///
+    /// ```
+    /// use pretty::{Arena, DocAllocator, FmtWrite, LayoutArena, LayoutIter, Step};
+    ///
+    /// let arena = Arena::<()>::new();
+    /// let doc = arena.intersperse(["a", "b", "c"].iter().map(|s| arena.text(*s)), arena.line());
+    /// let temp = LayoutArena::new();
+    /// let mut iter = LayoutIter::new(&temp, &doc.1, 80);
+    ///
+    /// let mut s = String::new();
+    /// let mut frames = 0;
+    /// while iter.render_step(2, &mut FmtWrite::new(&mut s)).unwrap() == Step::Pending {
+    ///     frames += 1;
+    /// }
+    /// assert_eq!(s, "a\nb\nc");
+    /// assert_eq!(frames, 2);
+    /// ```
+    pub fn render_step<W>(&mut self, budget: usize, out: &mut W) -> Result<Step, W::Error>
     where
-        W: ?Sized + Render,
+        W: ?Sized + RenderAnnotated<A>,
     {
-        out.write_str_all("\n")?;
-        write_spaces(ind, out)
+        for _ in 0..budget {
+            match self.next() {
+                Some(cmd) => self.write_cmd(cmd, out)?,
+                None => return Ok(Step::Done),
+            }
+        }
+        let done = self.cmd.is_none()
+            && self.bcmds.is_empty()
+            && self.annotation_levels.is_empty()
+            && self.newlines.is_empty()
+            && self.held.is_none();
+        Ok(if done { Step::Done } else { Step::Pending })
+    }
+
+    fn record_group(&mut self, group: &Doc<'a, T, A>) {
+        if let Some((_, mode, _)) = self.cmd {
+            if let Some(groups) = &mut self.groups {
+                groups.push((group, mode, self.pos));
+            }
+            if let Some(decisions) = &mut self.decisions {
+                decisions.push(Decision::Group(mode, self.pos));
+            }
+        }
     }
 
-    fn write_spaces<W>(spaces: usize, out: &mut W) -> Result<(), W::Error>
+    fn write_cmd<W>(&mut self, cmd: LayoutCmd<'d, A>, out: &mut W) -> Result<(), W::Error>
     where
-        W: ?Sized + Render,
+        W: ?Sized + RenderAnnotated<A>,
     {
-        let mut inserted = 0;
-        while inserted < spaces {
-            let insert = cmp::min(SPACES.len(), spaces - inserted);
-            inserted += out.write_str(&SPACES[..insert])?;
+        if self.mode != self.reported_mode && !matches!(cmd, LayoutCmd::PopAnnotation) {
+            self.reported_mode = self.mode;
+            out.set_mode(self.mode)?;
+        }
+        match cmd {
+            LayoutCmd::Text(s) => out.write_str_all(s),
+            LayoutCmd::Newline(ind) => write_newline(ind, self.indent, self.line_ending, out),
+            LayoutCmd::PushAnnotation(ann) => out.push_annotation(ann),
+            LayoutCmd::PopAnnotation => out.pop_annotation(),
+        }
+    }
+
+    /// Lays out `doc` on its own, starting at the current position, in order to measure it.
+    fn sub_layout(&self, doc: &'d Doc<'a, T, A>, ind: usize, mode: Mode) -> Self {
+        LayoutIter {
+            temp_arena: self.temp_arena,
+            width: self.width,
+            max_width: self.max_width,
+            max_width_set: self.max_width_set,
+            pos: self.pos,
+            cmd: None,
+            bcmds: vec![(ind, mode, doc)],
+            fcmds: vec![],
+            annotation_levels: vec![],
+            width_levels: vec![],
+            alignments: vec![],
+            check_annotations: false,
+            strict_fitting: self.strict_fitting,
+            cost: None,
+            penalty: 0,
+            stress: None,
+            budget: None,
+            mode: Mode::Break,
+            reported_mode: Mode::Break,
+            groups: None,
+            decisions: None,
+            max_blank_lines: None,
+            newlines: VecDeque::new(),
+            newlines_since_text: 0,
+            held: None,
+            indent: self.indent,
+            line_ending: self.line_ending,
         }
+    }
 
-        Ok(())
+    fn union_cost(
+        &self,
+        cost: &dyn Cost<A>,
+        doc: &'d Doc<'a, T, A>,
+        ind: usize,
+        mode: Mode,
+    ) -> u64 {
+        let mut iter = self.sub_layout(doc, ind, mode);
+        // The marks of the alternative are padded like the marks of the enclosing alignment groups
+        iter.alignments = self
+            .alignments
+            .iter()
+            .map(|alignment| Alignment {
+                level: None,
+                ..alignment.clone()
+            })
+            .collect();
+        let mut tags: Vec<_> = self.annotation_levels.iter().map(|&(_, ann)| ann).collect();
+        let overflow = |pos: usize| match pos.saturating_sub(self.width) {
+            0 => 0,
+            overflow => cost.overflow(overflow),
+        };
+
+        // The content following the union on the same line is laid out as well, as it may fit after
+        // one alternative but not after the other. It is taken from the pending commands one at a
+        // time, so only as much of it as is costed gets copied.
+        let mut pending = self.bcmds.len();
+        let mut annotation_levels = self.annotation_levels.len();
+        let mut width_levels = self.width_levels.len();
+        let mut alignments = self.alignments.len();
+        let mut following = false;
+        let mut total = 0u64;
+        let mut line_end = iter.pos;
+        loop {
+            let cmd = match iter.next() {
+                Some(cmd) => cmd,
+                None if pending == 0 => break,
+                None => {
+                    // Close the annotations and width limits which end before the next command
+                    while annotation_levels > 0
+                        && self.annotation_levels[annotation_levels - 1].0 == pending
+                    {
+                        annotation_levels -= 1;
+                        tags.pop();
+                    }
+                    while let Some(&(level, width, max_width)) =
+                        self.width_levels[..width_levels].last()
+                    {
+                        if level != pending {
+                            break;
+                        }
+                        iter.width = width;
+                        iter.max_width = max_width;
+                        width_levels -= 1;
+                    }
+                    while alignments > 0 && self.alignments[alignments - 1].level == Some(pending) {
+                        alignments -= 1;
+                        iter.alignments.pop();
+                    }
+                    pending -= 1;
+                    iter.bcmds.push(self.bcmds[pending]);
+                    following = true;
+                    continue;
+                }
+            };
+            match cmd {
+                LayoutCmd::Text(_) => (),
+                // The rest of the document is laid out the same after either alternative
+                LayoutCmd::Newline(_) if following => break,
+                LayoutCmd::Newline(_) => {
+                    total = total
+                        .saturating_add(overflow(line_end))
+                        .saturating_add(cost.line_break(tags.last().copied()));
+                }
+                LayoutCmd::PushAnnotation(ann) => tags.push(ann),
+                LayoutCmd::PopAnnotation => {
+                    tags.pop();
+                }
+            }
+            line_end = iter.pos;
+        }
+        total
+            .saturating_add(overflow(line_end))
+            .saturating_add(iter.penalty)
+    }
+
+    /// The column each mark of the alignment group `doc` is padded to. The group is laid out once
+    /// for every mark on its longest line, each time finding the column of the next mark with the
+    /// marks before it padded.
+    fn alignment_targets(&self, doc: &'d Doc<'a, T, A>, ind: usize, mode: Mode) -> Vec<usize> {
+        let mut targets = vec![];
+        loop {
+            let mut iter = self.sub_layout(doc, ind, mode);
+            iter.cost = self.cost;
+            iter.alignments.push(Alignment {
+                level: None,
+                targets,
+                mark: 0,
+                measured: None,
+            });
+            for _ in &mut iter {}
+            let alignment = iter.alignments.pop().expect("measured alignment group");
+            targets = alignment.targets;
+            match alignment.measured {
+                Some(column) => targets.push(column),
+                None => return targets,
+            }
+        }
+    }
+
+    /// The column that the next command will be placed at.
+    pub fn column(&self) -> usize {
+        self.pos
     }
 
-    fn fitting<'a, 'd, T, A>(
-        temp_arena: &'d typed_arena::Arena<T>,
+    fn fitting(
+        &mut self,
         next: &'d Doc<'a, T, A>,
-        bcmds: &[Cmd<'d, 'a, T, A>],
-        fcmds: &mut Vec<&'d Doc<'a, T, A>>,
-        mut pos: usize,
-        width: usize,
         ind: usize,
+        width: usize,
         newline_fits: fn(Mode) -> bool,
-    ) -> bool
-    where
-        T: DocPtr<'a, A>,
-    {
+    ) -> bool {
+        let bcmds = &self.bcmds;
+        let fcmds = &mut self.fcmds;
+        let width_levels = &self.width_levels;
+        let alignments = &self.alignments;
+        let page_max_width = self.max_width;
+        let strict = self.strict_fitting;
+        let mut pos = self.pos;
+        // The number of alignment groups enclosing the current command and the index of the next
+        // mark of the innermost one. The marks of groups which start while fitting have not been
+        // measured yet and are not padded until the fitted content has been laid out.
+        let mut active = alignments.len();
+        let mut mark = alignments.last().map(|alignment| alignment.mark);
+        let mut nested = false;
+        // The content being fitted must stay within `width` while the content following it on
+        // the same line may extend up to the hard maximum of the `max_width` it is part of
+        let fit_width = width;
+        let mut width = width;
+        let max_width_at = |bidx: usize| {
+            width_levels
+                .iter()
+                .rev()
+                .take_while(|&&(level, _, _)| bidx < level)
+                .last()
+                .map_or(page_max_width, |&(_, _, max_width)| max_width)
+        };
+
         let mut bidx = bcmds.len();
         fcmds.clear(); // clear from previous calls from best
-        fcmds.push(next);
+        fcmds.push((Mode::Flat, next));
 
-        let mut mode = Mode::Flat;
+        let mut mode;
+        let mut continuation = false;
         loop {
             let mut doc = match fcmds.pop() {
                 None => {
@@ -224,45 +2008,72 @@ where
                         return true;
                     } else {
                         bidx -= 1;
-                        mode = Mode::Break;
+                        continuation = true;
+                        // Only strict fitting keeps on measuring through the line breaks of
+                        // groups which have already been flattened
+                        mode = if strict { bcmds[bidx].1 } else { Mode::Break };
+                        width = cmp::max(max_width_at(bidx), fit_width);
+                        nested = false;
+                        while active > 0
+                            && matches!(alignments[active - 1].level, Some(level) if level > bidx)
+                        {
+                            active -= 1;
+                            mark = active.checked_sub(1).map(|i| alignments[i].mark);
+                        }
                         bcmds[bidx].2
                     }
                 }
-                Some(cmd) => cmd,
+                Some((cmd_mode, cmd)) => {
+                    mode = cmd_mode;
+                    cmd
+                }
             };
 
             loop {
+                if let Some(budget) = &mut self.budget {
+                    // The layout stops as soon as it sees that the budget is exceeded
+                    if !budget.visit() {
+                        return false;
+                    }
+                }
                 match *doc {
                     Doc::Nil => {}
                     Doc::Append(ref ldoc, ref rdoc) => {
-                        fcmds.push(rdoc);
+                        fcmds.push((mode, rdoc));
                         // Since appended documents often appear in sequence on the left side we
                         // gain a slight performance increase by batching these pushes (avoiding
                         // to push and directly pop `Append` documents)
                         doc = ldoc;
                         while let Doc::Append(ref l, ref r) = *doc {
-                            fcmds.push(r);
+                            fcmds.push((mode, r));
                             doc = l;
                         }
                         continue;
                     }
+                    // Strict fitting assumes that the groups following the fitted content are
+                    // flattened as well
+                    Doc::Group(ref next) | Doc::BiasedGroup(_, ref next) if strict => {
+                        mode = Mode::Flat;
+                        doc = next;
+                        continue;
+                    }
                     // Newlines inside the group makes it not fit, but those outside lets it
                     // fit on the current line
-                    Doc::Line => return newline_fits(mode),
-                    Doc::BorrowedText(ref str) => {
-                        pos += str.len();
+                    Doc::Line => return continuation || newline_fits(mode),
+                    Doc::BorrowedText(str) => {
+                        pos += text_width(str);
                         if pos > width {
                             return false;
                         }
                     }
                     Doc::OwnedText(ref str) => {
-                        pos += str.len();
+                        pos += text_width(str);
                         if pos > width {
                             return false;
                         }
                     }
                     Doc::SmallText(ref str) => {
-                        pos += str.len();
+                        pos += text_width(str);
                         if pos > width {
                             return false;
                         }
@@ -274,17 +2085,37 @@ where
                         };
                         continue;
                     }
+                    Doc::AlignmentMark => {
+                        if let (false, Some(mark)) = (nested, mark.as_mut()) {
+                            if let Some(&target) = alignments[active - 1].targets.get(&*mark) {
+                                pos = cmp::max(pos, target);
+                                if pos > width {
+                                    return false;
+                                }
+                            }
+                            *mark += 1;
+                        }
+                    }
+                    Doc::AlignmentGroup(ref next) => {
+                        nested = true;
+                        doc = next;
+                        continue;
+                    }
 
                     Doc::Column(ref f) => {
-                        doc = temp_arena.alloc(f(pos));
+                        doc = self.temp_arena.alloc(f(pos));
                         continue;
                     }
                     Doc::Nesting(ref f) => {
-                        doc = temp_arena.alloc(f(ind));
+                        doc = self.temp_arena.alloc(f(ind));
                         continue;
                     }
                     Doc::Nest(_, ref next)
+                    | Doc::MaxWidth(_, ref next)
+                    | Doc::Unbounded(ref next)
+                    | Doc::Delimiter(ref next)
                     | Doc::Group(ref next)
+                    | Doc::BiasedGroup(_, ref next)
                     | Doc::Annotated(_, ref next)
                     | Doc::Union(_, ref next) => {
                         doc = next;
@@ -295,116 +2126,476 @@ where
             }
         }
     }
+}
 
-    let temp_arena = typed_arena::Arena::new();
+impl<'d, 'a, T, A> Iterator for LayoutIter<'d, 'a, T, A>
+where
+    T: DocPtr<'a, A> + 'a,
+{
+    type Item = LayoutCmd<'d, A>;
 
-    let mut pos = 0;
-    let mut bcmds = vec![(0, Mode::Break, doc)];
-    let mut fcmds = vec![];
-    let mut annotation_levels = vec![];
+    fn next(&mut self) -> Option<Self::Item> {
+        let limit = match self.max_blank_lines {
+            Some(limit) => limit,
+            None => return self.next_cmd(),
+        };
+        // Line breaks are held back until the next command so that the ones which would exceed
+        // the limit can be dropped
+        loop {
+            if self.held.is_none() {
+                match self.next_cmd() {
+                    Some(LayoutCmd::Newline(ind)) => {
+                        self.newlines.push_back(ind);
+                        if self.newlines_since_text > limit {
+                            // Keep the indentation of the last line break
+                            if self.newlines.len() > 1 {
+                                self.newlines.pop_front();
+                            } else {
+                                self.newlines.pop_back();
+                            }
+                        } else {
+                            self.newlines_since_text += 1;
+                        }
+                        continue;
+                    }
+                    Some(cmd) => {
+                        if let LayoutCmd::Text(s) = cmd {
+                            if !s.is_empty() {
+                                self.newlines_since_text = 0;
+                            }
+                        }
+                        self.held = Some((cmd, self.mode));
+                    }
+                    None => (),
+                }
+            }
+            if let Some(ind) = self.newlines.pop_front() {
+                self.mode = Mode::Break;
+                return Some(LayoutCmd::Newline(ind));
+            }
+            let (cmd, mode) = self.held.take()?;
+            self.mode = mode;
+            return Some(cmd);
+        }
+    }
+}
 
-    while let Some(mut cmd) = bcmds.pop() {
+impl<'d, 'a, T, A> LayoutIter<'d, 'a, T, A>
+where
+    T: DocPtr<'a, A> + 'a,
+{
+    fn next_cmd(&mut self) -> Option<LayoutCmd<'d, A>> {
         loop {
-            let (ind, mode, doc) = cmd;
+            if self.check_annotations {
+                if matches!(self.annotation_levels.last(), Some(&(level, _)) if level == self.bcmds.len())
+                {
+                    self.annotation_levels.pop();
+                    return Some(LayoutCmd::PopAnnotation);
+                }
+                self.check_annotations = false;
+            }
+
+            let (ind, mode, doc) = match self.cmd.take() {
+                Some(cmd) => cmd,
+                None => {
+                    // Restore the width once the child of a `max_width` or `unbounded` has been laid out
+                    while let Some(&(level, width, max_width)) = self.width_levels.last() {
+                        if level != self.bcmds.len() {
+                            break;
+                        }
+                        self.width = width;
+                        self.max_width = max_width;
+                        self.width_levels.pop();
+                    }
+                    while matches!(self.alignments.last(), Some(alignment) if alignment.level == Some(self.bcmds.len()))
+                    {
+                        self.alignments.pop();
+                    }
+                    self.bcmds.pop()?
+                }
+            };
+            if let Some(budget) = &mut self.budget {
+                if !budget.visit() {
+                    return None;
+                }
+            }
+            let node = doc;
             match *doc {
                 Doc::Nil => {}
                 Doc::Append(ref ldoc, ref rdoc) => {
-                    bcmds.push((ind, mode, rdoc));
+                    self.bcmds.push((ind, mode, rdoc));
                     let mut doc = ldoc;
                     while let Doc::Append(ref l, ref r) = **doc {
-                        bcmds.push((ind, mode, r));
+                        self.bcmds.push((ind, mode, r));
                         doc = l;
                     }
-                    cmd = (ind, mode, doc);
+                    self.cmd = Some((ind, mode, doc));
                     continue;
                 }
                 Doc::FlatAlt(ref b, ref f) => {
-                    cmd = (
+                    self.cmd = Some((
                         ind,
                         mode,
                         match mode {
                             Mode::Break => b,
                             Mode::Flat => f,
                         },
-                    );
+                    ));
+                    continue;
+                }
+                Doc::Group(ref doc) => {
+                    self.cmd = Some(match mode {
+                        Mode::Flat => (ind, Mode::Flat, doc),
+                        Mode::Break => {
+                            let mut flat =
+                                self.fitting(doc, ind, self.width, |mode| mode == Mode::Break);
+                            if let Some(margin) = self.stress.as_ref().map(|s| s.margin) {
+                                let narrow = self.width.saturating_sub(margin);
+                                let wide = self.width.saturating_add(margin);
+                                if self.fitting(doc, ind, narrow, |mode| mode == Mode::Break)
+                                    != self.fitting(doc, ind, wide, |mode| mode == Mode::Break)
+                                {
+                                    let pos = self.pos;
+                                    let stress = self.stress.as_mut().unwrap();
+                                    let flipped = stress.next_bool();
+                                    flat ^= flipped;
+                                    stress.knife_edges.push(KnifeEdge {
+                                        line: stress.line,
+                                        column: pos,
+                                        flat,
+                                        flipped,
+                                    });
+                                }
+                            }
+                            (ind, if flat { Mode::Flat } else { Mode::Break }, doc)
+                        }
+                    });
+                    self.record_group(node);
+                    continue;
+                }
+                Doc::BiasedGroup(bias, ref doc) => {
+                    self.cmd = Some(match mode {
+                        Mode::Flat => (ind, Mode::Flat, doc),
+                        Mode::Break => {
+                            let width = if bias < 0 {
+                                self.width.saturating_sub(bias.unsigned_abs())
+                            } else if self.max_width_set {
+                                cmp::min(self.width.saturating_add(bias as usize), self.max_width)
+                            } else {
+                                self.width.saturating_add(bias as usize)
+                            };
+                            let flat = self.fitting(doc, ind, width, |mode| mode == Mode::Break);
+                            // Only matters when unions are chosen by cost
+                            let penalty = match (flat, bias < 0) {
+                                (true, true) | (false, false) => bias.unsigned_abs() as u64,
+                                _ => 0,
+                            };
+                            self.penalty = self.penalty.saturating_add(penalty);
+                            (ind, if flat { Mode::Flat } else { Mode::Break }, doc)
+                        }
+                    });
+                    self.record_group(node);
                     continue;
                 }
-                Doc::Group(ref doc) => match mode {
-                    Mode::Flat => {
-                        cmd = (ind, Mode::Flat, doc);
-                        continue;
-                    }
-                    Mode::Break => {
-                        cmd = if fitting(
-                            &temp_arena,
-                            doc,
-                            &bcmds,
-                            &mut fcmds,
-                            pos,
-                            width,
-                            ind,
-                            |mode| mode == Mode::Break,
-                        ) {
-                            (ind, Mode::Flat, &**doc)
-                        } else {
-                            (ind, Mode::Break, doc)
-                        };
-                        continue;
-                    }
-                },
                 Doc::Nest(off, ref doc) => {
-                    cmd = ((ind as isize).saturating_add(off) as usize, mode, doc);
+                    self.cmd = Some(((ind as isize).saturating_add(off) as usize, mode, doc));
+                    continue;
+                }
+                Doc::MaxWidth(width, ref doc) => {
+                    self.width_levels
+                        .push((self.bcmds.len(), self.width, self.max_width));
+                    let limit = self.pos.saturating_add(width);
+                    self.width = cmp::min(self.width, limit);
+                    self.max_width = cmp::min(self.max_width, limit);
+                    self.cmd = Some((ind, mode, doc));
+                    continue;
+                }
+                Doc::Delimiter(ref doc) => {
+                    self.cmd = Some((ind, mode, doc));
+                    continue;
+                }
+                Doc::Unbounded(ref doc) => {
+                    self.width_levels
+                        .push((self.bcmds.len(), self.width, self.max_width));
+                    self.width = usize::MAX;
+                    self.max_width = usize::MAX;
+                    self.cmd = Some((ind, mode, doc));
+                    continue;
+                }
+                Doc::AlignmentGroup(ref doc) => {
+                    let targets = self.alignment_targets(doc, ind, mode);
+                    self.alignments.push(Alignment {
+                        level: Some(self.bcmds.len()),
+                        targets,
+                        mark: 0,
+                        measured: None,
+                    });
+                    self.cmd = Some((ind, mode, doc));
                     continue;
                 }
+                Doc::AlignmentMark => {
+                    let pos = self.pos;
+                    if let Some(alignment) = self.alignments.last_mut() {
+                        let pad = match alignment.targets.get(&alignment.mark) {
+                            Some(&target) => target.saturating_sub(pos),
+                            None => {
+                                if alignment.mark == alignment.targets.len() {
+                                    alignment.measured =
+                                        Some(cmp::max(alignment.measured.unwrap_or(0), pos));
+                                }
+                                0
+                            }
+                        };
+                        let spaces = cmp::min(pad, SPACES.len());
+                        if spaces < pad {
+                            // The mark is visited again for the rest of the padding
+                            self.bcmds.push((ind, mode, doc));
+                        } else {
+                            alignment.mark += 1;
+                        }
+                        if spaces != 0 {
+                            self.pos += spaces;
+                            self.mode = mode;
+                            self.check_annotations = true;
+                            return Some(LayoutCmd::Text(&SPACES[..spaces]));
+                        }
+                    }
+                }
                 Doc::Line => {
-                    write_newline(ind, out)?;
-                    pos = ind;
+                    for alignment in &mut self.alignments {
+                        alignment.mark = 0;
+                    }
+                    self.pos = ind;
+                    self.mode = mode;
+                    if let Some(stress) = &mut self.stress {
+                        stress.line += 1;
+                    }
+                    self.check_annotations = true;
+                    return Some(LayoutCmd::Newline(ind));
                 }
                 Doc::OwnedText(ref s) => {
-                    out.write_str_all(s)?;
-                    pos += s.len();
+                    self.pos += text_width(s);
+                    self.mode = mode;
+                    self.check_annotations = true;
+                    return Some(LayoutCmd::Text(s));
                 }
-                Doc::BorrowedText(ref s) => {
-                    out.write_str_all(s)?;
-                    pos += s.len();
+                Doc::BorrowedText(s) => {
+                    self.pos += text_width(s);
+                    self.mode = mode;
+                    self.check_annotations = true;
+                    return Some(LayoutCmd::Text(s));
                 }
                 Doc::SmallText(ref s) => {
-                    out.write_str_all(s)?;
-                    pos += s.len();
+                    self.pos += text_width(s);
+                    self.mode = mode;
+                    self.check_annotations = true;
+                    return Some(LayoutCmd::Text(s));
                 }
                 Doc::Annotated(ref ann, ref doc) => {
-                    out.push_annotation(ann)?;
-                    annotation_levels.push(bcmds.len());
-                    cmd = (ind, mode, doc);
-                    continue;
+                    self.annotation_levels.push((self.bcmds.len(), ann));
+                    self.mode = mode;
+                    self.cmd = Some((ind, mode, doc));
+                    return Some(LayoutCmd::PushAnnotation(ann));
                 }
                 Doc::Union(ref l, ref r) => {
-                    cmd = if fitting(&temp_arena, l, &bcmds, &mut fcmds, pos, width, ind, |_| {
-                        true
-                    }) {
-                        (ind, mode, l)
-                    } else {
-                        (ind, mode, r)
+                    let first = match self.cost {
+                        Some(cost) => {
+                            self.union_cost(cost, r, ind, mode)
+                                >= self.union_cost(cost, l, ind, mode)
+                        }
+                        None => self.fitting(l, ind, self.width, |_| true),
                     };
+                    if let Some(decisions) = &mut self.decisions {
+                        decisions.push(Decision::Union(first, self.pos));
+                    }
+                    self.cmd = Some((ind, mode, if first { l } else { r }));
                     continue;
                 }
                 Doc::Column(ref f) => {
-                    cmd = (ind, mode, temp_arena.alloc(f(pos)));
+                    self.cmd = Some((ind, mode, self.temp_arena.alloc(f(self.pos))));
                     continue;
                 }
                 Doc::Nesting(ref f) => {
-                    cmd = (ind, mode, temp_arena.alloc(f(ind)));
+                    self.cmd = Some((ind, mode, self.temp_arena.alloc(f(ind))));
                     continue;
                 }
             }
 
-            break;
+            self.check_annotations = true;
+        }
+    }
+}
+
+fn write_newline<W>(
+    ind: usize,
+    indent: Indent,
+    line_ending: LineEnding,
+    out: &mut W,
+) -> Result<(), W::Error>
+where
+    W: ?Sized + Render,
+{
+    out.write_str_all(line_ending.as_str())?;
+    match indent {
+        Indent::Tabs(width) if width > 0 => {
+            for _ in 0..ind / width {
+                out.write_str_all("\t")?;
+            }
+            write_spaces(ind % width, out)
         }
-        while annotation_levels.last() == Some(&bcmds.len()) {
-            annotation_levels.pop();
-            out.pop_annotation()?;
+        _ => write_spaces(ind, out),
+    }
+}
+
+fn write_spaces<W>(spaces: usize, out: &mut W) -> Result<(), W::Error>
+where
+    W: ?Sized + Render,
+{
+    let mut inserted = 0;
+    while inserted < spaces {
+        let insert = cmp::min(SPACES.len(), spaces - inserted);
+        inserted += out.write_str(&SPACES[..insert])?;
+    }
+
+    Ok(())
+}
+
+/// The number of columns the layout counts for `s`, which is its length in bytes except that the
+/// bidi isolation characters (U+2066 to U+2069) take up no space, see
+/// [`BidiIsolate`](struct.BidiIsolate.html), and the box-drawing characters (U+2500 to U+257F)
+/// take up a single column, see [`tree`](../tree/fn.tree.html).
+pub(crate) fn text_width(s: &str) -> usize {
+    // All of the isolation and box-drawing characters start with the byte 0xE2 in UTF-8
+    if !s.as_bytes().contains(&0xE2) {
+        return s.len();
+    }
+    s.chars()
+        .map(|c| match c {
+            '\u{2066}'..='\u{2069}' => 0,
+            '\u{2500}'..='\u{257F}' => 1,
+            c => c.len_utf8(),
+        })
+        .sum()
+}
+
+/// The width of `doc` when flattened starting at `column`, or `None` if it contains a `hardline`.
+pub(crate) fn flat_width<'a, T, A>(doc: &Doc<'a, T, A>, column: usize) -> Option<usize>
+where
+    T: DocPtr<'a, A> + 'a,
+{
+    let temp_arena = LayoutArena::new();
+    let mut iter = LayoutIter::new(&temp_arena, doc, usize::MAX);
+    iter.bcmds = vec![(0, Mode::Flat, doc)];
+    iter.pos = column;
+    for cmd in &mut iter {
+        if let LayoutCmd::Newline(_) = cmd {
+            return None;
         }
     }
+    Some(iter.pos - column)
+}
+
+/// The groups of `doc` in the order they were laid out with `config`, together with their mode
+/// and the column they started at.
+pub(crate) fn group_modes<'a, T, A>(
+    doc: &Doc<'a, T, A>,
+    config: RenderConfig,
+) -> Vec<GroupMode<'a, T, A>>
+where
+    T: DocPtr<'a, A> + 'a,
+{
+    let temp_arena = LayoutArena::new();
+    let mut iter = LayoutIter::with_config(&temp_arena, doc, config);
+    iter.groups = Some(vec![]);
+    for _ in &mut iter {}
+    iter.groups.unwrap_or_default()
+}
+
+#[inline]
+pub fn fits<'a, T, A>(doc: &Doc<'a, T, A>, width: usize) -> bool
+where
+    T: DocPtr<'a, A> + 'a,
+{
+    let temp_arena = LayoutArena::new();
+    let mut iter = LayoutIter::new(&temp_arena, doc, width);
+    iter.bcmds.clear();
+    // Any newline means that some part of the document could not be flattened
+    iter.fitting(doc, 0, width, |_| false)
+}
+
+/// Like `best`, but aborts once `limits` are exceeded.
+pub fn best_limited<'a, W, T, A>(
+    doc: &Doc<'a, T, A>,
+    config: RenderConfig,
+    limits: Limits,
+    out: &mut W,
+) -> Result<(), LimitError<W::Error>>
+where
+    T: DocPtr<'a, A> + 'a,
+    W: ?Sized + RenderAnnotated<A>,
+{
+    let temp_arena = LayoutArena::new();
+    let mut iter = LayoutIter::with_config(&temp_arena, doc, config);
+    iter.budget = Some(Budget::new(limits));
+
+    let mut bytes = limits.max_bytes.unwrap_or(usize::MAX);
+    while let Some(cmd) = iter.next() {
+        let len = match cmd {
+            LayoutCmd::Text(s) => s.len(),
+            LayoutCmd::Newline(ind) => {
+                let indent = match iter.indent {
+                    Indent::Tabs(width) if width > 0 => ind / width + ind % width,
+                    _ => ind,
+                };
+                iter.line_ending.as_str().len() + indent
+            }
+            LayoutCmd::PushAnnotation(_) | LayoutCmd::PopAnnotation => 0,
+        };
+        bytes = match bytes.checked_sub(len) {
+            Some(bytes) => bytes,
+            None => return Err(LimitError::Exceeded(Limit::Bytes)),
+        };
+        iter.write_cmd(cmd, out).map_err(LimitError::Render)?;
+    }
+
+    match iter.budget.and_then(|budget| budget.exceeded) {
+        Some(limit) => Err(LimitError::Exceeded(limit)),
+        None => Ok(()),
+    }
+}
+
+pub fn best<'a, W, T, A>(
+    doc: &Doc<'a, T, A>,
+    config: RenderConfig,
+    cost: Option<&dyn Cost<A>>,
+    out: &mut W,
+) -> Result<(), W::Error>
+where
+    T: DocPtr<'a, A> + 'a,
+    W: ?Sized + RenderAnnotated<A>,
+{
+    best_at(doc, config, cost, (0, 0), out)
+}
+
+/// Like `best`, but starting at the column and with the indentation of `start`.
+pub fn best_at<'a, W, T, A>(
+    doc: &Doc<'a, T, A>,
+    config: RenderConfig,
+    cost: Option<&dyn Cost<A>>,
+    (column, indent): (usize, usize),
+    out: &mut W,
+) -> Result<(), W::Error>
+where
+    T: DocPtr<'a, A> + 'a,
+    W: ?Sized + RenderAnnotated<A>,
+{
+    let temp_arena = LayoutArena::new();
+    let mut iter = LayoutIter::with_config(&temp_arena, doc, config).starting_at(column, indent);
+    iter.cost = cost;
+
+    while let Some(cmd) = iter.next() {
+        iter.write_cmd(cmd, out)?;
+    }
 
     Ok(())
 }
@@ -0,0 +1,139 @@
+//! Layout helpers for code generators emitting Rust.
+//!
+//! The helpers follow the formatting used by `rustfmt`: literals are kept on a single line when
+//! they fit, otherwise each field is placed on its own line, indented by four spaces and
+//! followed by a trailing comma.
+//!
+//! ```
+//! use pretty::rust::{enum_variant, struct_lit, Payload};
+//! use pretty::{Arena, DocAllocator};
+//!
+//! let arena = Arena::<()>::new();
+//! let doc = struct_lit(
+//!     &arena,
+//!     "Point",
+//!     vec![("x", arena.text("1")), ("y", arena.text("2"))],
+//! );
+//! assert_eq!(doc.1.pretty(80).to_string(), "Point { x: 1, y: 2 }");
+//! assert_eq!(doc.1.pretty(10).to_string(), "Point {\n    x: 1,\n    y: 2,\n}");
+//!
+//! let doc = enum_variant(&arena, "Some", Payload::Tuple(vec![arena.text("1")]));
+//! assert_eq!(doc.1.pretty(80).to_string(), "Some(1)");
+//! ```
+
+use std::borrow::Cow;
+
+use crate::{DocAllocator, DocBuilder};
+
+const INDENT: isize = 4;
+
+/// The fields of an enum variant.
+pub enum Payload<'a, D, A = ()>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+{
+    /// A variant without fields, `Name`.
+    Unit,
+    /// A variant with positional fields, `Name(a, b)`.
+    Tuple(Vec<DocBuilder<'a, D, A>>),
+    /// A variant with named fields, `Name { a: x, b: y }`.
+    Struct(Vec<(Cow<'a, str>, DocBuilder<'a, D, A>)>),
+}
+
+/// Lays out `items` between `open` and `close`, either on a single line separated by `, ` or with
+/// one item per line followed by a trailing comma. `pad` is placed on the inside of the
+/// delimiters.
+fn delimited<'a, D, A, I>(
+    allocator: &'a D,
+    open: &'a str,
+    items: I,
+    close: &'a str,
+    pad: fn(&'a D) -> DocBuilder<'a, D, A>,
+) -> DocBuilder<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+    I: IntoIterator<Item = DocBuilder<'a, D, A>>,
+{
+    let mut items = items.into_iter();
+    let first = match items.next() {
+        Some(first) => first,
+        None => return allocator.text(open).append(close),
+    };
+    let body = items.fold(first, |body, item| {
+        body.append(",").append(allocator.line()).append(item)
+    });
+    allocator
+        .text(open)
+        .append(
+            pad(allocator)
+                .append(body)
+                .append(allocator.text(",").flat_alt(allocator.nil()))
+                .nest(INDENT),
+        )
+        .append(pad(allocator))
+        .append(close)
+        .group()
+}
+
+fn named_fields<'a, D, A, I, K>(
+    allocator: &'a D,
+    fields: I,
+) -> impl Iterator<Item = DocBuilder<'a, D, A>>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+    I: IntoIterator<Item = (K, DocBuilder<'a, D, A>)>,
+    K: Into<Cow<'a, str>>,
+    A: 'a,
+{
+    fields
+        .into_iter()
+        .map(move |(name, value)| allocator.text(name).append(": ").append(value))
+}
+
+/// A struct literal, `Name { a: x, b: y }`.
+pub fn struct_lit<'a, D, A, N, I, K>(allocator: &'a D, name: N, fields: I) -> DocBuilder<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+    N: Into<Cow<'a, str>>,
+    I: IntoIterator<Item = (K, DocBuilder<'a, D, A>)>,
+    K: Into<Cow<'a, str>>,
+{
+    allocator.text(name).append(" ").append(delimited(
+        allocator,
+        "{",
+        named_fields(allocator, fields),
+        "}",
+        |allocator| allocator.line(),
+    ))
+}
+
+/// A tuple struct literal or function call, `Name(a, b)`.
+pub fn tuple_lit<'a, D, A, N, I>(allocator: &'a D, name: N, items: I) -> DocBuilder<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+    N: Into<Cow<'a, str>>,
+    I: IntoIterator<Item = DocBuilder<'a, D, A>>,
+{
+    allocator
+        .text(name)
+        .append(delimited(allocator, "(", items, ")", |allocator| {
+            allocator.line_()
+        }))
+}
+
+/// An enum variant, `name` is typically a path such as `Option::Some`.
+pub fn enum_variant<'a, D, A, N>(
+    allocator: &'a D,
+    name: N,
+    payload: Payload<'a, D, A>,
+) -> DocBuilder<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+    N: Into<Cow<'a, str>>,
+{
+    match payload {
+        Payload::Unit => allocator.text(name),
+        Payload::Tuple(items) => tuple_lit(allocator, name, items),
+        Payload::Struct(fields) => struct_lit(allocator, name, fields),
+    }
+}
@@ -0,0 +1,134 @@
+//! Layout helpers for SQL-like languages built from keyword-led clauses.
+//!
+//! A query is kept on a single line if it fits, otherwise every clause starts on its own line
+//! and clause bodies which do not fit are broken and indented below their keyword. `AND`/`OR`
+//! chains put each operator at the start of a line, aligned with the first condition.
+//!
+//! ```
+//! use pretty::sql::{and, clause, list, query};
+//! use pretty::{Arena, DocAllocator};
+//!
+//! let arena = Arena::<()>::new();
+//! let doc = query(&arena, vec![
+//!     clause(&arena, "SELECT", list(&arena, vec![arena.text("id"), arena.text("name")])),
+//!     clause(&arena, "FROM", arena.text("users")),
+//!     clause(&arena, "WHERE", and(&arena, vec![arena.text("age > 18"), arena.text("active")])),
+//! ]);
+//! assert_eq!(
+//!     doc.1.pretty(80).to_string(),
+//!     "SELECT id, name FROM users WHERE age > 18 AND active",
+//! );
+//! assert_eq!(
+//!     doc.1.pretty(20).to_string(),
+//!     "SELECT id, name\nFROM users\nWHERE\n  age > 18\n  AND active",
+//! );
+//! ```
+
+use std::borrow::Cow;
+
+use crate::{DocAllocator, DocBuilder};
+
+const INDENT: isize = 2;
+
+fn join<'a, D, A, I>(
+    allocator: &'a D,
+    docs: I,
+    separator: impl Fn() -> DocBuilder<'a, D, A>,
+) -> DocBuilder<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+    I: IntoIterator<Item = DocBuilder<'a, D, A>>,
+{
+    let mut docs = docs.into_iter();
+    match docs.next() {
+        Some(first) => docs.fold(first, |doc, next| doc.append(separator()).append(next)),
+        None => allocator.nil(),
+    }
+}
+
+/// A sequence of clauses, placed on separate lines unless the whole query fits on one line.
+pub fn query<'a, D, A, I>(allocator: &'a D, clauses: I) -> DocBuilder<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+    I: IntoIterator<Item = DocBuilder<'a, D, A>>,
+{
+    join(allocator, clauses, || allocator.line()).group()
+}
+
+/// A clause led by `keyword`, such as `SELECT` or `WHERE`. If `body` does not fit after the
+/// keyword it is placed on the following lines, indented.
+pub fn clause<'a, D, A, K>(
+    allocator: &'a D,
+    keyword: K,
+    body: DocBuilder<'a, D, A>,
+) -> DocBuilder<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+    K: Into<Cow<'a, str>>,
+{
+    allocator
+        .text(keyword)
+        .append(allocator.line().append(body).nest(INDENT))
+        .group()
+}
+
+/// A comma separated list, with one item per line if it does not fit.
+pub fn list<'a, D, A, I>(allocator: &'a D, items: I) -> DocBuilder<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+    I: IntoIterator<Item = DocBuilder<'a, D, A>>,
+{
+    join(allocator, items, || {
+        allocator.text(",").append(allocator.line())
+    })
+    .group()
+}
+
+/// Conditions joined by `operator`, with each operator starting a new line if the chain does not
+/// fit.
+pub fn chain<'a, D, A, I>(
+    allocator: &'a D,
+    operator: &'a str,
+    conditions: I,
+) -> DocBuilder<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+    I: IntoIterator<Item = DocBuilder<'a, D, A>>,
+{
+    join(allocator, conditions, || {
+        allocator.line().append(operator).append(" ")
+    })
+    .group()
+}
+
+/// Conditions joined by `AND`.
+pub fn and<'a, D, A, I>(allocator: &'a D, conditions: I) -> DocBuilder<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+    I: IntoIterator<Item = DocBuilder<'a, D, A>>,
+{
+    chain(allocator, "AND", conditions)
+}
+
+/// Conditions joined by `OR`.
+pub fn or<'a, D, A, I>(allocator: &'a D, conditions: I) -> DocBuilder<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+    I: IntoIterator<Item = DocBuilder<'a, D, A>>,
+{
+    chain(allocator, "OR", conditions)
+}
+
+/// `body` in parentheses, such as a subquery or a nested condition. If it does not fit the body
+/// is placed on its own lines, indented.
+pub fn parens<'a, D, A>(allocator: &'a D, body: DocBuilder<'a, D, A>) -> DocBuilder<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+{
+    allocator
+        .text("(")
+        .append(allocator.line_().append(body).nest(INDENT))
+        .append(allocator.line_())
+        .append(")")
+        .group()
+}
@@ -0,0 +1,250 @@
+//! Statistics about the layout of a document, for tuning how a formatter groups its output.
+//!
+//! [`break_heat_map`](fn.break_heat_map.html) lays out a document and returns a copy of it where
+//! every group which was reached is annotated with its [`GroupStats`](struct.GroupStats.html): if
+//! it broke, how wide it would have been on a single line and at which column the decision was
+//! made. The copy can be rendered with any renderer, or dumped with [`trace`](../trace/index.html),
+//! to see which groups break and how close they were to fitting.
+//!
+//! ```
+//! use pretty::stats::{self, Annotation};
+//! use pretty::{trace, Arena, DocAllocator, RenderConfig};
+//!
+//! let arena = Arena::<()>::new();
+//! let call = |name| {
+//!     arena
+//!         .text(name)
+//!         .append("(")
+//!         .append(arena.line_().append("argument").nest(4))
+//!         .append(arena.line_())
+//!         .append(")")
+//!         .group()
+//! };
+//! let doc = call("short").append(arena.hardline()).append(call("much_longer_name"));
+//!
+//! let heat_arena = Arena::new();
+//! let heat = stats::break_heat_map(&doc.1, RenderConfig::new(20), &heat_arena);
+//! let mut out = Vec::new();
+//! trace::json(&heat.1, 20, &mut out, |ann| match ann {
+//!     Annotation::Group(stats) => format!(
+//!         "broke={} flat_width={:?} column={}",
+//!         stats.broke, stats.flat_width, stats.column
+//!     ),
+//!     Annotation::Doc(()) => String::new(),
+//! })
+//! .unwrap();
+//! let out = String::from_utf8(out).unwrap();
+//! assert!(out.contains("broke=false flat_width=Some(15) column=0"));
+//! assert!(out.contains("broke=true flat_width=Some(26) column=0"));
+//! ```
+//!
+//! [`suggest_width`](fn.suggest_width.html) finds the narrowest page a document can be rendered
+//! on without any line overflowing, for sizing a pane to its content or warning that a terminal
+//! is too narrow.
+
+use std::{cmp, collections::HashMap, convert::Infallible, rc::Rc};
+
+use crate::{
+    deep, render, BuildDoc, Doc, DocAllocator, DocBuilder, DocPtr, LayoutArena, LayoutCmd,
+    LayoutIter, Mode, RenderConfig,
+};
+
+/// How a group was laid out.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct GroupStats {
+    /// Whether the line breaks of the group were taken.
+    pub broke: bool,
+    /// The width of the group when laid out on a single line, or `None` if it contains a
+    /// `hardline`.
+    pub flat_width: Option<usize>,
+    /// The column at which the group starts, where it was decided whether it breaks.
+    pub column: usize,
+}
+
+/// The annotations of a break heat map.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Annotation<A> {
+    /// Added around every group which was laid out.
+    Group(GroupStats),
+    /// An annotation of the original document.
+    Doc(A),
+}
+
+/// Lays out `doc` with `config` and copies it into `allocator` with every group which was laid out
+/// annotated with its [`GroupStats`](struct.GroupStats.html). Groups which are reached several
+/// times, such as shared documents of an `Arena`, are annotated with the first time they were laid
+/// out. Groups inside of `column` and `nesting` documents are not annotated.
+pub fn break_heat_map<'a, 'b, T, A, D>(
+    doc: &Doc<'a, T, A>,
+    config: RenderConfig,
+    allocator: &'b D,
+) -> DocBuilder<'b, D, Annotation<A>>
+where
+    'a: 'b,
+    T: DocPtr<'a, A> + 'a,
+    A: Clone + 'b,
+    D: ?Sized + DocAllocator<'b, Annotation<A>>,
+{
+    let mut groups = HashMap::new();
+    for (group, mode, column) in render::group_modes(doc, config) {
+        groups.entry(group).or_insert((mode, column));
+    }
+    copy(doc, &groups, allocator)
+}
+
+fn copy<'a, 'b, T, A, D>(
+    doc: &Doc<'a, T, A>,
+    groups: &HashMap<*const Doc<'a, T, A>, (Mode, usize)>,
+    allocator: &'b D,
+) -> DocBuilder<'b, D, Annotation<A>>
+where
+    'a: 'b,
+    T: DocPtr<'a, A> + 'a,
+    A: Clone + 'b,
+    D: ?Sized + DocAllocator<'b, Annotation<A>>,
+{
+    // The documents returned by `column` and `nesting` are not the ones which were laid out, so
+    // they are copied without annotating their groups
+    let f = deep::Mapper {
+        text: None,
+        annotation: Rc::new(|ann: &A| Annotation::Doc(ann.clone())),
+    };
+    let copy = deep::fold(
+        doc,
+        |doc| Ok::<_, Infallible>(deep::map_leaf(doc, allocator, &f)),
+        |doc, children| {
+            let new = DocBuilder(
+                allocator,
+                deep::rebuild(doc, children, &*f.annotation).into(),
+            );
+            match (groups.get(&(doc as *const _)), doc) {
+                (Some(&(mode, column)), Doc::Group(ref child) | Doc::BiasedGroup(_, ref child)) => {
+                    new.annotate(Annotation::Group(GroupStats {
+                        broke: mode == Mode::Break,
+                        flat_width: render::flat_width(child, column),
+                        column,
+                    }))
+                    .into_doc()
+                }
+                _ => new.into_doc(),
+            }
+        },
+    );
+    DocBuilder(allocator, BuildDoc::DocPtr(deep::infallible(copy)))
+}
+
+/// The widths a document needs, see [`suggest_width`](fn.suggest_width.html).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WidthSuggestion {
+    /// The longest line when every group breaks, which is the longest text the layout cannot
+    /// break, including its indentation. Any narrower width overflows.
+    pub longest_run: usize,
+    /// For every group, the width needed to lay it out on a single line when every group around
+    /// it breaks, in ascending order. Groups containing a `hardline` are left out. A width
+    /// between two of these flattens the groups up to it, if they are not nested in each other.
+    pub group_widths: Vec<usize>,
+    /// The narrowest width at which no line is longer than the width.
+    pub min_width: usize,
+}
+
+/// Analyzes the layouts of `doc` to suggest the narrowest width it can be rendered at without
+/// overflowing.
+///
+/// The width is searched for by bisection, which assumes that a document that does not overflow
+/// a width does not overflow any wider one either. This holds unless `union`, `column` or
+/// `nesting` documents choose layouts which are wider than the page.
+///
+/// ```
+/// use pretty::stats::{self, WidthSuggestion};
+/// use pretty::{Arena, DocAllocator};
+///
+/// let arena = Arena::<()>::new();
+/// let doc = arena
+///     .text("call(")
+///     .append(arena.line_().append("first_argument,").nest(4))
+///     .append(arena.line().append("second").nest(4))
+///     .append(arena.line_())
+///     .append(")")
+///     .group();
+///
+/// assert_eq!(
+///     stats::suggest_width(&doc.1),
+///     WidthSuggestion {
+///         longest_run: 19,
+///         group_widths: vec![28],
+///         min_width: 19,
+///     },
+/// );
+/// ```
+pub fn suggest_width<'a, T, A>(doc: &Doc<'a, T, A>) -> WidthSuggestion
+where
+    T: DocPtr<'a, A> + 'a,
+{
+    let longest_run = longest_line(doc, 0);
+
+    // The first time each group was laid out
+    let mut columns: HashMap<_, _> = render::group_modes(doc, RenderConfig::new(0))
+        .into_iter()
+        .rev()
+        .map(|(group, _, column)| (group, column))
+        .collect();
+    let mut group_widths = vec![];
+    let mut stack = vec![doc];
+    while let Some(doc) = stack.pop() {
+        match *doc {
+            Doc::Append(ref l, ref r) | Doc::FlatAlt(ref l, ref r) | Doc::Union(ref l, ref r) => {
+                stack.push(r);
+                stack.push(l);
+            }
+            Doc::Group(ref child) | Doc::BiasedGroup(_, ref child) => {
+                if let Some(column) = columns.remove(&(doc as *const _)) {
+                    group_widths.extend(render::flat_width(child, column).map(|w| column + w));
+                }
+                stack.push(child);
+            }
+            Doc::Nest(_, ref child)
+            | Doc::MaxWidth(_, ref child)
+            | Doc::Unbounded(ref child)
+            | Doc::AlignmentGroup(ref child)
+            | Doc::Delimiter(ref child)
+            | Doc::Annotated(_, ref child) => stack.push(child),
+            _ => (),
+        }
+    }
+    group_widths.sort_unstable();
+
+    // Nothing breaks on an unbounded page, so its longest line fits on a page of that width
+    let (mut lo, mut hi) = (
+        longest_run,
+        cmp::max(longest_run, longest_line(doc, usize::MAX)),
+    );
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if longest_line(doc, mid) > mid {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+
+    WidthSuggestion {
+        longest_run,
+        group_widths,
+        min_width: lo,
+    }
+}
+
+fn longest_line<'a, T, A>(doc: &Doc<'a, T, A>, width: usize) -> usize
+where
+    T: DocPtr<'a, A> + 'a,
+{
+    let temp_arena = LayoutArena::new();
+    let mut iter = LayoutIter::new(&temp_arena, doc, width);
+    let mut longest = 0;
+    while let Some(cmd) = iter.next() {
+        if let LayoutCmd::Text(_) = cmd {
+            longest = cmp::max(longest, iter.column());
+        }
+    }
+    longest
+}
@@ -0,0 +1,64 @@
+//! Helpers for testing formatters.
+
+/// The whitespace between two characters of a rendered string.
+#[derive(Clone, Copy, PartialEq)]
+enum Gap {
+    None,
+    Space,
+    /// Whitespace containing a line break, along with any indentation
+    Break,
+}
+
+/// The characters of `s` which are not whitespace, each with the whitespace before it.
+fn gaps(s: &str) -> Vec<(Gap, char)> {
+    let mut out = vec![];
+    let mut gap = Gap::None;
+    for c in s.chars() {
+        if c == '\n' || c == '\r' {
+            gap = Gap::Break;
+        } else if c.is_whitespace() {
+            if gap == Gap::None {
+                gap = Gap::Space;
+            }
+        } else {
+            out.push((gap, c));
+            gap = Gap::None;
+        }
+    }
+    out
+}
+
+/// Returns `true` if the rendered outputs `a` and `b` only differ in their layout, such as the
+/// same document rendered at different widths.
+///
+/// The text other than whitespace must be the same. Where either output breaks the line, the
+/// other may have any whitespace or none at all, as a `line` or `line_` may have been broken
+/// there. Elsewhere, whitespace in one output must be matched by whitespace in the other,
+/// although its amount may differ. Whitespace at the start and end is ignored.
+///
+/// ```
+/// use pretty::testing::eq_modulo_whitespace;
+/// use pretty::RcDoc;
+///
+/// let doc = RcDoc::<()>::text("f(")
+///     .append(RcDoc::line_().append("x,").append(RcDoc::line()).append("y").nest(4))
+///     .append(RcDoc::line_())
+///     .append(")")
+///     .group();
+/// let wide = doc.pretty(80).to_string();
+/// let narrow = doc.pretty(4).to_string();
+/// assert_eq!(wide, "f(x, y)");
+/// assert_eq!(narrow, "f(\n    x,\n    y\n)");
+/// assert!(eq_modulo_whitespace(&wide, &narrow));
+///
+/// // Spaces which are not at a line break matter
+/// assert!(!eq_modulo_whitespace("f(x, y)", "f(x,y)"));
+/// assert!(!eq_modulo_whitespace("f(x, y)", "f(x, z)"));
+/// ```
+pub fn eq_modulo_whitespace(a: &str, b: &str) -> bool {
+    let (a, b) = (gaps(a), gaps(b));
+    a.len() == b.len()
+        && a.iter().zip(&b).enumerate().all(|(i, (&(l, c), &(r, d)))| {
+            c == d && (i == 0 || l == r || l == Gap::Break || r == Gap::Break)
+        })
+}
@@ -0,0 +1,484 @@
+//! Styling of semantic annotations.
+//!
+//! Documents are annotated with a [`Tag`](enum.Tag.html) describing what a piece of text is,
+//! such as a keyword or a comment, and a [`Theme`](struct.Theme.html) decides how each tag is
+//! displayed. A theme is an [`AnnotationTable`](../trait.AnnotationTable.html), so it is applied
+//! at render time with [`Keyed`](../struct.Keyed.html) and can be swapped without rebuilding the
+//! document. The resolved [`Style`](struct.Style.html)s are understood by
+//! [`AnsiStyled`](struct.AnsiStyled.html), [`HtmlStyled`](struct.HtmlStyled.html) and, with the
+//! `termcolor` feature, `TermColored`.
+//!
+//! ```
+//! use pretty::theme::{AnsiStyled, Color, HtmlStyled, Style, Tag, Theme};
+//! use pretty::{Arena, DocAllocator, FmtWrite, Keyed};
+//!
+//! let arena = Arena::new();
+//! let doc = arena
+//!     .text("let")
+//!     .annotate(Tag::Keyword)
+//!     .append(" x = ")
+//!     .append(arena.text("1").annotate(Tag::Literal));
+//!
+//! let theme = Theme::dark().with(Tag::Literal, Style::new().fg(Color::Cyan));
+//! let mut s = String::new();
+//! let mut out = Keyed::new(AnsiStyled::new(FmtWrite::new(&mut s)), &theme);
+//! doc.1.render_raw(80, &mut out).unwrap();
+//! assert_eq!(s, "\x1b[0;1;35mlet\x1b[0m x = \x1b[0;36m1\x1b[0m");
+//!
+//! let theme = Theme::light();
+//! let mut s = String::new();
+//! let mut out = Keyed::new(HtmlStyled::new(FmtWrite::new(&mut s)), &theme);
+//! doc.1.render_raw(80, &mut out).unwrap();
+//! assert_eq!(
+//!     s,
+//!     "<span style=\"color:blue;font-weight:bold\">let</span> x = \
+//!      <span style=\"color:green\">1</span>",
+//! );
+//! ```
+//!
+//! Large HTML output can be made navigable by annotating regions with
+//! [`HtmlAnnotation::Collapsible`](enum.HtmlAnnotation.html#variant.Collapsible), which
+//! `HtmlStyled` folds into `<details>` elements.
+
+use std::borrow::Cow;
+
+use crate::{escape, AnnotationTable, FmtWrite, RcDoc, Render, RenderAnnotated};
+
+/// The kinds of text which are styled by a theme.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Tag {
+    Keyword,
+    Literal,
+    Comment,
+    Error,
+}
+
+const TAGS: usize = 4;
+
+/// A color of the terminal palette or an exact color.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Color {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    fn ansi(self, out: &mut String) {
+        let code = match self {
+            Color::Black => 30,
+            Color::Red => 31,
+            Color::Green => 32,
+            Color::Yellow => 33,
+            Color::Blue => 34,
+            Color::Magenta => 35,
+            Color::Cyan => 36,
+            Color::White => 37,
+            Color::Rgb(r, g, b) => {
+                out.push_str(&format!(";38;2;{};{};{}", r, g, b));
+                return;
+            }
+        };
+        out.push_str(&format!(";{}", code));
+    }
+
+    fn css(self) -> Cow<'static, str> {
+        Cow::Borrowed(match self {
+            Color::Black => "black",
+            Color::Red => "red",
+            Color::Green => "green",
+            Color::Yellow => "olive",
+            Color::Blue => "blue",
+            Color::Magenta => "purple",
+            Color::Cyan => "teal",
+            Color::White => "white",
+            Color::Rgb(r, g, b) => return Cow::Owned(format!("#{:02x}{:02x}{:02x}", r, g, b)),
+        })
+    }
+}
+
+/// How a piece of text is displayed.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bold: bool,
+    /// Not supported by `TermColored`.
+    pub italic: bool,
+    pub underline: bool,
+}
+
+impl Style {
+    /// The style which leaves text unchanged.
+    pub const fn new() -> Style {
+        Style {
+            fg: None,
+            bold: false,
+            italic: false,
+            underline: false,
+        }
+    }
+
+    pub const fn fg(self, color: Color) -> Style {
+        Style {
+            fg: Some(color),
+            ..self
+        }
+    }
+
+    pub const fn bold(self) -> Style {
+        Style { bold: true, ..self }
+    }
+
+    pub const fn italic(self) -> Style {
+        Style {
+            italic: true,
+            ..self
+        }
+    }
+
+    pub const fn underline(self) -> Style {
+        Style {
+            underline: true,
+            ..self
+        }
+    }
+}
+
+/// A mapping from tags to styles.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Theme {
+    styles: [Style; TAGS],
+}
+
+impl Theme {
+    /// A theme which leaves all text unstyled.
+    pub const fn plain() -> Theme {
+        Theme {
+            styles: [Style::new(); TAGS],
+        }
+    }
+
+    /// A theme for light backgrounds.
+    pub const fn light() -> Theme {
+        Theme::plain()
+            .with(Tag::Keyword, Style::new().fg(Color::Blue).bold())
+            .with(Tag::Literal, Style::new().fg(Color::Green))
+            .with(
+                Tag::Comment,
+                Style::new().fg(Color::Rgb(106, 115, 125)).italic(),
+            )
+            .with(Tag::Error, Style::new().fg(Color::Red).bold().underline())
+    }
+
+    /// A theme for dark backgrounds.
+    pub const fn dark() -> Theme {
+        Theme::plain()
+            .with(Tag::Keyword, Style::new().fg(Color::Magenta).bold())
+            .with(Tag::Literal, Style::new().fg(Color::Yellow))
+            .with(
+                Tag::Comment,
+                Style::new().fg(Color::Rgb(150, 150, 150)).italic(),
+            )
+            .with(Tag::Error, Style::new().fg(Color::Red).bold().underline())
+    }
+
+    /// Overrides the style of `tag`.
+    pub const fn with(mut self, tag: Tag, style: Style) -> Theme {
+        self.styles[tag as usize] = style;
+        self
+    }
+
+    pub fn style(&self, tag: Tag) -> Style {
+        self.styles[tag as usize]
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::plain()
+    }
+}
+
+impl AnnotationTable<Tag> for Theme {
+    type Value = Style;
+
+    fn get(&self, tag: &Tag) -> Option<&Style> {
+        Some(&self.styles[*tag as usize])
+    }
+}
+
+/// Writes styles as ANSI escape sequences.
+pub struct AnsiStyled<W> {
+    styles: Vec<Style>,
+    colors: bool,
+    upstream: W,
+}
+
+impl<W> AnsiStyled<W> {
+    /// Writes escape sequences if [`colors_enabled`](../fn.colors_enabled.html) returns `true`.
+    pub fn new(upstream: W) -> AnsiStyled<W> {
+        AnsiStyled {
+            styles: Vec::new(),
+            colors: crate::colors_enabled(),
+            upstream,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.upstream
+    }
+}
+
+impl<W> AnsiStyled<W>
+where
+    W: Render,
+{
+    fn set_style(&mut self, style: Option<Style>) -> Result<(), W::Error> {
+        if !self.colors {
+            return Ok(());
+        }
+        let style = match style {
+            Some(style) => style,
+            None => return self.upstream.write_str_all("\x1b[0m"),
+        };
+        // Reset first so that nothing of an enclosing style is left over
+        let mut codes = String::from("\x1b[0");
+        if style.bold {
+            codes.push_str(";1");
+        }
+        if style.italic {
+            codes.push_str(";3");
+        }
+        if style.underline {
+            codes.push_str(";4");
+        }
+        if let Some(color) = style.fg {
+            color.ansi(&mut codes);
+        }
+        codes.push('m');
+        self.upstream.write_str_all(&codes)
+    }
+}
+
+impl<W> Render for AnsiStyled<W>
+where
+    W: Render,
+{
+    type Error = W::Error;
+
+    fn write_str(&mut self, s: &str) -> Result<usize, W::Error> {
+        self.upstream.write_str(s)
+    }
+
+    fn write_str_all(&mut self, s: &str) -> Result<(), W::Error> {
+        self.upstream.write_str_all(s)
+    }
+}
+
+impl<W> RenderAnnotated<Style> for AnsiStyled<W>
+where
+    W: Render,
+{
+    fn push_annotation(&mut self, style: &Style) -> Result<(), Self::Error> {
+        self.styles.push(*style);
+        self.set_style(Some(*style))
+    }
+
+    fn pop_annotation(&mut self) -> Result<(), Self::Error> {
+        self.styles.pop();
+        self.set_style(self.styles.last().copied())
+    }
+}
+
+/// An annotation understood by [`HtmlStyled`](struct.HtmlStyled.html).
+#[derive(Clone, Debug)]
+pub enum HtmlAnnotation<'a> {
+    Style(Style),
+    /// Wraps the annotated region in a `<details>` element which can be folded, with `summary`
+    /// rendered on a single line as its `<summary>`. The region is unfolded initially if `open`
+    /// is `true`.
+    ///
+    /// Browsers display `<details>` as a block, so a collapsible region should start on a line
+    /// of its own.
+    Collapsible {
+        summary: RcDoc<'a, Style>,
+        open: bool,
+    },
+}
+
+impl From<Style> for HtmlAnnotation<'_> {
+    fn from(style: Style) -> Self {
+        HtmlAnnotation::Style(style)
+    }
+}
+
+/// Writes styles as HTML `<span>` elements with inline CSS and escapes the text.
+///
+/// ```
+/// use pretty::theme::{HtmlAnnotation, HtmlStyled, Style};
+/// use pretty::{Arena, DocAllocator, FmtWrite, RcDoc};
+///
+/// let arena = Arena::new();
+/// let section = |title: &'static str, body: &'static str| {
+///     let summary = RcDoc::text(title).annotate(Style::new().bold());
+///     arena
+///         .text(body)
+///         .annotate(HtmlAnnotation::Collapsible {
+///             summary,
+///             open: false,
+///         })
+/// };
+/// let doc = section("Errors", "a < b")
+///     .append(arena.hardline())
+///     .append(section("Warnings", "none"));
+///
+/// let mut s = String::new();
+/// doc.1
+///     .render_raw(80, &mut HtmlStyled::new(FmtWrite::new(&mut s)))
+///     .unwrap();
+/// assert_eq!(
+///     s,
+///     "<details><summary><span style=\"font-weight:bold\">Errors</span></summary>\
+///      a &lt; b</details>&#10;\
+///      <details><summary><span style=\"font-weight:bold\">Warnings</span></summary>\
+///      none</details>",
+/// );
+/// ```
+pub struct HtmlStyled<W> {
+    upstream: W,
+    /// Whether each open `HtmlAnnotation` is collapsible
+    collapsible: Vec<bool>,
+}
+
+impl<W> HtmlStyled<W> {
+    pub fn new(upstream: W) -> HtmlStyled<W> {
+        HtmlStyled {
+            upstream,
+            collapsible: Vec::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.upstream
+    }
+}
+
+impl<W> Render for HtmlStyled<W>
+where
+    W: Render,
+{
+    type Error = W::Error;
+
+    fn write_str(&mut self, s: &str) -> Result<usize, W::Error> {
+        self.write_str_all(s).map(|_| s.len())
+    }
+
+    fn write_str_all(&mut self, s: &str) -> Result<(), W::Error> {
+        self.upstream.write_str_all(&escape::html(s.into()))
+    }
+}
+
+impl<W> RenderAnnotated<Style> for HtmlStyled<W>
+where
+    W: Render,
+{
+    fn push_annotation(&mut self, style: &Style) -> Result<(), Self::Error> {
+        let mut css = vec![];
+        if let Some(color) = style.fg {
+            css.push(format!("color:{}", color.css()));
+        }
+        if style.bold {
+            css.push("font-weight:bold".into());
+        }
+        if style.italic {
+            css.push("font-style:italic".into());
+        }
+        if style.underline {
+            css.push("text-decoration:underline".into());
+        }
+        self.upstream
+            .write_str_all(&format!("<span style=\"{}\">", css.join(";")))
+    }
+
+    fn pop_annotation(&mut self) -> Result<(), Self::Error> {
+        self.upstream.write_str_all("</span>")
+    }
+}
+
+#[cfg(feature = "termcolor")]
+impl From<Style> for termcolor::ColorSpec {
+    fn from(style: Style) -> Self {
+        let fg = style.fg.map(|color| match color {
+            Color::Black => termcolor::Color::Black,
+            Color::Red => termcolor::Color::Red,
+            Color::Green => termcolor::Color::Green,
+            Color::Yellow => termcolor::Color::Yellow,
+            Color::Blue => termcolor::Color::Blue,
+            Color::Magenta => termcolor::Color::Magenta,
+            Color::Cyan => termcolor::Color::Cyan,
+            Color::White => termcolor::Color::White,
+            Color::Rgb(r, g, b) => termcolor::Color::Rgb(r, g, b),
+        });
+        let mut spec = termcolor::ColorSpec::new();
+        spec.set_fg(fg)
+            .set_bold(style.bold)
+            .set_underline(style.underline);
+        spec
+    }
+}
+
+#[cfg(feature = "termcolor")]
+impl<W> RenderAnnotated<Style> for crate::TermColored<W>
+where
+    W: termcolor::WriteColor,
+{
+    fn push_annotation(&mut self, style: &Style) -> Result<(), Self::Error> {
+        RenderAnnotated::<termcolor::ColorSpec>::push_annotation(self, &(*style).into())
+    }
+
+    fn pop_annotation(&mut self) -> Result<(), Self::Error> {
+        RenderAnnotated::<termcolor::ColorSpec>::pop_annotation(self)
+    }
+}
+
+impl<'a, W> RenderAnnotated<HtmlAnnotation<'a>> for HtmlStyled<W>
+where
+    W: Render,
+{
+    fn push_annotation(&mut self, annotation: &HtmlAnnotation<'a>) -> Result<(), Self::Error> {
+        match annotation {
+            HtmlAnnotation::Style(style) => {
+                self.collapsible.push(false);
+                RenderAnnotated::<Style>::push_annotation(self, style)
+            }
+            HtmlAnnotation::Collapsible { summary, open } => {
+                self.collapsible.push(true);
+                let mut html = String::from(if *open {
+                    "<details open><summary>"
+                } else {
+                    "<details><summary>"
+                });
+                let mut out = HtmlStyled::new(FmtWrite::new(&mut html));
+                summary
+                    .render_raw(usize::MAX, &mut out)
+                    .expect("writing to a string does not fail");
+                html.push_str("</summary>");
+                self.upstream.write_str_all(&html)
+            }
+        }
+    }
+
+    fn pop_annotation(&mut self) -> Result<(), Self::Error> {
+        if self.collapsible.pop() == Some(true) {
+            self.upstream.write_str_all("</details>")
+        } else {
+            RenderAnnotated::<Style>::pop_annotation(self)
+        }
+    }
+}
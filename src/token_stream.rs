@@ -0,0 +1,258 @@
+//! Conversion of a `proc_macro2::TokenStream` into a document, so that procedural macros can show
+//! the code they expand to in a readable form.
+//!
+//! Delimited groups become groups of the document which break onto indented lines when they do
+//! not fit, and a brace group containing statements always does. The spacing between tokens is
+//! chosen by their kind, like `rustfmt` would for most code, without parsing the tokens as Rust.
+//!
+//! ```
+//! use pretty::token_stream::token_stream;
+//! use pretty::Arena;
+//!
+//! let tokens: proc_macro2::TokenStream = "fn add(a: u8, b: u8) -> u8 { let sum = a + b; sum }"
+//!     .parse()
+//!     .unwrap();
+//! let arena = Arena::<()>::new();
+//! let doc = token_stream(&arena, tokens);
+//! assert_eq!(
+//!     doc.1.pretty(80).to_string(),
+//!     "fn add(a: u8, b: u8) -> u8 {\n    let sum = a + b;\n    sum\n}",
+//! );
+//! ```
+
+use std::cmp;
+
+use proc_macro2::{Delimiter, Spacing, TokenStream, TokenTree};
+
+use crate::{DocAllocator, DocBuilder};
+
+const INDENT: isize = 4;
+
+/// Item keywords which may be followed by generic parameters after the name.
+const ITEMS: [&str; 6] = ["fn", "struct", "enum", "trait", "type", "union"];
+
+/// Keywords which are followed by an expression, so that an operator after them is a prefix
+/// operator.
+const KEYWORDS: [&str; 9] = [
+    "if", "while", "match", "return", "in", "let", "else", "break", "yield",
+];
+
+/// The operators consisting of several characters.
+const OPS: [&str; 25] = [
+    "::", "->", "=>", "==", "!=", "<=", ">=", "&&", "||", "+=", "-=", "*=", "/=", "%=", "^=", "&=",
+    "|=", "<<", ">>", "<<=", ">>=", "..", "...", "..=", "<-",
+];
+
+enum Token {
+    /// An identifier or a literal
+    Word(String),
+    /// Punctuation, with joint characters such as `::` or `->` merged
+    Op(String),
+    Group(Delimiter, TokenStream),
+}
+
+/// The token before the next one, which decides the space between them.
+#[derive(Clone, Copy, PartialEq)]
+enum Prev {
+    Start,
+    Word,
+    /// The name of an item, so that a following `<` opens generic parameters
+    ItemName,
+    /// An operator which is not followed by a space, such as a prefix operator or `::`
+    Tight,
+    /// An operator which is followed by a space
+    Spaced,
+    Comma,
+    Semicolon,
+    Group,
+    /// The brackets of an attribute such as `#[inline]`
+    Attribute,
+}
+
+fn tokens(stream: TokenStream) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut joint = false;
+    for tree in stream {
+        match tree {
+            TokenTree::Punct(punct) => {
+                match tokens.last_mut() {
+                    // Joint characters are only merged into known operators, as `<` in `Vec<&T>`
+                    // is joint with the `&` after it as well
+                    Some(Token::Op(op)) if joint && OPS.contains(&&*format!("{}{}", op, punct)) => {
+                        op.push(punct.as_char())
+                    }
+                    _ => tokens.push(Token::Op(punct.as_char().to_string())),
+                }
+                joint = punct.spacing() == Spacing::Joint;
+                continue;
+            }
+            TokenTree::Ident(ident) => tokens.push(Token::Word(ident.to_string())),
+            TokenTree::Literal(literal) => tokens.push(Token::Word(literal.to_string())),
+            TokenTree::Group(group) => tokens.push(Token::Group(group.delimiter(), group.stream())),
+        }
+        joint = false;
+    }
+    tokens
+}
+
+/// Converts `tokens` into a document.
+pub fn token_stream<'a, D, A>(allocator: &'a D, tokens: TokenStream) -> DocBuilder<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+{
+    stream(allocator, tokens, true).0.group()
+}
+
+fn group<'a, D, A>(
+    allocator: &'a D,
+    delimiter: Delimiter,
+    content: TokenStream,
+) -> DocBuilder<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+{
+    let (open, close) = match delimiter {
+        Delimiter::Parenthesis => ("(", ")"),
+        Delimiter::Bracket => ("[", "]"),
+        Delimiter::Brace => ("{", "}"),
+        Delimiter::None => return stream(allocator, content, false).0,
+    };
+    if content.is_empty() {
+        return allocator.text(open).append(close);
+    }
+    let brace = delimiter == Delimiter::Brace;
+    let (content, statements) = stream(allocator, content, brace);
+    let line = || match delimiter {
+        _ if statements => allocator.hardline(),
+        Delimiter::Brace => allocator.line(),
+        _ => allocator.line_(),
+    };
+    allocator
+        .text(open)
+        .append(line().append(content).nest(INDENT))
+        .append(line())
+        .append(close)
+        .group()
+}
+
+/// Converts an identifier or a literal. A string literal may span several lines, which are placed
+/// at the start of the line so that its value is unchanged.
+fn word<'a, D, A>(allocator: &'a D, word: &str) -> DocBuilder<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+{
+    let mut doc = allocator.nil();
+    for (i, line) in word.split('\n').enumerate() {
+        if i != 0 {
+            doc =
+                doc.append(allocator.nesting(move |nesting| {
+                    allocator.hardline().nest(-(nesting as isize)).into_doc()
+                }));
+        }
+        doc = doc.append(allocator.text(line.to_string()));
+    }
+    doc
+}
+
+/// Converts a sequence of tokens, which are separated by line breaks after each `;` if they are
+/// `statements`. Also returns whether any `;` was found in that case.
+fn stream<'a, D, A>(
+    allocator: &'a D,
+    stream: TokenStream,
+    statements: bool,
+) -> (DocBuilder<'a, D, A>, bool)
+where
+    D: ?Sized + DocAllocator<'a, A>,
+{
+    let mut doc = allocator.nil();
+    let mut prev = Prev::Start;
+    // The text of the previous token if it was a word or an operator
+    let mut prev_text = String::new();
+    // The number of `<` which open generic arguments and have not been closed yet
+    let mut generics = 0;
+    // Whether the parameters of a closure are being converted
+    let mut closure = false;
+    let mut has_statements = false;
+
+    for token in tokens(stream) {
+        let (tight_before, next, token, text) = match token {
+            Token::Word(word) => {
+                let next = if prev == Prev::Word && ITEMS.contains(&&prev_text[..]) {
+                    Prev::ItemName
+                } else if KEYWORDS.contains(&&word[..]) {
+                    Prev::Spaced
+                } else {
+                    Prev::Word
+                };
+                (false, next, self::word(allocator, &word), word)
+            }
+            Token::Group(delimiter, content) => {
+                let tight = delimiter != Delimiter::Brace
+                    && matches!(prev, Prev::Word | Prev::ItemName | Prev::Group);
+                let next = if delimiter == Delimiter::Bracket
+                    && prev == Prev::Tight
+                    && (prev_text == "#" || prev_text == "!")
+                {
+                    Prev::Attribute
+                } else {
+                    Prev::Group
+                };
+                let group = group(allocator, delimiter, content);
+                (tight, next, group, String::new())
+            }
+            Token::Op(op) => {
+                let after_word = matches!(prev, Prev::Word | Prev::ItemName | Prev::Group);
+                let (tight_before, next) = match &op[..] {
+                    "," => (true, Prev::Comma),
+                    ";" => {
+                        has_statements |= statements;
+                        (true, Prev::Semicolon)
+                    }
+                    "::" | "." | ".." => (true, Prev::Tight),
+                    "#" | "'" | "$" => (false, Prev::Tight),
+                    "!" if after_word => (true, Prev::Tight),
+                    "?" => (true, Prev::Word),
+                    ":" => (true, Prev::Spaced),
+                    "<" if prev == Prev::ItemName
+                        || prev == Prev::Word
+                            && (prev_text == "impl"
+                                || prev_text.starts_with(char::is_uppercase))
+                        || prev == Prev::Tight && prev_text == "::" =>
+                    {
+                        generics += 1;
+                        (true, Prev::Tight)
+                    }
+                    _ if generics > 0 && op.chars().all(|c| c == '>') => {
+                        generics -= cmp::min(generics, op.len());
+                        (true, Prev::Word)
+                    }
+                    "|" if closure => {
+                        closure = false;
+                        (true, Prev::Spaced)
+                    }
+                    "|" if !after_word => {
+                        closure = true;
+                        (false, Prev::Tight)
+                    }
+                    "||" if !after_word => (false, Prev::Spaced),
+                    // A prefix operator, such as `&`, `-` or `!`
+                    _ if !after_word => (false, Prev::Tight),
+                    _ => (false, Prev::Spaced),
+                };
+                (tight_before, next, allocator.text(op.clone()), op)
+            }
+        };
+        let space = match prev {
+            Prev::Start | Prev::Tight => allocator.nil(),
+            Prev::Comma if generics > 0 => allocator.text(" "),
+            Prev::Comma => allocator.line(),
+            Prev::Semicolon | Prev::Attribute if statements => allocator.hardline(),
+            _ if tight_before => allocator.nil(),
+            _ => allocator.text(" "),
+        };
+        doc = doc.append(space).append(token);
+        prev = next;
+        prev_text = text;
+    }
+    (doc, has_statements)
+}
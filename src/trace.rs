@@ -0,0 +1,113 @@
+//! A machine readable dump of the layout of a document.
+//!
+//! [`json`](fn.json.html) writes each command of the chosen layout as a JSON object on its own
+//! line ([JSON Lines](https://jsonlines.org/)), so that layouts can be verified and consumed by
+//! tools written in other languages. The events are:
+//!
+//! - `{"event":"text","line":L,"column":C,"text":"..."}` for text placed at line `L`, column `C`
+//! - `{"event":"newline","indent":N}` for a line break followed by `N` spaces of indentation
+//! - `{"event":"push_annotation","annotation":"..."}` when an annotated document is entered
+//! - `{"event":"pop_annotation"}` when it is left
+//! - `{"event":"group","line":L,"column":C,"mode":"flat"}` when a group starting at line `L`,
+//!   column `C` is laid out, with the mode `"flat"` if it fit on the line or `"break"` otherwise
+//! - `{"event":"union","line":L,"column":C,"alternative":"first"}` when the `"first"` or
+//!   `"second"` alternative of a union is chosen
+//!
+//! Lines and columns start at 0 and columns are measured in bytes, the same as the layout.
+//!
+//! ```
+//! use pretty::{trace, Arena, DocAllocator};
+//!
+//! let arena = Arena::new();
+//! let doc = arena
+//!     .text("let")
+//!     .append(arena.line())
+//!     .append(arena.text("x").annotate("ident"))
+//!     .nest(4)
+//!     .group();
+//!
+//! let mut out = Vec::new();
+//! trace::json(&doc.1, 3, &mut out, |ann: &&str| ann.to_string()).unwrap();
+//! assert_eq!(
+//!     String::from_utf8(out).unwrap(),
+//!     r#"{"event":"group","line":0,"column":0,"mode":"break"}
+//! {"event":"text","line":0,"column":0,"text":"let"}
+//! {"event":"newline","indent":4}
+//! {"event":"push_annotation","annotation":"ident"}
+//! {"event":"text","line":1,"column":4,"text":"x"}
+//! {"event":"pop_annotation"}
+//! "#,
+//! );
+//! ```
+
+use std::io;
+
+use crate::{escape, render::Decision, Doc, DocPtr, LayoutArena, LayoutCmd, LayoutIter, Mode};
+
+/// Writes the layout of `doc` at `width` to `out` as JSON Lines. `annotation` converts each
+/// annotation into the string stored in its `push_annotation` event.
+pub fn json<'a, T, A, W>(
+    doc: &Doc<'a, T, A>,
+    width: usize,
+    out: &mut W,
+    mut annotation: impl FnMut(&A) -> String,
+) -> io::Result<()>
+where
+    T: DocPtr<'a, A> + 'a,
+    W: ?Sized + io::Write,
+{
+    let temp_arena = LayoutArena::new();
+    let mut line = 0;
+    let mut column = 0;
+
+    let mut iter = LayoutIter::new(&temp_arena, doc, width);
+    iter.decisions = Some(vec![]);
+    while let Some(cmd) = iter.next() {
+        // The decisions were made while finding the command so they come before it
+        for decision in iter.decisions.as_mut().map_or(vec![], std::mem::take) {
+            match decision {
+                Decision::Group(mode, column) => writeln!(
+                    out,
+                    r#"{{"event":"group","line":{},"column":{},"mode":"{}"}}"#,
+                    line,
+                    column,
+                    match mode {
+                        Mode::Break => "break",
+                        Mode::Flat => "flat",
+                    }
+                )?,
+                Decision::Union(first, column) => writeln!(
+                    out,
+                    r#"{{"event":"union","line":{},"column":{},"alternative":"{}"}}"#,
+                    line,
+                    column,
+                    if first { "first" } else { "second" }
+                )?,
+            }
+        }
+        match cmd {
+            LayoutCmd::Text(text) => {
+                writeln!(
+                    out,
+                    r#"{{"event":"text","line":{},"column":{},"text":"{}"}}"#,
+                    line,
+                    column,
+                    escape::json(text.into())
+                )?;
+                column = iter.column();
+            }
+            LayoutCmd::Newline(indent) => {
+                writeln!(out, r#"{{"event":"newline","indent":{}}}"#, indent)?;
+                line += 1;
+                column = indent;
+            }
+            LayoutCmd::PushAnnotation(ann) => writeln!(
+                out,
+                r#"{{"event":"push_annotation","annotation":"{}"}}"#,
+                escape::json(annotation(ann).into())
+            )?,
+            LayoutCmd::PopAnnotation => writeln!(out, r#"{{"event":"pop_annotation"}}"#)?,
+        }
+    }
+    Ok(())
+}
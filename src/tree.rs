@@ -0,0 +1,109 @@
+//! Rendering of recursive structures as trees drawn with box-drawing characters.
+//!
+//! ```
+//! use pretty::tree::tree;
+//! use pretty::Arena;
+//!
+//! struct Node(&'static str, Vec<Node>);
+//!
+//! let root = Node("src", vec![
+//!     Node("lib.rs", vec![]),
+//!     Node("render", vec![Node("mod.rs", vec![])]),
+//!     Node("tree.rs", vec![]),
+//! ]);
+//!
+//! let arena = Arena::<()>::new();
+//! let doc = tree(&arena, &root, &|node: &Node| node.0, &|node: &Node| node.1.iter());
+//! assert_eq!(
+//!     doc.1.pretty(80).to_string(),
+//!     "src\n├── lib.rs\n├── render\n│   └── mod.rs\n└── tree.rs",
+//! );
+//! ```
+//!
+//! Labels which do not fit on a line are wrapped at whitespace, with the continuation lines
+//! prefixed by the vertical lines of the branches they are nested in. The box-drawing characters
+//! take up a single column each.
+//!
+//! ```
+//! # use pretty::tree::tree;
+//! # use pretty::Arena;
+//! # struct Node(&'static str, Vec<Node>);
+//! let root = Node("root", vec![
+//!     Node("a label which is too long", vec![Node("leaf", vec![])]),
+//!     Node("last", vec![]),
+//! ]);
+//!
+//! let arena = Arena::<()>::new();
+//! let doc = tree(&arena, &root, &|node: &Node| node.0, &|node: &Node| node.1.iter());
+//! assert_eq!(
+//!     doc.1.pretty(24).to_string(),
+//!     "root\n├── a label which is too\n│   long\n│   └── leaf\n└── last",
+//! );
+//! ```
+
+use std::borrow::Cow;
+
+use crate::{DocAllocator, DocBuilder};
+
+const BRANCH: &str = "├── ";
+const LAST_BRANCH: &str = "└── ";
+const VERTICAL: &str = "│   ";
+const EMPTY: &str = "    ";
+
+/// Renders `root` and its descendants as a tree. `label` provides the text of each node and
+/// `children` its child nodes. The tree is walked with an explicit stack so it may be arbitrarily
+/// deep.
+pub fn tree<'a, 't, D, A, T, L, S, C, I>(
+    allocator: &'a D,
+    root: &'t T,
+    label: &L,
+    children: &C,
+) -> DocBuilder<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+    T: ?Sized,
+    L: Fn(&'t T) -> S,
+    S: Into<Cow<'a, str>>,
+    C: Fn(&'t T) -> I,
+    I: IntoIterator<Item = &'t T>,
+{
+    let mut doc = allocator.nil();
+    // Nodes still to be drawn, with the prefix of their first line and of the lines below it
+    let mut stack = vec![(root, String::new(), String::new())];
+    while let Some((item, first, base)) = stack.pop() {
+        if !first.is_empty() {
+            doc = doc.append(allocator.hardline());
+        }
+        doc = doc.append(allocator.text(first));
+
+        let text = label(item).into();
+        for (i, word) in text.split_whitespace().enumerate() {
+            if i != 0 {
+                doc = doc.append(
+                    allocator
+                        .hardline()
+                        .append(allocator.text(base.clone()))
+                        .flat_alt(allocator.space())
+                        .group(),
+                );
+            }
+            doc = doc.append(allocator.text(word.to_string()));
+        }
+
+        let items: Vec<_> = children(item).into_iter().collect();
+        let last = items.len().saturating_sub(1);
+        for (i, child) in items.into_iter().enumerate().rev() {
+            let (branch, guide) = if i == last {
+                (LAST_BRANCH, EMPTY)
+            } else {
+                (BRANCH, VERTICAL)
+            };
+            stack.push((
+                child,
+                format!("{}{}", base, branch),
+                format!("{}{}", base, guide),
+            ));
+        }
+    }
+    doc
+}
@@ -0,0 +1,149 @@
+use std::{error::Error, fmt};
+
+use crate::{Doc, DocPtr};
+
+const PAIRS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+
+/// A problem found by [`Doc::validate`](../enum.Doc.html#method.validate).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ValidationError {
+    /// The path from the root of the document to the offending node, such as
+    /// `Group > Append.1 > Nest(-4)`.
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}", self.message, self.path)
+    }
+}
+
+impl Error for ValidationError {}
+
+pub(crate) fn validate<'a, T, A>(doc: &Doc<'a, T, A>) -> Result<(), ValidationError>
+where
+    T: DocPtr<'a, A>,
+{
+    // Each entry is the document, the length of the path to its parent, its own step, the
+    // indentation it is laid out with and whether it is part of a delimiter
+    let mut stack = vec![(doc, 0, String::from("root"), 0isize, false)];
+    let mut path: Vec<String> = vec![];
+    let mut open: Vec<(char, String)> = vec![];
+    let error = |path: &[String], message: String| ValidationError {
+        path: path.join(" > "),
+        message,
+    };
+
+    while let Some((doc, depth, step, indent, delimiter)) = stack.pop() {
+        path.truncate(depth);
+        path.push(step);
+        let depth = path.len();
+        let text = match *doc {
+            Doc::Nil | Doc::Line | Doc::AlignmentMark | Doc::Column(_) | Doc::Nesting(_) => {
+                continue
+            }
+            Doc::Append(ref l, ref r) => {
+                stack.push((r, depth, "Append.1".into(), indent, delimiter));
+                stack.push((l, depth, "Append.0".into(), indent, delimiter));
+                continue;
+            }
+            // The same alternatives as `collect_text`
+            Doc::FlatAlt(_, ref doc) => {
+                stack.push((doc, depth, "FlatAlt.1".into(), indent, delimiter));
+                continue;
+            }
+            Doc::Union(ref doc, _) => {
+                stack.push((doc, depth, "Union.0".into(), indent, delimiter));
+                continue;
+            }
+            Doc::Group(ref doc) => {
+                stack.push((doc, depth, "Group".into(), indent, delimiter));
+                continue;
+            }
+            Doc::BiasedGroup(bias, ref doc) => {
+                stack.push((
+                    doc,
+                    depth,
+                    format!("BiasedGroup({})", bias),
+                    indent,
+                    delimiter,
+                ));
+                continue;
+            }
+            Doc::MaxWidth(width, ref doc) => {
+                stack.push((
+                    doc,
+                    depth,
+                    format!("MaxWidth({})", width),
+                    indent,
+                    delimiter,
+                ));
+                continue;
+            }
+            Doc::Unbounded(ref doc) => {
+                stack.push((doc, depth, "Unbounded".into(), indent, delimiter));
+                continue;
+            }
+            Doc::AlignmentGroup(ref doc) => {
+                stack.push((doc, depth, "AlignmentGroup".into(), indent, delimiter));
+                continue;
+            }
+            Doc::Delimiter(ref doc) => {
+                stack.push((doc, depth, "Delimiter".into(), indent, true));
+                continue;
+            }
+            Doc::Annotated(_, ref doc) => {
+                stack.push((doc, depth, "Annotated".into(), indent, delimiter));
+                continue;
+            }
+            Doc::Nest(off, ref doc) => {
+                let step = format!("Nest({})", off);
+                let indent = indent.saturating_add(off);
+                if indent < 0 {
+                    path.push(step);
+                    return Err(error(
+                        &path,
+                        format!("nesting dedents {} columns past the left margin", -indent),
+                    ));
+                }
+                stack.push((doc, depth, step, indent, delimiter));
+                continue;
+            }
+            // Only the delimiters inserted by `enclose` are checked, other text may contain
+            // delimiters which are not meant to be balanced
+            _ if !delimiter => continue,
+            Doc::OwnedText(ref s) => &s[..],
+            Doc::BorrowedText(s) => s,
+            Doc::SmallText(ref s) => &s[..],
+        };
+
+        for c in text.chars() {
+            if PAIRS.iter().any(|&(l, _)| l == c) {
+                path.push(format!("Text({:?})", text));
+                open.push((c, path.join(" > ")));
+                path.pop();
+            } else if let Some(&(l, _)) = PAIRS.iter().find(|&&(_, r)| r == c) {
+                match open.pop() {
+                    Some((opened, _)) if opened == l => (),
+                    opened => {
+                        path.push(format!("Text({:?})", text));
+                        let message = match opened {
+                            Some((opened, _)) => format!("`{}` closes `{}`", c, opened),
+                            None => format!("`{}` has no opening delimiter", c),
+                        };
+                        return Err(error(&path, message));
+                    }
+                }
+            }
+        }
+    }
+
+    match open.pop() {
+        Some((c, path)) => Err(ValidationError {
+            path,
+            message: format!("`{}` is never closed", c),
+        }),
+        None => Ok(()),
+    }
+}
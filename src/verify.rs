@@ -0,0 +1,364 @@
+//! Verification that a formatter neither drops nor duplicates content and that its output is
+//! stable.
+//!
+//! A formatter which annotates every token of the source it formats can check the rendered
+//! output with [`tokens`](fn.tokens.html): the output must consist of exactly the tokens of the
+//! source, in order, separated by nothing but whitespace.
+//!
+//! ```
+//! use pretty::verify::{self, TokenError};
+//! use pretty::{Arena, DocAllocator, RenderConfig};
+//!
+//! #[derive(Clone, Copy, PartialEq)]
+//! enum Ann {
+//!     Token,
+//!     Keyword,
+//! }
+//!
+//! let source = ["let", "x", "=", "1", ";"];
+//! let arena = Arena::new();
+//! let token = |s: &'static str| arena.text(s).annotate(Ann::Token);
+//! let statement = token("let")
+//!     .annotate(Ann::Keyword)
+//!     .append(" ")
+//!     .append(token("x"))
+//!     .append(arena.line().append(token("=")).append(" ").append(token("1")).nest(4));
+//! let doc = statement.clone().append(token(";")).group();
+//!
+//! let is_token = |ann: &Ann| *ann == Ann::Token;
+//! assert_eq!(verify::tokens(&doc.1, RenderConfig::new(5), source, is_token), Ok(()));
+//!
+//! // A formatter which forgot the `;`
+//! let broken = statement.group();
+//! assert_eq!(
+//!     verify::tokens(&broken.1, RenderConfig::new(80), source, is_token),
+//!     Err(TokenError::Mismatch {
+//!         index: 4,
+//!         line: 0,
+//!         column: 9,
+//!         expected: Some(";".into()),
+//!         found: None,
+//!     }),
+//! );
+//! ```
+//!
+//! Formatting already formatted output must not change it, which
+//! [`idempotent`](fn.idempotent.html) checks by parsing the output back into a document.
+
+use std::{cmp, convert::Infallible, error::Error, fmt, ops::Deref};
+
+use crate::{Doc, DocPtr, Render, RenderAnnotated, RenderConfig};
+
+/// The first difference between the rendered output and the expected tokens.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TokenError {
+    /// Text which is not whitespace was written outside of any token.
+    Stray {
+        line: usize,
+        column: usize,
+        text: String,
+    },
+    /// The token at `index` differs from the expected one. `expected` is `None` if the output has
+    /// more tokens than expected and `found` is `None` if it has fewer.
+    Mismatch {
+        index: usize,
+        line: usize,
+        column: usize,
+        expected: Option<String>,
+        found: Option<String>,
+    },
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenError::Stray { line, column, text } => write!(
+                f,
+                "{}:{}: text outside of any token: {:?}",
+                line + 1,
+                column + 1,
+                text
+            ),
+            TokenError::Mismatch {
+                index,
+                line,
+                column,
+                expected,
+                found,
+            } => {
+                write!(f, "{}:{}: token {}: ", line + 1, column + 1, index)?;
+                match (expected, found) {
+                    (Some(expected), Some(found)) => {
+                        write!(f, "expected {:?}, found {:?}", expected, found)
+                    }
+                    (Some(expected), None) => write!(f, "expected {:?}, found the end", expected),
+                    (None, Some(found)) => write!(f, "unexpected {:?}", found),
+                    (None, None) => write!(f, "mismatch"),
+                }
+            }
+        }
+    }
+}
+
+impl Error for TokenError {}
+
+/// Records the output together with the byte range of every token.
+struct Tokens<F> {
+    is_token: F,
+    out: String,
+    /// Whether each open annotation is a token
+    open: Vec<bool>,
+    start: usize,
+    tokens: Vec<(usize, usize)>,
+}
+
+impl<F> Render for Tokens<F> {
+    type Error = Infallible;
+
+    fn write_str(&mut self, s: &str) -> Result<usize, Infallible> {
+        self.out.push_str(s);
+        Ok(s.len())
+    }
+}
+
+impl<A, F> RenderAnnotated<A> for Tokens<F>
+where
+    F: FnMut(&A) -> bool,
+{
+    fn push_annotation(&mut self, annotation: &A) -> Result<(), Infallible> {
+        // Tokens nested in a token are part of the outer one
+        let token = !self.open.contains(&true) && (self.is_token)(annotation);
+        if token {
+            self.start = self.out.len();
+        }
+        self.open.push(token);
+        Ok(())
+    }
+
+    fn pop_annotation(&mut self) -> Result<(), Infallible> {
+        if self.open.pop() == Some(true) {
+            self.tokens.push((self.start, self.out.len()));
+        }
+        Ok(())
+    }
+}
+
+fn position(out: &str, offset: usize) -> (usize, usize) {
+    let before = &out[..offset];
+    let line = before.matches('\n').count();
+    let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1);
+    (line, column)
+}
+
+/// Renders `doc` with `config` and checks that the output consists of the `expected` tokens, in
+/// order and separated only by whitespace. A token is the text of a document annotated with an
+/// annotation for which `is_token` returns `true`.
+pub fn tokens<'a, T, A, I>(
+    doc: &Doc<'a, T, A>,
+    config: RenderConfig,
+    expected: I,
+    is_token: impl FnMut(&A) -> bool,
+) -> Result<(), TokenError>
+where
+    T: DocPtr<'a, A> + 'a,
+    I: IntoIterator,
+    I::Item: AsRef<str>,
+{
+    let mut sink = Tokens {
+        is_token,
+        out: String::new(),
+        open: vec![],
+        start: 0,
+        tokens: vec![],
+    };
+    match doc.render_raw_with(config, &mut sink) {
+        Ok(()) => (),
+        Err(never) => match never {},
+    }
+    let out = &sink.out;
+
+    let stray = |from: usize, to: usize| {
+        let gap = &out[from..to];
+        match gap.find(|c: char| !c.is_whitespace()) {
+            None => Ok(()),
+            Some(i) => {
+                let (line, column) = position(out, from + i);
+                Err(TokenError::Stray {
+                    line,
+                    column,
+                    text: gap.trim().to_string(),
+                })
+            }
+        }
+    };
+
+    let mut expected = expected.into_iter();
+    let mut end = 0;
+    for (index, &(start, token_end)) in sink.tokens.iter().enumerate() {
+        stray(end, start)?;
+        let found = &out[start..token_end];
+        match expected.next() {
+            Some(expected) if expected.as_ref() == found => (),
+            expected => {
+                let (line, column) = position(out, start);
+                return Err(TokenError::Mismatch {
+                    index,
+                    line,
+                    column,
+                    expected: expected.map(|s| s.as_ref().to_string()),
+                    found: Some(found.to_string()),
+                });
+            }
+        }
+        end = token_end;
+    }
+    stray(end, out.len())?;
+    match expected.next() {
+        None => Ok(()),
+        Some(expected) => {
+            let (line, column) = position(out, out.len());
+            Err(TokenError::Mismatch {
+                index: sink.tokens.len(),
+                line,
+                column,
+                expected: Some(expected.as_ref().to_string()),
+                found: None,
+            })
+        }
+    }
+}
+
+/// The output of a formatter changed when it was formatted again, see
+/// [`idempotent`](fn.idempotent.html).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum IdempotenceError<E> {
+    /// The first output could not be parsed.
+    Parse(E),
+    /// Formatting the first output produced the second one.
+    Changed {
+        first: String,
+        second: String,
+        /// The lines which differ, prefixed with `-` if they are only in `first` and `+` if they
+        /// are only in `second`, after a `@@ line N @@` header giving the first line which
+        /// differs.
+        diff: String,
+    },
+}
+
+impl<E> fmt::Display for IdempotenceError<E>
+where
+    E: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IdempotenceError::Parse(err) => write!(f, "the output could not be parsed: {}", err),
+            IdempotenceError::Changed { diff, .. } => {
+                write!(f, "formatting the output changed it:\n{}", diff)
+            }
+        }
+    }
+}
+
+impl<E> Error for IdempotenceError<E> where E: fmt::Debug + fmt::Display {}
+
+/// Renders `doc` with `config`, parses the output back into a document with `parse` and checks
+/// that rendering that document produces the same output.
+///
+/// ```
+/// use pretty::verify::{self, IdempotenceError};
+/// use pretty::{RcDoc, RenderConfig};
+///
+/// let indent = |source: &str, trim: bool| -> RcDoc<()> {
+///     let lines = source.lines().map(|line| {
+///         let line = if trim { line.trim_start() } else { line };
+///         RcDoc::text(format!("  {}", line))
+///     });
+///     RcDoc::intersperse(lines, RcDoc::hardline())
+/// };
+/// let config = RenderConfig::new(80);
+///
+/// let doc = indent("a\n b", true);
+/// let reparse = |out: &str| Ok::<_, ()>(indent(out, true));
+/// assert_eq!(verify::idempotent(&doc, config, reparse), Ok(()));
+///
+/// // Indenting already indented lines again is not idempotent
+/// let doc = indent("a\nb", false);
+/// let reparse = |out: &str| Ok::<_, ()>(indent(out, false));
+/// match verify::idempotent(&doc, config, reparse) {
+///     Err(IdempotenceError::Changed { diff, .. }) => {
+///         assert_eq!(diff, "@@ line 1 @@\n-  a\n-  b\n+    a\n+    b\n");
+///     }
+///     result => panic!("{:?}", result),
+/// }
+/// ```
+pub fn idempotent<'a, 'b, T, A, U, B, D, E>(
+    doc: &Doc<'a, T, A>,
+    config: RenderConfig,
+    parse: impl FnOnce(&str) -> Result<D, E>,
+) -> Result<(), IdempotenceError<E>>
+where
+    T: DocPtr<'a, A> + 'a,
+    U: DocPtr<'b, B> + 'b,
+    B: 'b,
+    D: Deref<Target = Doc<'b, U, B>>,
+{
+    let first = doc.pretty_with(config).to_string();
+    let reparsed = parse(&first).map_err(IdempotenceError::Parse)?;
+    let second = reparsed.pretty_with(config).to_string();
+    if first == second {
+        return Ok(());
+    }
+    let diff = diff_lines(&first, &second);
+    Err(IdempotenceError::Changed {
+        first,
+        second,
+        diff,
+    })
+}
+
+/// A line diff of `old` and `new` which only shows the lines between the first and the last
+/// difference.
+fn diff_lines(old: &str, new: &str) -> String {
+    let old: Vec<&str> = old.split('\n').collect();
+    let new: Vec<&str> = new.split('\n').collect();
+    let prefix = old.iter().zip(&new).take_while(|(l, r)| l == r).count();
+    let max_suffix = cmp::min(old.len(), new.len()) - prefix;
+    let suffix = old
+        .iter()
+        .rev()
+        .zip(new.iter().rev())
+        .take(max_suffix)
+        .take_while(|(l, r)| l == r)
+        .count();
+    let old = &old[prefix..old.len() - suffix];
+    let new = &new[prefix..new.len() - suffix];
+
+    // `lcs[i][j]` is the length of the longest common subsequence of `old[i..]` and `new[j..]`
+    let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                cmp::max(lcs[i + 1][j], lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = format!("@@ line {} @@\n", prefix + 1);
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            diff.push_str(&format!(" {}\n", old[i]));
+            i += 1;
+            j += 1;
+        } else if j == new.len() || i < old.len() && lcs[i + 1][j] >= lcs[i][j + 1] {
+            diff.push_str(&format!("-{}\n", old[i]));
+            i += 1;
+        } else {
+            diff.push_str(&format!("+{}\n", new[j]));
+            j += 1;
+        }
+    }
+    diff
+}
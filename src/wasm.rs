@@ -0,0 +1,85 @@
+//! A small facade over documents exported through `wasm-bindgen`.
+//!
+//! Documents are built in JavaScript by combining immutable `Doc` handles and rendered to a
+//! string at a given width.
+//!
+//! ```js
+//! import { Doc } from "pretty";
+//!
+//! const doc = Doc.text("hello").append(Doc.line()).append(Doc.text("world")).group();
+//! console.log(doc.render(80)); // "hello world"
+//! ```
+
+use wasm_bindgen::prelude::*;
+
+use crate::{owned::SharedDoc, Doc};
+
+/// A handle to an immutable document. Documents share their parts, so combining them takes
+/// constant time however large they are.
+#[wasm_bindgen(js_name = Doc)]
+#[derive(Clone)]
+pub struct WasmDoc(SharedDoc);
+
+#[wasm_bindgen(js_class = Doc)]
+impl WasmDoc {
+    /// The empty document.
+    pub fn nil() -> WasmDoc {
+        WasmDoc(Doc::nil().into())
+    }
+
+    /// The given text, with each line break laid out as a hard line break.
+    pub fn text(text: &str) -> WasmDoc {
+        WasmDoc(SharedDoc::text(text))
+    }
+
+    /// A single space.
+    pub fn space() -> WasmDoc {
+        WasmDoc(Doc::space().into())
+    }
+
+    /// A line break which is never flattened.
+    pub fn hardline() -> WasmDoc {
+        WasmDoc(Doc::hardline().into())
+    }
+
+    /// A line break, or a space when the enclosing group is flattened.
+    pub fn line() -> WasmDoc {
+        WasmDoc(SharedDoc::line(Doc::space()))
+    }
+
+    /// A line break, or nothing when the enclosing group is flattened.
+    #[wasm_bindgen(js_name = lineOrNil)]
+    pub fn line_() -> WasmDoc {
+        WasmDoc(SharedDoc::line(Doc::nil()))
+    }
+
+    /// A space if the rest of the line fits, otherwise a line break.
+    pub fn softline() -> WasmDoc {
+        WasmDoc(SharedDoc::line(Doc::space()).group())
+    }
+
+    /// Nothing if the rest of the line fits, otherwise a line break.
+    #[wasm_bindgen(js_name = softlineOrNil)]
+    pub fn softline_() -> WasmDoc {
+        WasmDoc(SharedDoc::line(Doc::nil()).group())
+    }
+
+    pub fn append(&self, other: &WasmDoc) -> WasmDoc {
+        WasmDoc(self.0.append(&other.0))
+    }
+
+    /// Lays out the document on a single line if it fits.
+    pub fn group(&self) -> WasmDoc {
+        WasmDoc(self.0.group())
+    }
+
+    /// Increases the indentation of the line breaks in the document by `offset`.
+    pub fn nest(&self, offset: i32) -> WasmDoc {
+        WasmDoc(self.0.nest(offset as isize))
+    }
+
+    /// Renders the document with lines of at most `width` columns where possible.
+    pub fn render(&self, width: usize) -> String {
+        self.0.pretty(width).to_string()
+    }
+}
@@ -0,0 +1,198 @@
+//! Helpers for emitting block style YAML.
+//!
+//! Indentation is significant in YAML so the structure of mappings and sequences is always laid
+//! out with hard line breaks, which means that no choice of page width can change the meaning of
+//! the output. Scalars are quoted whenever emitting them plainly could change their meaning.
+//! As with any YAML file the rendered output should be terminated with a newline, otherwise the
+//! final line break of a trailing block scalar is lost.
+//!
+//! ```
+//! use pretty::yaml::{block_scalar, mapping, scalar, sequence};
+//! use pretty::Arena;
+//!
+//! let arena = Arena::<()>::new();
+//! let doc = mapping(&arena, vec![
+//!     ("name", scalar(&arena, "pretty")),
+//!     ("version", scalar(&arena, "0.7")),
+//!     ("tags", sequence(&arena, vec![scalar(&arena, "yes"), scalar(&arena, "printing")])),
+//!     ("script", block_scalar(&arena, "cargo build\ncargo test\n")),
+//! ]);
+//! assert_eq!(
+//!     doc.into_builder().1.pretty(80).to_string(),
+//!     r#"name: pretty
+//! version: "0.7"
+//! tags:
+//!   - "yes"
+//!   - printing
+//! script: |
+//!   cargo build
+//!   cargo test"#,
+//! );
+//! ```
+
+use std::borrow::Cow;
+
+use crate::{escape, DocAllocator, DocBuilder};
+
+const INDENT: isize = 2;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Kind {
+    /// Fits on the line after a key or sequence marker
+    Inline,
+    /// A mapping or sequence which must start on its own line after a key
+    Block,
+}
+
+/// A YAML node along with the information needed to place it inside a collection.
+pub struct Node<'a, D, A = ()>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+{
+    doc: DocBuilder<'a, D, A>,
+    kind: Kind,
+}
+
+impl<'a, D, A> Node<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+{
+    /// Returns the document of this node.
+    pub fn into_builder(self) -> DocBuilder<'a, D, A> {
+        self.doc
+    }
+}
+
+fn is_plain_safe(s: &str) -> bool {
+    const INDICATORS: &str = "-?:,[]{}#&*!|>'\"%@`";
+    const RESERVED: &[&str] = &[
+        "~", "null", "true", "false", "yes", "no", "on", "off", "y", "n",
+    ];
+
+    let first = match s.chars().next() {
+        Some(first) => first,
+        None => return false,
+    };
+    !(INDICATORS.contains(first)
+        || first.is_whitespace()
+        || s.ends_with(char::is_whitespace)
+        || s.contains(": ")
+        || s.contains(" #")
+        || s.ends_with(':')
+        || s.chars().any(char::is_control)
+        || RESERVED.iter().any(|r| r.eq_ignore_ascii_case(s))
+        || s.parse::<f64>().is_ok()
+        || s.starts_with('.')
+        || s.starts_with(|c: char| c.is_ascii_digit()))
+}
+
+/// A scalar, emitted plainly if possible and double quoted otherwise.
+pub fn scalar<'a, D, A, S>(allocator: &'a D, value: S) -> Node<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+    S: Into<Cow<'a, str>>,
+{
+    let value = value.into();
+    let doc = if is_plain_safe(&value) {
+        allocator.text(value)
+    } else {
+        allocator.text(escape::json(value)).double_quotes()
+    };
+    Node {
+        doc,
+        kind: Kind::Inline,
+    }
+}
+
+/// A literal block scalar (`|`) which preserves every newline of `value`.
+pub fn block_scalar<'a, D, A>(allocator: &'a D, value: &'a str) -> Node<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+{
+    let content = value.trim_end_matches('\n');
+    let chomping = match value.len() - content.len() {
+        0 => "-",
+        1 => "",
+        _ => "+",
+    };
+    // An explicit indentation indicator is needed if the first line starts with a space
+    let indentation = if content.starts_with(' ') { "2" } else { "" };
+
+    let lines = content.split('\n').fold(allocator.nil(), |doc, line| {
+        doc.append(allocator.hardline()).append(line)
+    });
+    let trailing = (1..value.len() - content.len())
+        .fold(allocator.nil(), |doc, _| doc.append(allocator.hardline()));
+    Node {
+        doc: allocator
+            .text("|")
+            .append(indentation)
+            .append(chomping)
+            .append(lines.append(trailing).nest(INDENT)),
+        kind: Kind::Inline,
+    }
+}
+
+/// A block mapping. An empty mapping is emitted as `{}`.
+pub fn mapping<'a, D, A, I, K>(allocator: &'a D, entries: I) -> Node<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+    I: IntoIterator<Item = (K, Node<'a, D, A>)>,
+    K: Into<Cow<'a, str>>,
+{
+    let mut doc = allocator.nil();
+    let mut empty = true;
+    for (key, value) in entries {
+        if !empty {
+            doc = doc.append(allocator.hardline());
+        }
+        empty = false;
+        let key = scalar(allocator, key).doc.append(":");
+        doc = doc.append(match value.kind {
+            Kind::Inline => key.append(" ").append(value.doc),
+            Kind::Block => key.append(allocator.hardline().append(value.doc).nest(INDENT)),
+        });
+    }
+    if empty {
+        return Node {
+            doc: allocator.text("{}"),
+            kind: Kind::Inline,
+        };
+    }
+    Node {
+        doc,
+        kind: Kind::Block,
+    }
+}
+
+/// A block sequence. An empty sequence is emitted as `[]`.
+pub fn sequence<'a, D, A, I>(allocator: &'a D, items: I) -> Node<'a, D, A>
+where
+    D: ?Sized + DocAllocator<'a, A>,
+    I: IntoIterator<Item = Node<'a, D, A>>,
+{
+    let mut doc = allocator.nil();
+    let mut empty = true;
+    for item in items {
+        if !empty {
+            doc = doc.append(allocator.hardline());
+        }
+        empty = false;
+        // Block scalars are indented relative to the sequence itself while compact mappings and
+        // sequences are indented relative to the entry
+        doc = doc.append(allocator.text("- ").append(match item.kind {
+            Kind::Inline => item.doc,
+            Kind::Block => item.doc.nest(INDENT),
+        }));
+    }
+    if empty {
+        return Node {
+            doc: allocator.text("[]"),
+            kind: Kind::Inline,
+        };
+    }
+    Node {
+        doc,
+        kind: Kind::Block,
+    }
+}